@@ -1,26 +1,36 @@
 // #![allow(warnings)]
 #![cfg(target_arch = "wasm32")]
 
+use std::collections::HashMap;
 use std::sync::mpsc;
 use std::sync::Mutex;
 
 use libp2p::PeerId;
 
-use js_sys::Date;
-
-use crate::conventions::{get_proximity, PeerAccounting};
+use crate::conventions::{
+    get_proximity, LockRecover, OverlayAddress, PeerAccounting, PeerFile, RefreshBudget,
+};
 
 pub const REFRESH_RATE: u64 = 4500000;
 pub const PO_PRICE: u64 = 10000;
+pub const LATENCY_EMA_ALPHA: f64 = 0.2;
+// a delivery counts half as much after this long, so old streaks fade either way
+pub const SCORE_HALF_LIFE_MS: f64 = 300000.0;
+pub const DEFAULT_REFRESH_WINDOW_MS: f64 = 60000.0;
 
 pub fn set_payment_threshold(a: &Mutex<PeerAccounting>, amount: u64) {
-    let mut account = a.lock().unwrap();
+    let mut account = a.lock_recover();
     account.threshold = amount;
 }
 
-pub fn reserve(a: &Mutex<PeerAccounting>, amount: u64, chan: &mpsc::Sender<(PeerId, u64)>) -> bool {
-    let mut account = a.lock().unwrap();
-    if account.balance > REFRESH_RATE && account.refreshment + 1000.0 < Date::now() {
+pub fn reserve(
+    a: &Mutex<PeerAccounting>,
+    amount: u64,
+    chan: &mpsc::Sender<(PeerId, u64)>,
+    now: f64,
+) -> bool {
+    let mut account = a.lock_recover();
+    if account.balance > REFRESH_RATE && account.refreshment + 1000.0 < now {
         // start refreshing
         let _ = chan.send((account.id.clone(), account.threshold));
     }
@@ -31,9 +41,26 @@ pub fn reserve(a: &Mutex<PeerAccounting>, amount: u64, chan: &mpsc::Sender<(Peer
     return false;
 }
 
+// a peer at its cap is passed over until one of its outstanding requests finishes
+
+pub fn begin_request(a: &Mutex<PeerAccounting>, cap: u32) -> bool {
+    let mut account = a.lock_recover();
+    if cap > 0 && account.in_flight >= cap {
+        return false;
+    }
+    account.in_flight += 1;
+    true
+}
+
+pub fn end_request(a: &Mutex<PeerAccounting>) {
+    let mut account = a.lock_recover();
+    account.in_flight = account.in_flight.saturating_sub(1);
+}
+
 pub fn apply_credit(a: &Mutex<PeerAccounting>, amount: u64) {
-    let mut account = a.lock().unwrap();
+    let mut account = a.lock_recover();
     account.balance += amount;
+    account.credited += amount;
     if account.reserve > amount {
         account.reserve -= amount;
         return;
@@ -41,8 +68,20 @@ pub fn apply_credit(a: &Mutex<PeerAccounting>, amount: u64) {
     account.reserve = 0;
 }
 
+// a chunk served to the peer earns its price, which settles what is owed to the peer first
+
+pub fn apply_debit(a: &Mutex<PeerAccounting>, amount: u64) {
+    let mut account = a.lock_recover();
+    account.balance = account.balance.saturating_sub(amount);
+    account.served += amount;
+}
+
 pub fn apply_refreshment(a: &Mutex<PeerAccounting>, amount: u64) {
-    let mut account = a.lock().unwrap();
+    let mut account = a.lock_recover();
+    account.refreshed += amount;
+    if amount > 0 {
+        account.refreshes += 1;
+    }
     if account.balance > amount {
         account.balance -= amount;
         return;
@@ -50,8 +89,55 @@ pub fn apply_refreshment(a: &Mutex<PeerAccounting>, amount: u64) {
     account.balance = 0;
 }
 
+// pseudosettle is time based, the peer accepts at most the refresh rate for every
+// second since the last refreshment and never more than we owe
+
+pub fn refresh_allowance(account: &PeerAccounting, now: f64) -> u64 {
+    let elapsed_secs = ((now - account.refreshment) / 1000.0).max(0.0) as u64;
+    REFRESH_RATE
+        .saturating_mul(elapsed_secs)
+        .min(account.balance)
+}
+
+// a peer is asked at most once a second, for what pseudosettle allows and the budget
+// leaves, zero means nothing is asked this time
+
+pub fn plan_refresh(
+    a: &Mutex<PeerAccounting>,
+    amount: u64,
+    budget: &RefreshBudget,
+    now: f64,
+) -> u64 {
+    let mut account = a.lock_recover();
+    if now <= account.refreshment + 1000.0 {
+        return 0;
+    }
+
+    let amount = amount.min(refresh_allowance(&account, now));
+    let amount = match amount {
+        0 => 0,
+        _ => budget.take(amount, now),
+    };
+    if amount > 0 {
+        account.refreshment = now;
+    }
+    amount
+}
+
+// an overdrafted peer is asked to refresh just enough for the request it refused,
+// never more than we owe nor more than its threshold
+
+pub fn overdraft_refresh(a: &Mutex<PeerAccounting>, price: u64) -> u64 {
+    let account = a.lock_recover();
+    let needed = (account.reserve + account.balance + price + 1).saturating_sub(account.threshold);
+    needed
+        .max(price)
+        .min(account.balance)
+        .min(account.threshold)
+}
+
 pub fn cancel_reserve(a: &Mutex<PeerAccounting>, amount: u64) {
-    let mut account = a.lock().unwrap();
+    let mut account = a.lock_recover();
     if account.reserve > amount {
         account.reserve -= amount;
         return;
@@ -59,9 +145,253 @@ pub fn cancel_reserve(a: &Mutex<PeerAccounting>, amount: u64) {
     account.reserve = 0;
 }
 
-pub fn price(peer_overlay: &String, chunk_address: &Vec<u8>) -> u64 {
+pub fn record_latency(a: &Mutex<PeerAccounting>, latency: f64) {
+    let mut account = a.lock_recover();
+    if account.latency == 0.0 {
+        account.latency = latency;
+        return;
+    }
+    account.latency = LATENCY_EMA_ALPHA * latency + (1.0 - LATENCY_EMA_ALPHA) * account.latency;
+}
+
+pub fn record_delivery(a: &Mutex<PeerAccounting>, success: bool, now: f64) {
+    let mut account = a.lock_recover();
+    let decayed = decayed_score(&account, now);
+    account.scored_at = now;
+    if success {
+        account.successes += 1;
+        account.score = decayed + 1.0;
+        return;
+    }
+    account.failures += 1;
+    account.score = decayed - 1.0;
+}
+
+fn decayed_score(account: &PeerAccounting, now: f64) -> f64 {
+    let elapsed = (now - account.scored_at).max(0.0);
+    account.score * 0.5_f64.powf(elapsed / SCORE_HALF_LIFE_MS)
+}
+
+pub fn peer_score(a: &Mutex<PeerAccounting>, now: f64) -> f64 {
+    decayed_score(&a.lock_recover(), now)
+}
+
+// every overlay maps to one peer id and every peer id to one overlay, a reconnect under a new id
+// replaces the stale id and drops its state, returns the id that was replaced
+
+pub fn insert_peer(
+    overlay_peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    connected: &Mutex<HashMap<PeerId, PeerFile>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    overlay: OverlayAddress,
+    peer_file: PeerFile,
+) -> Option<PeerId> {
+    let peer_id = peer_file.peer_id;
+
+    let stale = {
+        let mut peers = overlay_peers.lock_recover();
+        peers.retain(|ov, id| *ov == overlay || *id != peer_id);
+        peers.insert(overlay, peer_id).filter(|old| *old != peer_id)
+    };
+
+    if let Some(stale) = stale {
+        connected.lock_recover().remove(&stale);
+        accounting.lock_recover().remove(&stale);
+    }
+
+    connected.lock_recover().insert(peer_id, peer_file);
+    accounting.lock_recover().entry(peer_id).or_insert_with(|| {
+        Mutex::new(PeerAccounting {
+            balance: 0,
+            threshold: 0,
+            reserve: 0,
+            refreshment: 0.0,
+            id: peer_id,
+            latency: 0.0,
+            credited: 0,
+            refreshed: 0,
+            refreshes: 0,
+            served: 0,
+            successes: 0,
+            failures: 0,
+            score: 0.0,
+            scored_at: 0.0,
+            in_flight: 0,
+        })
+    });
+
+    stale
+}
+
+pub fn eviction_candidate(
+    connected: &Mutex<HashMap<PeerId, PeerFile>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    overlay: &Vec<u8>,
+    now: f64,
+) -> Option<(PeerId, u8)> {
+    // lowest decayed delivery score first, furthest from our overlay among equals
+
+    let pos: Vec<(PeerId, u8)> = connected
+        .lock_recover()
+        .iter()
+        .map(|(id, peer_file)| (*id, get_proximity(overlay, &peer_file.overlay)))
+        .collect();
+
+    let accounting_peers = accounting.lock_recover();
+
+    pos.into_iter().min_by_key(|(id, po)| {
+        let value = match accounting_peers.get(id) {
+            Some(accounting_peer) => (peer_score(accounting_peer, now) * 1000.0) as i64,
+            None => 0,
+        };
+        (value, *po)
+    })
+}
+
+pub fn accounting_snapshot(
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    now: f64,
+) -> serde_json::Value {
+    // overlays are collected and released before accounting is locked, peers are then visited in id order
+
+    let overlays: HashMap<PeerId, String> = peers
+        .lock_recover()
+        .iter()
+        .map(|(ov, id)| (*id, ov.to_string()))
+        .collect();
+
+    let accounting_peers = accounting.lock_recover();
+
+    let mut ids: Vec<&PeerId> = accounting_peers.keys().collect();
+    ids.sort();
+
+    let mut snapshot: Vec<serde_json::Value> = vec![];
+    for id in ids {
+        let score = peer_score(&accounting_peers[id], now);
+        let account = accounting_peers[id].lock_recover();
+        snapshot.push(serde_json::json!({
+            "peer": id.to_string(),
+            "overlay": overlays.get(id).cloned().unwrap_or_default(),
+            "balance": account.balance,
+            "reserve": account.reserve,
+            "threshold": account.threshold,
+            "overdraft": account.reserve + account.balance >= account.threshold,
+            "credited": account.credited,
+            "refreshed": account.refreshed,
+            "refreshes": account.refreshes,
+            "served": account.served,
+            "successes": account.successes,
+            "failures": account.failures,
+            "score": score,
+            "latency": account.latency,
+            "in_flight": account.in_flight,
+        }));
+    }
+
+    serde_json::Value::Array(snapshot)
+}
+
+pub fn price(peer_overlay: &OverlayAddress, chunk_address: &Vec<u8>) -> u64 {
     // return uint64(swarm.MaxPO-swarm.Proximity(peer.Bytes(), chunk.Bytes())+1) * pricer.PO_PRICE
 
-    let po = get_proximity(&hex::decode(peer_overlay).unwrap(), &chunk_address);
+    let po = get_proximity(&peer_overlay.to_vec(), &chunk_address);
     return ((u64::from(crate::conventions::MAX_PO) - u64::from(po) + 1) * PO_PRICE).into();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conventions::{Clock, ManualClock};
+    use wasm_bindgen_test::*;
+
+    fn two_peers() -> (
+        Mutex<HashMap<OverlayAddress, PeerId>>,
+        Mutex<HashMap<PeerId, PeerFile>>,
+        Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+        PeerId,
+        PeerId,
+    ) {
+        let (overlays, connected, accounting) = (
+            Mutex::new(HashMap::new()),
+            Mutex::new(HashMap::new()),
+            Mutex::new(HashMap::new()),
+        );
+        let (far, near) = (PeerId::random(), PeerId::random());
+        for (peer_id, byte) in [(far, 0x80_u8), (near, 0x40_u8)] {
+            insert_peer(
+                &overlays,
+                &connected,
+                &accounting,
+                OverlayAddress::from_slice(&[byte; 32]).unwrap(),
+                PeerFile {
+                    peer_id,
+                    overlay: vec![byte; 32],
+                },
+            );
+        }
+        (overlays, connected, accounting, far, near)
+    }
+
+    #[wasm_bindgen_test]
+    fn old_deliveries_fade_on_the_configured_clock() {
+        let clock = ManualClock::starting_at(1_000_000.0);
+        let (overlays, connected, accounting, far, near) = two_peers();
+        let overlay = vec![0_u8; 32];
+
+        {
+            let peers = accounting.lock_recover();
+            record_delivery(&peers[&far], true, clock.now_ms());
+            record_delivery(&peers[&far], true, clock.now_ms());
+        }
+        assert_eq!(
+            eviction_candidate(&connected, &accounting, &overlay, clock.now_ms()),
+            Some((near, 1))
+        );
+
+        clock.advance(2.0 * SCORE_HALF_LIFE_MS);
+        record_delivery(&accounting.lock_recover()[&near], true, clock.now_ms());
+        assert_eq!(
+            eviction_candidate(&connected, &accounting, &overlay, clock.now_ms()),
+            Some((far, 0))
+        );
+
+        let snapshot = accounting_snapshot(&overlays, &accounting, clock.now_ms());
+        let score_of = |peer: PeerId| {
+            snapshot
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|entry| entry["peer"] == peer.to_string())
+                .and_then(|entry| entry["score"].as_f64())
+        };
+        assert_eq!(score_of(far), Some(0.5));
+        assert_eq!(score_of(near), Some(1.0));
+    }
+
+    #[wasm_bindgen_test]
+    fn refreshes_follow_the_configured_clock() {
+        let clock = ManualClock::starting_at(1_000_000.0);
+        let (_, _, accounting, far, _) = two_peers();
+        let budget = RefreshBudget::default();
+
+        let peers = accounting.lock_recover();
+        peers[&far].lock_recover().balance = 10 * REFRESH_RATE;
+        peers[&far].lock_recover().refreshment = clock.now_ms();
+
+        assert_eq!(
+            plan_refresh(&peers[&far], u64::MAX, &budget, clock.now_ms()),
+            0
+        );
+
+        clock.advance(2000.0);
+        assert_eq!(
+            plan_refresh(&peers[&far], u64::MAX, &budget, clock.now_ms()),
+            2 * REFRESH_RATE
+        );
+        assert_eq!(
+            plan_refresh(&peers[&far], u64::MAX, &budget, clock.now_ms()),
+            0
+        );
+    }
+}
@@ -0,0 +1,338 @@
+#![cfg(target_arch = "wasm32")]
+
+use crate::{
+    // // // // // // // //
+    ecdsa,
+    // // // // // // // //
+    BehaviourConfig,
+    // // // // // // // //
+    FeedSearch,
+    // // // // // // // //
+    JsValue,
+    // // // // // // // //
+    LockRecover,
+    // // // // // // // //
+    RetrievalConfig,
+    // // // // // // // //
+    Sekirei,
+    // // // // // // // //
+    SelectionStrategy,
+    // // // // // // // //
+    StreamProtocol,
+    // // // // // // // //
+    BOOTNODE_ADDRESS,
+    // // // // // // // //
+    DEFAULT_BOOTSTRAP_ATTEMPTS,
+    // // // // // // // //
+    DEFAULT_CONNECT_TIMEOUT_MS,
+    // // // // // // // //
+    DEFAULT_HANDSHAKE_TIMEOUT_MS,
+    // // // // // // // //
+    DEFAULT_MAX_ESTABLISHED,
+    // // // // // // // //
+    DEFAULT_MAX_PENDING,
+    // // // // // // // //
+    DEFAULT_NETWORK_ID,
+    // // // // // // // //
+    DEFAULT_REFRESH_WINDOW_MS,
+    // // // // // // // //
+    DNS_RESOLVER_URL,
+    // // // // // // // //
+    IDENTIFY_AGENT_VERSION,
+    // // // // // // // //
+    IDENTIFY_PROTOCOL_VERSION,
+    // // // // // // // //
+};
+
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+// collects every client option in one place, from js a single config object is passed
+// and any key left out keeps its default
+
+#[wasm_bindgen]
+pub struct Weeb3ClientBuilder {
+    private_key: Option<ecdsa::SecretKey>,
+    network_id: u64,
+    handshake_timeout_ms: f64,
+    handshake_protocols: Vec<String>,
+    connect_timeout_ms: f64,
+    bootnode: String,
+    relays: Vec<String>,
+    dns_resolver: String,
+    protocol_version: String,
+    agent_version: String,
+    retrieval: RetrievalConfig,
+    protocols: BehaviourConfig,
+    max_established: u32,
+    max_pending: u32,
+    bootstrap_attempts: u32,
+    serving: bool,
+    permissioned_serving: bool,
+    sign_requests: bool,
+    full_node: bool,
+}
+
+impl Default for Weeb3ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Weeb3ClientBuilder {
+    pub fn new() -> Weeb3ClientBuilder {
+        Weeb3ClientBuilder {
+            private_key: None,
+            network_id: DEFAULT_NETWORK_ID,
+            handshake_timeout_ms: DEFAULT_HANDSHAKE_TIMEOUT_MS,
+            handshake_protocols: vec![],
+            connect_timeout_ms: DEFAULT_CONNECT_TIMEOUT_MS,
+            bootnode: BOOTNODE_ADDRESS.to_string(),
+            relays: vec![],
+            dns_resolver: DNS_RESOLVER_URL.to_string(),
+            protocol_version: IDENTIFY_PROTOCOL_VERSION.to_string(),
+            agent_version: IDENTIFY_AGENT_VERSION.to_string(),
+            retrieval: RetrievalConfig::default(),
+            protocols: BehaviourConfig::default(),
+            max_established: DEFAULT_MAX_ESTABLISHED,
+            max_pending: DEFAULT_MAX_PENDING,
+            bootstrap_attempts: DEFAULT_BOOTSTRAP_ATTEMPTS,
+            serving: false,
+            permissioned_serving: false,
+            sign_requests: false,
+            full_node: false,
+        }
+    }
+
+    pub fn from_config(config: JsValue) -> Result<Weeb3ClientBuilder, JsValue> {
+        let text: String = js_sys::JSON::stringify(&config)?.into();
+        let v: Value = match serde_json::from_str(&text) {
+            Ok(v) => v,
+            _ => return Err(JsValue::from("config is not a json object")),
+        };
+
+        let mut builder = Weeb3ClientBuilder::new();
+
+        if let Some(private_key) = v["private_key"].as_str() {
+            let key_bytes = match hex::decode(private_key.trim_start_matches("0x")) {
+                Ok(key_bytes) => key_bytes,
+                _ => return Err(JsValue::from("private_key is not hex")),
+            };
+            builder.private_key = match ecdsa::SecretKey::try_from_bytes(key_bytes) {
+                Ok(secret_key) => Some(secret_key),
+                _ => return Err(JsValue::from("private_key is not a valid secp256k1 key")),
+            };
+        }
+        if let Some(network_id) = v["network_id"].as_u64() {
+            builder.network_id = network_id;
+        }
+        if let Some(handshake_timeout_ms) = v["handshake_timeout_ms"].as_f64() {
+            builder.handshake_timeout_ms = handshake_timeout_ms.max(0.0);
+        }
+        if let Some(handshake_protocols) = v["handshake_protocols"].as_array() {
+            builder.handshake_protocols = vec![];
+            for protocol in handshake_protocols {
+                match protocol
+                    .as_str()
+                    .and_then(|protocol| StreamProtocol::try_from_owned(protocol.to_string()).ok())
+                {
+                    Some(protocol) => builder.handshake_protocols.push(protocol.to_string()),
+                    None => return Err(JsValue::from("invalid handshake protocol")),
+                }
+            }
+        }
+        if let Some(connect_timeout_ms) = v["connect_timeout_ms"].as_f64() {
+            builder.connect_timeout_ms = connect_timeout_ms.max(0.0);
+        }
+        if let Some(bootnode) = v["bootnode"].as_str() {
+            builder.bootnode = bootnode.to_string();
+        }
+        if let Some(relays) = v["relays"].as_array() {
+            builder.relays = relays
+                .iter()
+                .filter_map(|relay| relay.as_str().map(|relay| relay.to_string()))
+                .collect();
+        }
+        if let Some(dns_resolver) = v["dns_resolver"].as_str() {
+            builder.dns_resolver = dns_resolver.to_string();
+        }
+        if let Some(protocol_version) = v["protocol_version"].as_str() {
+            builder.protocol_version = protocol_version.to_string();
+        }
+        if let Some(agent_version) = v["agent_version"].as_str() {
+            builder.agent_version = agent_version.to_string();
+        }
+        if let Some(max_established) = v["max_established"].as_u64() {
+            builder.max_established = max_established as u32;
+        }
+        if let Some(max_pending) = v["max_pending"].as_u64() {
+            builder.max_pending = max_pending as u32;
+        }
+        if let Some(bootstrap_attempts) = v["bootstrap_attempts"].as_u64() {
+            builder.bootstrap_attempts = bootstrap_attempts as u32;
+        }
+        if let Some(serving) = v["serving"].as_bool() {
+            builder.serving = serving;
+        }
+        if let Some(permissioned_serving) = v["permissioned_serving"].as_bool() {
+            builder.permissioned_serving = permissioned_serving;
+        }
+        if let Some(sign_requests) = v["sign_requests"].as_bool() {
+            builder.sign_requests = sign_requests;
+        }
+        if let Some(full_node) = v["full_node"].as_bool() {
+            builder.full_node = full_node;
+        }
+
+        let r = &v["retrieval"];
+        if let Some(strategy) = r["strategy"].as_u64() {
+            builder.retrieval.strategy = match SelectionStrategy::from_code(strategy as u8) {
+                Some(strategy) => strategy,
+                None => return Err(JsValue::from("unknown retrieval strategy")),
+            };
+        }
+        if let Some(latency_weight) = r["latency_weight"].as_f64() {
+            builder.retrieval.latency_weight = latency_weight.max(0.0);
+        }
+        if let Some(score_weight) = r["score_weight"].as_f64() {
+            builder.retrieval.score_weight = score_weight.max(0.0);
+        }
+        if let Some(retry_budget) = r["retry_budget"].as_u64() {
+            builder.retrieval.retry_budget = retry_budget;
+        }
+        if let Some(verify_root) = r["verify_root"].as_bool() {
+            builder.retrieval.verify_root = verify_root;
+        }
+        if let Some(proximity_floor) = r["proximity_floor"].as_u64() {
+            builder.retrieval.proximity_floor = proximity_floor as u8;
+        }
+        if let Some(clamp_after_rounds) = r["clamp_after_rounds"].as_u64() {
+            builder.retrieval.clamp_after_rounds = clamp_after_rounds as u32;
+        }
+        if let Some(direct_timeout_ms) = r["direct_timeout_ms"].as_f64() {
+            builder.retrieval.direct_timeout_ms = direct_timeout_ms.max(0.0);
+        }
+        if let Some(forward_hop_timeout_ms) = r["forward_hop_timeout_ms"].as_f64() {
+            builder.retrieval.forward_hop_timeout_ms = forward_hop_timeout_ms.max(0.0);
+        }
+        if let Some(max_forward_hops) = r["max_forward_hops"].as_u64() {
+            builder.retrieval.max_forward_hops = max_forward_hops as u8;
+        }
+        if let Some(max_bytes_per_sec) = r["max_bytes_per_sec"].as_u64() {
+            builder
+                .retrieval
+                .bandwidth
+                .set_max_bytes_per_sec(max_bytes_per_sec);
+        }
+        if let Some(require_stamp) = r["require_stamp"].as_bool() {
+            builder.retrieval.require_stamp = require_stamp;
+        }
+        if let Some(max_concurrent_streams) = r["max_concurrent_streams"].as_u64() {
+            builder.retrieval.streams.set_cap(max_concurrent_streams);
+        }
+        if let Some(max_join_fanout) = r["max_join_fanout"].as_u64() {
+            builder.retrieval.max_join_fanout = max_join_fanout as usize;
+        }
+        if let Some(max_in_flight_per_peer) = r["max_in_flight_per_peer"].as_u64() {
+            builder.retrieval.max_in_flight_per_peer = max_in_flight_per_peer as u32;
+        }
+        if let Some(min_peers) = r["min_peers"].as_u64() {
+            builder.retrieval.min_peers = min_peers as usize;
+        }
+        if let Some(min_peers_timeout_ms) = r["min_peers_timeout_ms"].as_f64() {
+            builder.retrieval.min_peers_timeout_ms = min_peers_timeout_ms.max(0.0);
+        }
+        if let Some(leaf_cache_capacity) = r["leaf_cache_capacity"].as_u64() {
+            builder.retrieval.leaf_cache_capacity = leaf_cache_capacity as usize;
+        }
+        if let Some(manifest_node_capacity) = r["manifest_node_capacity"].as_u64() {
+            builder.retrieval.manifest_node_capacity = manifest_node_capacity as usize;
+        }
+        if let Some(max_refresh_per_window) = r["max_refresh_per_window"].as_u64() {
+            let window_ms = r["refresh_window_ms"]
+                .as_f64()
+                .unwrap_or(DEFAULT_REFRESH_WINDOW_MS);
+            builder
+                .retrieval
+                .refresh
+                .set_cap(max_refresh_per_window, window_ms);
+        }
+        if let Some(feed_initial_width) = r["feed_initial_width"].as_u64() {
+            builder.retrieval.feed_search = FeedSearch::new(
+                feed_initial_width,
+                builder.retrieval.feed_search.probe_density,
+            );
+        }
+        if let Some(feed_probe_density) = r["feed_probe_density"].as_u64() {
+            builder.retrieval.feed_search.probe_density = feed_probe_density;
+        }
+        if let Some(gateway) = r["gateway"].as_str() {
+            builder.retrieval.gateway = Some(gateway.to_string());
+        }
+
+        let p = &v["protocols"];
+        if let Some(enabled) = p["autonat"].as_bool() {
+            builder.protocols.autonat = enabled;
+        }
+        if let Some(enabled) = p["dcutr"].as_bool() {
+            builder.protocols.dcutr = enabled;
+        }
+        if let Some(enabled) = p["identify"].as_bool() {
+            builder.protocols.identify = enabled;
+        }
+        if let Some(enabled) = p["ping"].as_bool() {
+            builder.protocols.ping = enabled;
+        }
+        if let Some(enabled) = p["relay"].as_bool() {
+            builder.protocols.relay = enabled;
+        }
+        if let Some(enabled) = p["gossip"].as_bool() {
+            builder.protocols.gossip = enabled;
+        }
+        if let Some(enabled) = p["pricing"].as_bool() {
+            builder.protocols.pricing = enabled;
+        }
+        if let Some(enabled) = p["pushsync"].as_bool() {
+            builder.protocols.pushsync = enabled;
+        }
+
+        Ok(builder)
+    }
+
+    pub fn build(self) -> Sekirei {
+        crate::init_panic_hook();
+
+        let mut client = Sekirei::from_secret_key(
+            self.private_key.unwrap_or_else(ecdsa::SecretKey::generate),
+            self.protocol_version,
+            self.agent_version,
+            self.protocols,
+        );
+
+        client.network_id = self.network_id;
+        *client.handshake_timeout_ms.lock_recover() = self.handshake_timeout_ms;
+        client.set_handshake_protocols(self.handshake_protocols);
+        client.set_connect_timeout(self.connect_timeout_ms);
+        *client.bootnode_address.lock_recover() = self.bootnode;
+        for relay in self.relays {
+            if !client.add_relay(relay.clone()) {
+                web_sys::console::log_1(&JsValue::from(format!("Invalid relay address {}", relay)));
+            }
+        }
+        *client.dns_resolver.lock_recover() = self.dns_resolver;
+        *client.retrieval_config.lock_recover() = RetrievalConfig {
+            network_id: self.network_id,
+            ..self.retrieval
+        };
+        *client.max_established.lock_recover() = self.max_established;
+        *client.max_pending.lock_recover() = self.max_pending;
+        client.set_bootstrap_attempts(self.bootstrap_attempts);
+        client.enable_serving(self.serving);
+        client.set_permissioned_serving(self.permissioned_serving);
+        client.set_request_signing(self.sign_requests);
+        client.set_full_node(self.full_node);
+
+        client
+    }
+}
@@ -0,0 +1,126 @@
+#![cfg(target_arch = "wasm32")]
+
+use crate::JsValue;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+// chunks kept in the cache storage so they outlive the worker, one named cache per use
+
+pub const LEAF_CHUNK_CACHE: &str = "weeb3-leaf-chunks";
+pub const PIN_CHUNK_CACHE: &str = "weeb3-pin-chunks";
+pub const PIN_ROOT_CACHE: &str = "weeb3-pin-roots";
+
+// clients of different networks share the origin and with it the cache storage, every
+// stored cache is named after the network so nothing one of them keeps reaches another
+
+pub fn network_cache(name: &str, network_id: u64) -> String {
+    format!("{}-{}", name, network_id)
+}
+
+fn chunk_key(address: &[u8]) -> String {
+    format!("/chunk/{}", hex::encode(address))
+}
+
+async fn chunk_cache(name: &str) -> Option<web_sys::Cache> {
+    let caches = match js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("caches")) {
+        Ok(caches) if !caches.is_undefined() => caches.unchecked_into::<web_sys::CacheStorage>(),
+        _ => return None,
+    };
+
+    match JsFuture::from(caches.open(name)).await {
+        Ok(cache) => Some(cache.unchecked_into::<web_sys::Cache>()),
+        _ => None,
+    }
+}
+
+pub async fn load_chunk(name: &str, address: &[u8]) -> Option<Vec<u8>> {
+    let cache = chunk_cache(name).await?;
+
+    let response = JsFuture::from(cache.match_with_str(&chunk_key(address)))
+        .await
+        .ok()?;
+    if response.is_undefined() {
+        return None;
+    }
+
+    let buffer = JsFuture::from(
+        response
+            .unchecked_into::<web_sys::Response>()
+            .array_buffer()
+            .ok()?,
+    )
+    .await
+    .ok()?;
+
+    Some(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+pub async fn store_chunk(name: &str, address: &[u8], data: &[u8]) -> bool {
+    let cache = match chunk_cache(name).await {
+        Some(cache) => cache,
+        None => return false,
+    };
+
+    let mut body = data.to_vec();
+    let response = match web_sys::Response::new_with_opt_u8_array(Some(&mut body)) {
+        Ok(response) => response,
+        _ => return false,
+    };
+
+    JsFuture::from(cache.put_with_str(&chunk_key(address), &response))
+        .await
+        .is_ok()
+}
+
+pub async fn delete_chunks(name: &str, addresses: &[Vec<u8>]) {
+    let cache = match chunk_cache(name).await {
+        Some(cache) => cache,
+        None => return,
+    };
+
+    for address in addresses {
+        let _ = JsFuture::from(cache.delete_with_str(&chunk_key(address))).await;
+    }
+}
+
+pub async fn stored_addresses(name: &str) -> Vec<Vec<u8>> {
+    let cache = match chunk_cache(name).await {
+        Some(cache) => cache,
+        None => return vec![],
+    };
+
+    let keys = match JsFuture::from(cache.keys()).await {
+        Ok(keys) => keys.unchecked_into::<js_sys::Array>(),
+        _ => return vec![],
+    };
+
+    keys.iter()
+        .filter_map(|request| {
+            let url = request.unchecked_into::<web_sys::Request>().url();
+            hex::decode(url.rsplit('/').next()?).ok()
+        })
+        .collect()
+}
+
+// entries are listed in the order they were last put, so the oldest ones beyond the capacity
+// are dropped, which bounds what earlier sessions left behind
+
+pub async fn prune_chunks(name: &str, capacity: usize) -> usize {
+    let cache = match chunk_cache(name).await {
+        Some(cache) => cache,
+        None => return 0,
+    };
+
+    let keys = match JsFuture::from(cache.keys()).await {
+        Ok(keys) => keys.unchecked_into::<js_sys::Array>(),
+        _ => return 0,
+    };
+
+    let excess = (keys.length() as usize).saturating_sub(capacity);
+    for request in keys.iter().take(excess) {
+        let _ = JsFuture::from(cache.delete_with_request(&request.unchecked_into())).await;
+    }
+
+    excess
+}
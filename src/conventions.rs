@@ -1,13 +1,20 @@
 #![cfg(target_arch = "wasm32")]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
 use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
 
 use alloy::primitives::keccak256;
 use alloy::primitives::{normalize_v, PrimitiveSignature as Signature};
 
 use libp2p::multiaddr::Protocol;
-use libp2p::{Multiaddr, PeerId};
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
 
 use wasm_bindgen::prelude::*;
 use web_sys::{Document, HtmlElement};
@@ -25,22 +32,1402 @@ pub struct Manifest {
     pub entries: HashMap<String, Entry>,
 }
 
+// a panic while a lock is held poisons it, the guard is taken back anyway since every
+// critical section here leaves its map or counter usable, so one failed chunk does not
+// fail every later lock
+
+pub trait LockRecover<T> {
+    fn lock_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockRecover<T> for Mutex<T> {
+    fn lock_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
 pub const MAX_PO: u8 = 31;
+
+// chunk format: [span: u64 little endian][payload up to CHUNK_SIZE bytes], intermediate
+// payloads are packed references, 2 * REFERENCE_SIZE when encrypted
+
+pub const CHUNK_SIZE: usize = 4096;
+pub const REFERENCE_SIZE: usize = 32;
 pub const SPAN_SIZE: usize = 8;
+pub const NEIGHBORHOOD_SATURATION: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct PeerFile {
+    pub peer_id: PeerId,
+    pub overlay: Vec<u8>,
+}
+
+// a remote on another network answers with its own network id, or closes the stream
+// right after our syn, both are reported apart from ordinary stream failures
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    NetworkIdMismatch { ours: u64, theirs: u64 },
+    ClosedAfterSyn,
+    DecodeFailed(String),
+    SignatureInvalid,
+    UnsupportedKeyType,
+    Timeout,
+    Io(io::Error),
+}
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT_MS: f64 = 15000.0;
+pub const HANDSHAKE_ATTEMPTS: u32 = 2;
+pub const HANDSHAKE_RETRY_BACKOFF_MS: u64 = 500;
+
+#[derive(Debug, Clone)]
+pub struct HandshakeConfig {
+    pub network_id: u64,
+    // the whole exchange from syn to ack must finish within this time
+    pub timeout_ms: f64,
+    // receives (peer id, message, hex bytes) for every raw handshake message when set
+    pub trace: Option<js_sys::Function>,
+    // our own reachable addresses, the first is signed into our ack, empty for a light node
+    pub advertised: Vec<Multiaddr>,
+    // tried in order, the next one only when the peer does not support the previous
+    pub protocols: Vec<StreamProtocol>,
+    // ages the cache of verified peers
+    pub clock: Arc<dyn Clock>,
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(e: io::Error) -> Self {
+        HandshakeError::Io(e)
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::NetworkIdMismatch { ours, theirs } => write!(
+                f,
+                "network id mismatch, we are on {} and the peer is on {}",
+                ours, theirs
+            ),
+            HandshakeError::ClosedAfterSyn => write!(
+                f,
+                "peer closed the handshake after syn, possibly a network id mismatch"
+            ),
+            HandshakeError::DecodeFailed(e) => write!(f, "malformed handshake message {}", e),
+            HandshakeError::SignatureInvalid => {
+                write!(f, "peer address signature does not match its overlay")
+            }
+            HandshakeError::UnsupportedKeyType => {
+                write!(f, "our key cannot sign handshake addresses")
+            }
+            HandshakeError::Timeout => write!(f, "handshake timed out"),
+            HandshakeError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl HandshakeError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HandshakeError::NetworkIdMismatch { .. } => "NetworkIdMismatch",
+            HandshakeError::ClosedAfterSyn => "ClosedAfterSyn",
+            HandshakeError::DecodeFailed(_) => "DecodeFailed",
+            HandshakeError::SignatureInvalid => "SignatureInvalid",
+            HandshakeError::UnsupportedKeyType => "UnsupportedKeyType",
+            HandshakeError::Timeout => "Timeout",
+            HandshakeError::Io(_) => "Io",
+        }
+    }
+
+    // only transport trouble is worth another attempt, anything the peer or our key decided
+    // will come out the same way again
+
+    pub fn retryable(&self) -> bool {
+        match self {
+            HandshakeError::Timeout => true,
+            HandshakeError::Io(e) => e.kind() != io::ErrorKind::Unsupported,
+            _ => false,
+        }
+    }
+}
+
+// failing to reach a peer at all is kept apart from a handshake that went wrong
+
+#[derive(Debug)]
+pub enum ConnectError {
+    Dial(String),
+    Timeout { address: Multiaddr, timeout_ms: f64 },
+}
+
+pub const DEFAULT_CONNECT_TIMEOUT_MS: f64 = 10000.0;
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Dial(e) => write!(f, "dial failed {}", e),
+            ConnectError::Timeout {
+                address,
+                timeout_ms,
+            } => write!(f, "no connection to {} within {}ms", address, timeout_ms),
+        }
+    }
+}
+
+impl ConnectError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConnectError::Dial(_) => "Dial",
+            ConnectError::Timeout { .. } => "Timeout",
+        }
+    }
+}
+
+// the transport is websockets, so there is no peer connection to query, round trips come from ping
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats {
+    pub established_at: f64,
+    pub rtt_ms: Option<f64>,
+    pub last_ping: f64,
+    // remote address of the first connection, kept for peer snapshots
+    pub address: Option<Multiaddr>,
+}
+
+// handshake data a peer was verified with, reused on redials until it expires or changes
+
+pub const VERIFIED_PEER_TTL: f64 = 600000.0;
+
+#[derive(Debug, Clone)]
+pub struct VerifiedPeer {
+    pub overlay: Vec<u8>,
+    pub underlay: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub observed_underlay: Vec<u8>,
+    pub ack: crate::messages::Ack,
+    pub verified_at: f64,
+}
+
+// overlays are validated as 32 bytes where they enter the peers map, so a
+// malformed key is rejected there instead of silently never matching
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OverlayAddress([u8; 32]);
+
+impl OverlayAddress {
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        let overlay: [u8; 32] = bytes.try_into().ok()?;
+        Some(Self(overlay))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+impl FromStr for OverlayAddress {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut overlay = [0_u8; 32];
+        hex::decode_to_slice(s.strip_prefix("0x").unwrap_or(s), &mut overlay)?;
+        Ok(Self(overlay))
+    }
+}
+
+impl fmt::Display for OverlayAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+#[derive(Debug)]
+pub struct PeerAccounting {
+    pub balance: u64,
+    pub threshold: u64,
+    pub reserve: u64,
+    pub refreshment: f64,
+    pub id: PeerId,
+    pub latency: f64,
+    pub credited: u64,
+    pub refreshed: u64,
+    pub refreshes: u64,
+    pub served: u64,
+    pub successes: u64,
+    pub failures: u64,
+    pub score: f64,
+    pub scored_at: f64,
+    pub in_flight: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkKind {
+    Cac,
+    Soc { owner: Vec<u8>, id: Vec<u8> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetrieveOutcome {
+    Delivered,
+    // peer answered but does not have the chunk
+    NotFound,
+    // stream died before a complete answer arrived
+    StreamReset,
+    // peer does not speak the retrieval protocol, says nothing about the chunk
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetrievalError {
+    InvalidAddress = 1,
+    NotFound = 2,
+    BudgetExhausted = 3,
+    IntegrityMismatch = 4,
+    UnknownPeer = 5,
+    PeerUnavailable = 6,
+    UnsupportedProtocol = 7,
+}
+
+impl RetrievalError {
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(RetrievalError::InvalidAddress),
+            2 => Some(RetrievalError::NotFound),
+            3 => Some(RetrievalError::BudgetExhausted),
+            4 => Some(RetrievalError::IntegrityMismatch),
+            5 => Some(RetrievalError::UnknownPeer),
+            6 => Some(RetrievalError::PeerUnavailable),
+            7 => Some(RetrievalError::UnsupportedProtocol),
+            _ => None,
+        }
+    }
+}
+
+// a plain or encrypted reference, the zero address is a sentinel that is never retrieved
+
+pub fn valid_chunk_address(address: &[u8]) -> bool {
+    (address.len() == REFERENCE_SIZE || address.len() == 2 * REFERENCE_SIZE)
+        && address[0..REFERENCE_SIZE].iter().any(|b| *b != 0)
+}
+
+// swarm cids are cidv1 in lowercase base32 multibase: [version: 1][codec varint]
+// [keccak-256: 0x1b][digest length: 32][address], the codec tells manifests from feeds
+
+pub const SWARM_MANIFEST_CODEC: u64 = 0xfa;
+pub const SWARM_FEED_CODEC: u64 = 0xfb;
+const KECCAK_256_MULTIHASH: u8 = 0x1b;
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_lowercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn read_varint(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+    for (i, byte) in data.iter().enumerate().take(9) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn write_varint(mut value: u64) -> Vec<u8> {
+    let mut out = vec![];
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn encode_swarm_cid(address: &[u8], codec: u64) -> Option<String> {
+    if address.len() != REFERENCE_SIZE {
+        return None;
+    }
+
+    let mut cid = vec![1];
+    cid.extend(write_varint(codec));
+    cid.push(KECCAK_256_MULTIHASH);
+    cid.push(REFERENCE_SIZE as u8);
+    cid.extend_from_slice(address);
+
+    Some(format!("b{}", base32_encode(&cid)))
+}
+
+pub fn decode_swarm_cid(cid: &str) -> Option<(Vec<u8>, u64)> {
+    let bytes = base32_decode(cid.strip_prefix('b').or(cid.strip_prefix('B'))?)?;
+
+    let (version, read) = read_varint(&bytes)?;
+    let (codec, read_codec) = read_varint(&bytes[read..])?;
+    let rest = &bytes[read + read_codec..];
+    if version != 1
+        || (codec != SWARM_MANIFEST_CODEC && codec != SWARM_FEED_CODEC)
+        || rest.len() != 2 + REFERENCE_SIZE
+        || rest[0] != KECCAK_256_MULTIHASH
+        || rest[1] as usize != REFERENCE_SIZE
+    {
+        return None;
+    }
+
+    Some((rest[2..].to_vec(), codec))
+}
+
+// public entry points take a hex reference, with or without 0x, or a swarm cid
+
+pub fn decode_address(address: &str) -> Option<Vec<u8>> {
+    match hex::decode(address.trim_start_matches("0x")) {
+        Ok(address) => Some(address),
+        Err(_) => decode_swarm_cid(address).map(|(address, _codec)| address),
+    }
+}
+
+#[wasm_bindgen]
+pub fn address_to_cid(address: String) -> String {
+    match hex::decode(address.trim_start_matches("0x")) {
+        Ok(address) => encode_swarm_cid(&address, SWARM_MANIFEST_CODEC).unwrap_or_default(),
+        Err(_) => "".to_string(),
+    }
+}
+
+#[wasm_bindgen]
+pub fn cid_to_address(cid: String) -> String {
+    match decode_swarm_cid(&cid) {
+        Some((address, _codec)) => hex::encode(address),
+        None => "".to_string(),
+    }
+}
+
+// chunk streams open at once across every request, feed probes included, zero leaves it
+// unbounded
+
+pub const DEFAULT_MAX_CONCURRENT_STREAMS: u64 = 64;
+
+#[derive(Debug, Default)]
+struct StreamSlots {
+    open: u64,
+    cap: u64,
+    waiters: VecDeque<Waker>,
+}
+
+impl StreamSlots {
+    fn wake_all(&mut self) {
+        for waiter in std::mem::take(&mut self.waiters) {
+            waiter.wake();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StreamBudget {
+    slots: Arc<Mutex<StreamSlots>>,
+}
+
+impl StreamBudget {
+    pub fn with_cap(cap: u64) -> Self {
+        let budget = Self::default();
+        budget.set_cap(cap);
+        budget
+    }
+
+    pub fn set_cap(&self, cap: u64) {
+        let mut slots = self.slots.lock_recover();
+        slots.cap = cap;
+        slots.wake_all();
+    }
+
+    pub fn cap(&self) -> u64 {
+        self.slots.lock_recover().cap
+    }
+
+    // resolves once a stream may be opened, the slot is given back when the permit drops
+    // however the request ends
+
+    pub fn acquire(&self) -> StreamAcquire {
+        StreamAcquire {
+            budget: self.clone(),
+        }
+    }
+}
+
+pub struct StreamAcquire {
+    budget: StreamBudget,
+}
+
+impl Future for StreamAcquire {
+    type Output = StreamPermit;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<StreamPermit> {
+        let mut slots = self.budget.slots.lock_recover();
+        if slots.cap == 0 || slots.open < slots.cap {
+            slots.open += 1;
+            return Poll::Ready(StreamPermit {
+                budget: self.budget.clone(),
+            });
+        }
+        slots.waiters.push_back(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+// every waiter is woken on release, a waker left behind by a dropped acquire can then not
+// swallow the wake of one still waiting
+
+pub struct StreamPermit {
+    budget: StreamBudget,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        let mut slots = self.budget.slots.lock_recover();
+        slots.open = slots.open.saturating_sub(1);
+        slots.wake_all();
+    }
+}
+
+// chunks by address with a least recently used order, once full every insert evicts the
+// entry read or written longest ago and hands its address back
+
+#[derive(Debug, Clone, Default)]
+pub struct ChunkLru {
+    entries: HashMap<Vec<u8>, (u64, Vec<u8>)>,
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+impl ChunkLru {
+    pub fn get(&mut self, address: &Vec<u8>) -> Option<Vec<u8>> {
+        self.tick += 1;
+        let (used, data) = self.entries.get_mut(address)?;
+        self.order.remove(used);
+        *used = self.tick;
+        self.order.insert(self.tick, address.clone());
+        Some(data.clone())
+    }
+
+    pub fn insert(&mut self, address: &Vec<u8>, data: &Vec<u8>, capacity: usize) -> Vec<Vec<u8>> {
+        self.tick += 1;
+        if let Some((used, _)) = self.entries.remove(address) {
+            self.order.remove(&used);
+        }
+
+        let mut evicted = vec![];
+        while self.entries.len() >= capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                    evicted.push(oldest);
+                }
+                None => break,
+            }
+        }
+
+        if capacity > 0 {
+            self.entries
+                .insert(address.clone(), (self.tick, data.clone()));
+            self.order.insert(self.tick, address.clone());
+        }
+        evicted
+    }
+}
+
+// manifest nodes are content addressed, so one fetched while resolving a path serves every
+// later path under the same manifest, the least recently used node makes room at the capacity
+
+#[derive(Debug, Clone, Default)]
+pub struct ManifestNodeCache {
+    nodes: Arc<Mutex<ChunkLru>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl ManifestNodeCache {
+    pub fn get(&self, address: &Vec<u8>) -> Option<Vec<u8>> {
+        let node = self.nodes.lock_recover().get(address);
+        match node.is_some() {
+            true => self.hits.fetch_add(1, Ordering::Relaxed),
+            false => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        node
+    }
+
+    pub fn insert(&self, address: &Vec<u8>, node: &Vec<u8>, capacity: usize) {
+        self.nodes.lock_recover().insert(address, node, capacity);
+    }
+
+    pub fn clear(&self) {
+        *self.nodes.lock_recover() = ChunkLru::default();
+    }
+
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+// chunks of pinned roots, kept apart from every capped cache so nothing evicts them,
+// a chunk stays while at least one pin still holds it, chunks and the addresses every
+// root holds are also written to the cache storage of the network so pins outlive the worker
+
+#[derive(Debug, Clone, Default)]
+pub struct PinStore {
+    chunks: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    pins: Arc<Mutex<HashMap<Vec<u8>, HashSet<Vec<u8>>>>>,
+}
+
+impl PinStore {
+    pub fn chunk(&self, address: &Vec<u8>) -> Option<Vec<u8>> {
+        self.chunks.lock_recover().get(address).cloned()
+    }
+
+    pub fn store(&self, network_id: u64, root: &Vec<u8>, address: &Vec<u8>, data: &Vec<u8>) {
+        self.pins
+            .lock_recover()
+            .entry(root.clone())
+            .or_default()
+            .insert(address.clone());
+        let known = self
+            .chunks
+            .lock_recover()
+            .insert(address.clone(), data.clone())
+            .is_some();
+
+        if !known {
+            let (address, data) = (address.clone(), data.clone());
+            wasm_bindgen_futures::spawn_local(async move {
+                let name = crate::network_cache(crate::PIN_CHUNK_CACHE, network_id);
+                crate::store_chunk(&name, &address, &data).await;
+            });
+        }
+    }
+
+    pub fn held(&self, root: &Vec<u8>) -> Option<HashSet<Vec<u8>>> {
+        self.pins.lock_recover().get(root).cloned()
+    }
+
+    // records which chunks the root holds once its whole tree is stored
+
+    pub async fn persist(&self, network_id: u64, root: &Vec<u8>) -> bool {
+        let held = match self.held(root) {
+            Some(held) => held.into_iter().collect(),
+            None => return false,
+        };
+
+        let name = crate::network_cache(crate::PIN_ROOT_CACHE, network_id);
+        crate::store_chunk(&name, root, &encode_frame_fields(&held)).await
+    }
+
+    // pins written by earlier sessions, a chunk missing from the storage is left out
+
+    pub async fn restore(&self, network_id: u64) -> usize {
+        let roots = crate::network_cache(crate::PIN_ROOT_CACHE, network_id);
+        let chunks = crate::network_cache(crate::PIN_CHUNK_CACHE, network_id);
+
+        let mut restored = 0;
+        for root in crate::stored_addresses(&roots).await {
+            let held = match crate::load_chunk(&roots, &root).await {
+                Some(held) => decode_frame_fields(&held),
+                None => continue,
+            };
+
+            let mut kept = HashSet::new();
+            for address in held {
+                if let Some(data) = crate::load_chunk(&chunks, &address).await {
+                    self.chunks.lock_recover().insert(address.clone(), data);
+                    kept.insert(address);
+                }
+            }
+            self.pins.lock_recover().insert(root, kept);
+            restored += 1;
+        }
+        restored
+    }
+
+    pub fn unpin(&self, network_id: u64, root: &Vec<u8>) -> bool {
+        let released = {
+            let mut pins = self.pins.lock_recover();
+            let held = match pins.remove(root) {
+                Some(held) => held,
+                None => return false,
+            };
+            self.release(&pins, held)
+        };
+
+        let root = root.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let roots = crate::network_cache(crate::PIN_ROOT_CACHE, network_id);
+            crate::delete_chunks(&roots, &[root]).await;
+            let chunks = crate::network_cache(crate::PIN_CHUNK_CACHE, network_id);
+            crate::delete_chunks(&chunks, &released).await;
+        });
+        true
+    }
+
+    // a failed pin gives back only what it added, the root keeps what an earlier pin held
+
+    pub fn roll_back(
+        &self,
+        network_id: u64,
+        root: &Vec<u8>,
+        held_before: Option<HashSet<Vec<u8>>>,
+    ) {
+        let held_before = match held_before {
+            Some(held_before) => held_before,
+            None => {
+                self.unpin(network_id, root);
+                return;
+            }
+        };
+
+        let released = {
+            let mut pins = self.pins.lock_recover();
+            let added = match pins.insert(root.clone(), held_before.clone()) {
+                Some(held) => held.difference(&held_before).cloned().collect(),
+                None => HashSet::new(),
+            };
+            self.release(&pins, added)
+        };
+
+        wasm_bindgen_futures::spawn_local(async move {
+            let chunks = crate::network_cache(crate::PIN_CHUNK_CACHE, network_id);
+            crate::delete_chunks(&chunks, &released).await;
+        });
+    }
+
+    fn release(
+        &self,
+        pins: &HashMap<Vec<u8>, HashSet<Vec<u8>>>,
+        candidates: HashSet<Vec<u8>>,
+    ) -> Vec<Vec<u8>> {
+        let mut chunks = self.chunks.lock_recover();
+        candidates
+            .into_iter()
+            .filter(|address| !pins.values().any(|held| held.contains(address)))
+            .filter(|address| chunks.remove(address).is_some())
+            .collect()
+    }
+
+    pub fn roots(&self) -> Vec<Vec<u8>> {
+        self.pins.lock_recover().keys().cloned().collect()
+    }
+}
+
+// byte counters shared by every clone, a nonzero rate delays reads and writes past
+// the per second allowance instead of dropping them
+
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthMeter {
+    bytes_in: Arc<AtomicU64>,
+    bytes_out: Arc<AtomicU64>,
+    max_bytes_per_sec: Arc<AtomicU64>,
+    window: Arc<Mutex<(f64, u64)>>,
+    // the clock of the config the meter belongs to, none reads the browser clock
+    clock: Arc<Mutex<Option<Arc<dyn Clock>>>>,
+}
+
+impl BandwidthMeter {
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    pub fn max_bytes_per_sec(&self) -> u64 {
+        self.max_bytes_per_sec.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_bytes_per_sec(&self, rate: u64) {
+        self.max_bytes_per_sec.store(rate, Ordering::Relaxed);
+    }
+
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        *self.clock.lock_recover() = Some(clock);
+    }
+
+    fn now_ms(&self) -> f64 {
+        match self.clock.lock_recover().as_ref() {
+            Some(clock) => clock.now_ms(),
+            None => BrowserClock.now_ms(),
+        }
+    }
+
+    pub async fn meter_in(&self, n: usize) {
+        self.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+        self.throttle(n as u64).await;
+    }
+
+    pub async fn meter_out(&self, n: usize) {
+        self.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+        self.throttle(n as u64).await;
+    }
+
+    async fn throttle(&self, n: u64) {
+        let rate = self.max_bytes_per_sec();
+        if rate == 0 {
+            return;
+        }
+
+        let now = self.now_ms();
+        let wait = {
+            let mut window = self.window.lock_recover();
+            if now - window.0 >= 1000.0 {
+                *window = (now, 0);
+            }
+            window.1 += n;
+            (window.1 as f64 / rate as f64) * 1000.0 - (now - window.0)
+        };
+
+        if wait > 0.0 {
+            async_std::task::sleep(std::time::Duration::from_millis(wait as u64)).await;
+        }
+    }
+}
+
+// refresh amounts asked of peers, shared by every clone, a nonzero cap bounds what is asked
+// within one window so a miscounted balance cannot be refreshed without end
+
+#[derive(Debug, Clone, Default)]
+pub struct RefreshBudget {
+    asked: Arc<AtomicU64>,
+    granted: Arc<AtomicU64>,
+    refreshes: Arc<AtomicU64>,
+    throttled: Arc<AtomicU64>,
+    cap: Arc<Mutex<(u64, f64)>>,
+    window: Arc<Mutex<(f64, u64)>>,
+}
+
+impl RefreshBudget {
+    // refreshes are asked at most once a second per peer, shorter windows would not bound them
+
+    pub fn set_cap(&self, max_amount: u64, window_ms: f64) {
+        *self.cap.lock_recover() = (max_amount, window_ms.max(1000.0));
+    }
+
+    pub fn cap(&self) -> (u64, f64) {
+        *self.cap.lock_recover()
+    }
+
+    // returns the part of the amount the window still allows and counts it as asked
+
+    pub fn take(&self, amount: u64, now: f64) -> u64 {
+        let (max_amount, window_ms) = self.cap();
+        let allowed = match max_amount {
+            0 => amount,
+            _ => {
+                let mut window = self.window.lock_recover();
+                if now - window.0 >= window_ms {
+                    *window = (now, 0);
+                }
+                let allowed = amount.min(max_amount.saturating_sub(window.1));
+                window.1 += allowed;
+                allowed
+            }
+        };
+
+        if allowed < amount {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+        }
+        self.asked.fetch_add(allowed, Ordering::Relaxed);
+        allowed
+    }
+
+    pub fn record_grant(&self, granted: u64) {
+        if granted > 0 {
+            self.granted.fetch_add(granted, Ordering::Relaxed);
+            self.refreshes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // asked, granted, completed refreshes and requests cut by the cap
+
+    pub fn stats(&self) -> (u64, u64, u64, u64) {
+        (
+            self.asked.load(Ordering::Relaxed),
+            self.granted.load(Ordering::Relaxed),
+            self.refreshes.load(Ordering::Relaxed),
+            self.throttled.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SelectionStrategy {
+    #[default]
+    Closest = 0,
+    Fastest = 1,
+    Cheapest = 2,
+}
+
+impl SelectionStrategy {
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(SelectionStrategy::Closest),
+            1 => Some(SelectionStrategy::Fastest),
+            2 => Some(SelectionStrategy::Cheapest),
+            _ => None,
+        }
+    }
+}
+
+// timing decisions read the time through a clock so they can be driven without the browser
+// clock, sleeps still take real time
+
+pub trait Clock: fmt::Debug {
+    fn now_ms(&self) -> f64;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BrowserClock;
+
+impl Clock for BrowserClock {
+    fn now_ms(&self) -> f64 {
+        js_sys::Date::now()
+    }
+}
+
+// only moves when told to, for stepping through round timing and refresh intervals
+
+#[cfg(any(test, feature = "manual-clock"))]
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<Mutex<f64>>,
+}
+
+#[cfg(any(test, feature = "manual-clock"))]
+impl ManualClock {
+    pub fn starting_at(now_ms: f64) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now_ms)),
+        }
+    }
+
+    pub fn advance(&self, ms: f64) {
+        *self.now.lock_recover() += ms.max(0.0);
+    }
+}
+
+#[cfg(any(test, feature = "manual-clock"))]
+impl Clock for ManualClock {
+    fn now_ms(&self) -> f64 {
+        *self.now.lock_recover()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetrievalConfig {
+    // ordering applied to candidate peers, proximity breaks ties for every strategy
+    pub strategy: SelectionStrategy,
+    // proximity orders traded per second of measured peer latency
+    pub latency_weight: f64,
+    // proximity orders traded per point of decayed delivery score
+    pub score_weight: f64,
+    // failed chunk attempts allowed across one top level request
+    pub retry_budget: u64,
+    // rehash reconstructed data and compare against the requested root
+    pub verify_root: bool,
+    // peers below this proximity order to the chunk are not asked, zero accepts any peer
+    pub proximity_floor: u8,
+    // rounds without a peer at or above the floor before the best available peer is accepted
+    pub clamp_after_rounds: u32,
+    // time allowed for a peer already inside the chunk neighborhood to answer
+    pub direct_timeout_ms: f64,
+    // extra time allowed for every hop the request is expected to be forwarded
+    pub forward_hop_timeout_ms: f64,
+    // upper bound on the hops accounted for when the timeout is computed
+    pub max_forward_hops: u8,
+    // traffic meter shared by every clone of the config
+    pub bandwidth: BandwidthMeter,
+    // validated leaves kept in memory and the cache storage so retried downloads resume
+    pub leaf_cache_capacity: usize,
+    // deliveries without a stamp of a tracked batch signed for the chunk are treated as invalid
+    pub require_stamp: bool,
+    // known batches, their stamps are held to the batch owner and depth
+    pub batches: crate::postage::BatchRegistry,
+    // pinned chunks, served before any peer is asked
+    pub pins: PinStore,
+    // known peers wanted before the first request is served, zero starts right away
+    pub min_peers: usize,
+    // how long to wait for min_peers before serving with whatever peers are known
+    pub min_peers_timeout_ms: f64,
+    // chunk requests one peer may have outstanding at once, zero leaves it unbounded
+    pub max_in_flight_per_peer: u32,
+    // children of one intermediate chunk fetched at once, zero fetches them all together
+    pub max_join_fanout: usize,
+    // time source for round timing, refreshes and feed polling
+    pub clock: Arc<dyn Clock>,
+    // called with (address hex, chunk) after content addressing checks, a falsy answer rejects
+    // the delivery and the chunk is asked from another peer
+    pub validator: Option<js_sys::Function>,
+    // chunk streams shared by every request, feed search dispatches no faster than it allows
+    pub streams: StreamBudget,
+    // manifest nodes kept for the session, shared by every clone of the config
+    pub manifest_nodes: ManifestNodeCache,
+    pub manifest_node_capacity: usize,
+    // bee gateway asked over http for chunks no peer delivers, none keeps retrieval p2p only
+    pub gateway: Option<String>,
+    // refresh totals and the cap on what is asked per window, shared by every clone
+    pub refresh: RefreshBudget,
+    // width and density of sequential feed searches started by the client
+    pub feed_search: FeedSearch,
+    // identity key retrieval requests are signed with, none sends them unsigned
+    pub request_key: Option<libp2p::identity::ecdsa::SecretKey>,
+    // network of the client, what retrieval stores outside memory is kept apart per network
+    pub network_id: u64,
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        Self {
+            strategy: SelectionStrategy::Closest,
+            latency_weight: 0.0,
+            score_weight: 0.0,
+            retry_budget: 4096,
+            verify_root: false,
+            proximity_floor: 0,
+            clamp_after_rounds: 2,
+            direct_timeout_ms: 4000.0,
+            forward_hop_timeout_ms: 3000.0,
+            max_forward_hops: 4,
+            bandwidth: BandwidthMeter::default(),
+            leaf_cache_capacity: crate::retrieval::LEAF_CACHE_CAPACITY,
+            require_stamp: false,
+            batches: crate::postage::BatchRegistry::default(),
+            pins: PinStore::default(),
+            min_peers: 0,
+            min_peers_timeout_ms: 10000.0,
+            max_in_flight_per_peer: 4,
+            max_join_fanout: crate::retrieval::DEFAULT_MAX_JOIN_FANOUT,
+            clock: Arc::new(BrowserClock),
+            validator: None,
+            streams: StreamBudget::with_cap(DEFAULT_MAX_CONCURRENT_STREAMS),
+            manifest_nodes: ManifestNodeCache::default(),
+            manifest_node_capacity: crate::manifest::MANIFEST_NODE_CACHE_CAPACITY,
+            gateway: None,
+            refresh: RefreshBudget::default(),
+            feed_search: FeedSearch::from_redundancy(DEFAULT_FEED_REDUNDANCY),
+            request_key: None,
+            network_id: crate::DEFAULT_NETWORK_ID,
+        }
+    }
+}
+
+// bee peers forward a retrieval request hop by hop toward the chunk neighborhood
+// and stream the delivery back along the same path, the request itself carries no
+// hop count so a light node asking a distant peer simply waits longer for the answer
+
+pub const STORAGE_DEPTH_ESTIMATE: u8 = 10;
+
+impl RetrievalConfig {
+    // higher is better, unmeasured peers report zero latency and are tried early
+
+    pub fn selection_score(&self, po: u8, latency_ms: f64, price: u64, score: f64) -> f64 {
+        let base = match self.strategy {
+            SelectionStrategy::Closest => f64::from(po) - self.latency_weight * latency_ms / 1000.0,
+            SelectionStrategy::Fastest => -latency_ms,
+            SelectionStrategy::Cheapest => -(price as f64),
+        };
+        base + self.score_weight * score
+    }
+
+    pub fn request_timeout(&self, po: u8) -> f64 {
+        let hops = STORAGE_DEPTH_ESTIMATE
+            .saturating_sub(po)
+            .min(self.max_forward_hops);
+        self.direct_timeout_ms + self.forward_hop_timeout_ms * f64::from(hops)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetrievalScope {
+    id: u64,
+    retry_budget: Arc<AtomicU64>,
+    integrity_mismatches: Arc<Mutex<HashSet<Vec<u8>>>>,
+    cancelled: Arc<AtomicBool>,
+    bytes_retrieved: Arc<AtomicU64>,
+    resolved_feeds: Arc<Mutex<Vec<Vec<u8>>>>,
+    soc_depth: u64,
+    unstamped: Arc<AtomicU64>,
+    pin_root: Option<Vec<u8>>,
+    pinned: Arc<AtomicU64>,
+    fetched: Arc<Mutex<HashMap<Vec<u8>, (Vec<u8>, bool, u32)>>>,
+    in_flight: Arc<Mutex<HashMap<Vec<u8>, u32>>>,
+    shared_fetches: Arc<AtomicU64>,
+    manifest_nodes: Option<(ManifestNodeCache, usize)>,
+    streams: Option<StreamBudget>,
+    probe: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl RetrievalScope {
+    pub fn new(id: u64, retry_budget: u64) -> Self {
+        Self {
+            id,
+            retry_budget: Arc::new(AtomicU64::new(retry_budget)),
+            integrity_mismatches: Arc::new(Mutex::new(HashSet::new())),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            bytes_retrieved: Arc::new(AtomicU64::new(0)),
+            resolved_feeds: Arc::new(Mutex::new(vec![])),
+            soc_depth: 0,
+            unstamped: Arc::new(AtomicU64::new(0)),
+            pin_root: None,
+            pinned: Arc::new(AtomicU64::new(0)),
+            fetched: Arc::new(Mutex::new(HashMap::new())),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            shared_fetches: Arc::new(AtomicU64::new(0)),
+            manifest_nodes: None,
+            streams: None,
+            probe: false,
+            clock: Arc::new(BrowserClock),
+        }
+    }
+
+    // feed probes expect most indexes to be missing, so a peer answering not found under a
+    // probing scope answers the probe and does not cost the shared retry budget
+
+    pub fn probing(&self) -> Self {
+        let mut probing = self.clone();
+        probing.probe = true;
+        probing
+    }
+
+    pub fn is_probe(&self) -> bool {
+        self.probe
+    }
+
+    // feed search, manifest traversal and data reconstruction of one request share the raw
+    // chunks they fetch, a chunk another branch is still fetching is waited for instead,
+    // a branch that fails to claim counts itself as waiting the first time only
+
+    pub fn claim_chunk(&self, chunk_address: &Vec<u8>, waiting: bool) -> bool {
+        let mut in_flight = self.in_flight.lock_recover();
+        match in_flight.get_mut(chunk_address) {
+            Some(waiters) => {
+                if !waiting {
+                    *waiters += 1;
+                }
+                false
+            }
+            None => {
+                in_flight.insert(chunk_address.clone(), 0);
+                true
+            }
+        }
+    }
+
+    pub fn stop_waiting(&self, chunk_address: &Vec<u8>) {
+        if let Some(waiters) = self.in_flight.lock_recover().get_mut(chunk_address) {
+            *waiters = waiters.saturating_sub(1);
+        }
+    }
+
+    // only intermediate chunks, a small share of any tree, are kept for the whole request,
+    // a leaf is kept until every branch waiting for it took it, so a file is not held twice
+
+    pub fn settle_chunk(&self, chunk_address: &Vec<u8>, chunk: &Vec<u8>, stamped: bool) {
+        let waiters = self
+            .in_flight
+            .lock_recover()
+            .remove(chunk_address)
+            .unwrap_or(0);
+        if chunk.is_empty() {
+            return;
+        }
+
+        let intermediate = match chunk.get(..SPAN_SIZE) {
+            Some(span) => u64::from_le_bytes(span.try_into().unwrap_or([0; 8])) > CHUNK_SIZE as u64,
+            None => false,
+        };
+        if intermediate {
+            self.fetched
+                .lock_recover()
+                .insert(chunk_address.clone(), (chunk.clone(), stamped, u32::MAX));
+        } else if waiters > 0 {
+            self.fetched
+                .lock_recover()
+                .insert(chunk_address.clone(), (chunk.clone(), stamped, waiters));
+        }
+    }
+
+    pub fn fetched_chunk(&self, chunk_address: &Vec<u8>) -> Option<(Vec<u8>, bool)> {
+        let mut fetched = self.fetched.lock_recover();
+        let (chunk, stamped, waiters) = fetched.get_mut(chunk_address)?;
+        let shared = (chunk.clone(), *stamped);
+        if *waiters != u32::MAX {
+            *waiters -= 1;
+            if *waiters == 0 {
+                fetched.remove(chunk_address);
+            }
+        }
+        self.shared_fetches.fetch_add(1, Ordering::Relaxed);
+        Some(shared)
+    }
+
+    pub fn shared_fetches(&self) -> u64 {
+        self.shared_fetches.load(Ordering::Relaxed)
+    }
+
+    pub fn with_manifest_nodes(mut self, cache: ManifestNodeCache, capacity: usize) -> Self {
+        self.manifest_nodes = Some((cache, capacity));
+        self
+    }
+
+    pub fn manifest_nodes(&self) -> Option<&(ManifestNodeCache, usize)> {
+        self.manifest_nodes.as_ref()
+    }
+
+    pub fn with_streams(mut self, streams: StreamBudget) -> Self {
+        self.streams = Some(streams);
+        self
+    }
+
+    pub fn streams(&self) -> Option<&StreamBudget> {
+        self.streams.as_ref()
+    }
+
+    // feed searches started under the scope read the time from the client clock
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn now_ms(&self) -> f64 {
+        self.clock.now_ms()
+    }
+
+    // every chunk retrieved under a pinning scope is stored for its root
+
+    pub fn with_pin_root(mut self, root: Vec<u8>) -> Self {
+        self.pin_root = Some(root);
+        self
+    }
+
+    pub fn pin_root(&self) -> Option<&Vec<u8>> {
+        self.pin_root.as_ref()
+    }
+
+    pub fn add_pinned(&self) {
+        self.pinned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn pinned_chunks(&self) -> u64 {
+        self.pinned.load(Ordering::Relaxed)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // cancelling drains the retry budget so no further chunk is requested
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.retry_budget.store(0, Ordering::Relaxed);
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes(&self, amount: u64) {
+        self.bytes_retrieved.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn bytes_retrieved(&self) -> u64 {
+        self.bytes_retrieved.load(Ordering::Relaxed)
+    }
+
+    // feed update addresses resolved while serving this request, in resolution order
+
+    pub fn record_feed(&self, feed_address: &Vec<u8>) {
+        let mut resolved_feeds = self.resolved_feeds.lock_recover();
+        if !resolved_feeds.contains(feed_address) {
+            resolved_feeds.push(feed_address.clone());
+        }
+    }
+
+    pub fn resolved_feeds(&self) -> Vec<Vec<u8>> {
+        self.resolved_feeds.lock_recover().clone()
+    }
+
+    // soc references are followed with a deeper scope, so the bound applies to one chain of
+    // follows and not to the sibling feeds a request resolves side by side
+
+    pub fn can_follow_soc(&self) -> bool {
+        self.soc_depth < SOC_FOLLOW_MAX_DEPTH
+    }
+
+    pub fn following_soc(&self) -> Self {
+        let mut following = self.clone();
+        following.soc_depth += 1;
+        following
+    }
+
+    // chunks delivered over the network without a valid stamp, cached leaves are not counted
+
+    pub fn flag_unstamped(&self) {
+        self.unstamped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn all_stamped(&self) -> bool {
+        self.unstamped.load(Ordering::Relaxed) == 0
+    }
+
+    pub fn flag_integrity_mismatch(&self, address: &Vec<u8>) {
+        self.integrity_mismatches
+            .lock_recover()
+            .insert(address.clone());
+    }
+
+    pub fn integrity_mismatched(&self, address: &Vec<u8>) -> bool {
+        self.integrity_mismatches.lock_recover().contains(address)
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.retry_budget.load(Ordering::Relaxed) == 0
+    }
+
+    pub fn consume_retry(&self) -> bool {
+        self.retry_budget
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok()
+    }
+}
 
 #[derive(Debug, Clone)]
-pub struct PeerFile {
-    pub peer_id: PeerId,
-    pub overlay: Vec<u8>,
+pub struct ActiveRetrieval {
+    pub address: Vec<u8>,
+    pub started_at: f64,
+    pub scope: RetrievalScope,
 }
 
-#[derive(Debug)]
-pub struct PeerAccounting {
-    pub balance: u64,
-    pub threshold: u64,
-    pub reserve: u64,
-    pub refreshment: f64,
-    pub id: PeerId,
+// bzz://<address>/<path>, /<address>/<path> or a full url with the address as first path segment
+
+pub fn parse_bzz_url(request_url: &str) -> Option<(String, String)> {
+    let rest = match request_url.strip_prefix("bzz://") {
+        Some(rest) => rest.to_string(),
+        None => match web_sys::Url::new(request_url) {
+            Ok(url) => url.pathname(),
+            _ => request_url.to_string(),
+        },
+    };
+
+    let rest = rest.trim_start_matches('/');
+    let (address, path) = match rest.split_once('/') {
+        Some((address, path)) => (address, path),
+        None => (rest, ""),
+    };
+
+    if address.len() == 0 {
+        return None;
+    }
+
+    let path = match js_sys::decode_uri_component(path) {
+        Ok(decoded) => String::from(decoded),
+        _ => path.to_string(),
+    };
+
+    Some((address.to_string(), path))
+}
+
+// immutable content is tagged by its root, content reached through feeds by the
+// update chunks it was resolved from, so a new feed update changes the tag
+
+pub fn content_etag(root: &[u8], resolved_feeds: &[Vec<u8>]) -> String {
+    if resolved_feeds.is_empty() {
+        return format!("\"{}\"", hex::encode(root));
+    }
+
+    let feeds: Vec<String> = resolved_feeds.iter().map(hex::encode).collect();
+    format!("\"{}\"", feeds.join("-"))
+}
+
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == etag)
+}
+
+pub const FEED_WATCH_MAX_BACKOFF: u32 = 4;
+
+// one polling task per feed, every watcher of the same owner and topic is a subscriber of it
+// and remembers the last index it was handed
+
+pub struct FeedWatch {
+    pub owner: String,
+    pub topic: String,
+    pub interval_ms: f64,
+    pub subscribers: HashMap<u64, (js_sys::Function, Option<u64>)>,
+    pub last_index: Option<u64>,
+    pub next_poll: f64,
+    pub idle_rounds: u32,
+}
+
+// /p2p-circuit addresses reach a peer through a circuit relay v2 reservation on the relay
+
+pub fn relayed_address(relay: &Multiaddr, peer: PeerId) -> Multiaddr {
+    relay
+        .clone()
+        .with(Protocol::P2pCircuit)
+        .with(Protocol::P2p(peer.into()))
+}
+
+// loopback and unspecified addresses only make sense from inside this host
+
+pub fn advertisable(address: &Multiaddr) -> bool {
+    address.iter().all(|p| match p {
+        Protocol::Ip4(ip) => !ip.is_loopback() && !ip.is_unspecified(),
+        Protocol::Ip6(ip) => !ip.is_loopback() && !ip.is_unspecified(),
+        _ => true,
+    })
 }
 
 pub fn try_from_multiaddr(address: &Multiaddr) -> Option<PeerId> {
@@ -86,6 +1473,27 @@ fn js_error(msg: &str) -> JsError {
     io::Error::new(io::ErrorKind::Other, msg).into()
 }
 
+// closest first by the full 32 byte xor distance, byte arrays compare like big endian numbers
+
+pub fn xor_distance(target: &[u8], overlay: &OverlayAddress) -> [u8; 32] {
+    let mut distance = [0xff_u8; 32];
+    for (i, byte) in overlay.as_bytes().iter().enumerate() {
+        if let Some(t) = target.get(i) {
+            distance[i] = t ^ byte;
+        }
+    }
+    distance
+}
+
+pub fn sort_by_distance(
+    target: &[u8],
+    peers: &[(OverlayAddress, PeerId)],
+) -> Vec<(OverlayAddress, PeerId)> {
+    let mut sorted = peers.to_vec();
+    sorted.sort_by_cached_key(|(overlay, _)| xor_distance(target, overlay));
+    sorted
+}
+
 pub fn get_proximity(one: &Vec<u8>, other: &Vec<u8>) -> u8 {
     let mut b: usize = (MAX_PO / 4 + 1).into();
 
@@ -114,6 +1522,33 @@ pub fn get_proximity(one: &Vec<u8>, other: &Vec<u8>) -> u8 {
     return MAX_PO;
 }
 
+pub fn is_within_neighborhood(chunk_address: &Vec<u8>, our_overlay: &Vec<u8>, depth: u8) -> bool {
+    get_proximity(chunk_address, our_overlay) >= depth
+}
+
+pub fn saturated_depth(
+    our_overlay: &Vec<u8>,
+    peer_overlays: &Vec<Vec<u8>>,
+    saturation: usize,
+) -> u8 {
+    // deepest proximity order still shared with at least saturation many peers
+
+    if saturation == 0 {
+        return MAX_PO;
+    }
+
+    let mut pos: Vec<u8> = peer_overlays
+        .iter()
+        .map(|peer_overlay| get_proximity(our_overlay, peer_overlay))
+        .collect();
+    pos.sort_unstable_by(|a, b| b.cmp(a));
+
+    match pos.get(saturation - 1) {
+        Some(po) => *po,
+        None => 0,
+    }
+}
+
 pub fn content_address(chunk_content: Vec<u8>) -> Vec<u8> {
     let (something, something2) = chunk_content.split_at(SPAN_SIZE);
 
@@ -143,6 +1578,86 @@ pub fn valid_cac(chunk_content: &Vec<u8>, address: &Vec<u8>) -> bool {
     //
 }
 
+// the span is only stripped when it matches the content that follows it, anything else is
+// handed back untouched instead of losing its first eight bytes
+
+pub fn strip_span(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < SPAN_SIZE {
+        return None;
+    }
+
+    let span = u64::from_le_bytes(data[0..SPAN_SIZE].try_into().ok()?);
+    match span == (data.len() - SPAN_SIZE) as u64 {
+        true => Some(data[SPAN_SIZE..].to_vec()),
+        false => Some(data.to_vec()),
+    }
+}
+
+pub fn file_root_address(data: &Vec<u8>) -> Vec<u8> {
+    // rebuilds the unencrypted chunk tree of span prefixed data bottom up
+
+    if data.len() < SPAN_SIZE {
+        return vec![];
+    }
+
+    let content = &data[SPAN_SIZE..];
+
+    let mut level: Vec<(Vec<u8>, u64)> = vec![];
+    for leaf in content.chunks(CHUNK_SIZE) {
+        let span = (leaf.len() as u64).to_le_bytes().to_vec();
+        level.push((
+            content_address([span, leaf.to_vec()].concat()),
+            leaf.len() as u64,
+        ));
+    }
+
+    if level.is_empty() {
+        return content_address(data.clone());
+    }
+
+    let branching = CHUNK_SIZE / SECTION_SIZE;
+    while level.len() > 1 {
+        let mut next: Vec<(Vec<u8>, u64)> = vec![];
+        for group in level.chunks(branching) {
+            // a lone trailing reference is carried up unwrapped
+            if group.len() == 1 {
+                next.push(group[0].clone());
+                continue;
+            }
+            let span: u64 = group.iter().map(|(_, sub_span)| sub_span).sum();
+            let refs: Vec<u8> = group
+                .iter()
+                .flat_map(|(address, _)| address.clone())
+                .collect();
+            next.push((
+                content_address([span.to_le_bytes().to_vec(), refs].concat()),
+                span,
+            ));
+        }
+        level = next;
+    }
+
+    level[0].0.clone()
+}
+
+// addresses for js uploaders, computed locally exactly as an upload would produce them
+
+#[wasm_bindgen(js_name = chunk_address)]
+pub fn chunk_address_hex(data: Vec<u8>) -> String {
+    if data.len() > CHUNK_SIZE {
+        return "".to_string();
+    }
+
+    let span = (data.len() as u64).to_le_bytes().to_vec();
+    hex::encode(content_address([span, data].concat()))
+}
+
+#[wasm_bindgen(js_name = file_root_address)]
+pub fn file_root_address_hex(data: Vec<u8>) -> String {
+    let span = (data.len() as u64).to_le_bytes().to_vec();
+    hex::encode(file_root_address(&[span, data].concat()))
+}
+
 const SECTION_SIZE: usize = 32;
 const SECTION2_SIZE: usize = 2 * SECTION_SIZE;
 const DIFF: usize = 0;
@@ -150,7 +1665,7 @@ const DIFF: usize = 0;
 pub fn hasher_0(content_in: &Vec<u8>) -> Vec<u8> {
     let mut content = content_in.clone();
 
-    let padding = 4096 - (content.len() - DIFF);
+    let padding = CHUNK_SIZE - (content.len() - DIFF);
     let zerobyte: u8 = 0;
 
     for _ in 0..padding {
@@ -194,11 +1709,16 @@ pub fn hasher_1(content_in: &Vec<u8>, length: usize) -> Vec<u8> {
     return content_holder_2[0].clone();
 }
 
-pub fn valid_soc(chunk_content: &Vec<u8>, address: &Vec<u8>) -> bool {
+// a soc wraps exactly one content addressed chunk, anything larger is refused before recovery
+
+pub const SOC_MAX_PAYLOAD: usize = SPAN_SIZE + CHUNK_SIZE;
+pub const SOC_FOLLOW_MAX_DEPTH: u64 = 16;
+
+pub fn soc_owner(chunk_content: &Vec<u8>) -> Option<Vec<u8>> {
     //
 
-    if chunk_content.len() < 97 + 40 {
-        return false;
+    if chunk_content.len() < 97 + 40 || chunk_content.len() > 97 + SOC_MAX_PAYLOAD {
+        return None;
     }
     let soc_address = chunk_content[0..32].to_vec();
     let soc_signature = chunk_content[32..97].to_vec();
@@ -210,7 +1730,7 @@ pub fn valid_soc(chunk_content: &Vec<u8>, address: &Vec<u8>) -> bool {
     let parity: bool = match normalize_v(soc_signature[64] as u64) {
         Some(par) => par,
         _ => {
-            return false;
+            return None;
         }
     };
     let sig = Signature::from_bytes_and_parity(&soc_signature[0..64], parity);
@@ -218,143 +1738,713 @@ pub fn valid_soc(chunk_content: &Vec<u8>, address: &Vec<u8>) -> bool {
     let owner = match sig.recover_address_from_msg(to_sign) {
         Ok(ow) => ow,
         _ => {
+            return None;
+        }
+    };
+
+    Some(owner.as_slice().to_vec())
+}
+
+pub fn valid_soc(chunk_content: &Vec<u8>, address: &Vec<u8>) -> bool {
+    //
+
+    let owner = match soc_owner(chunk_content) {
+        Some(ow) => ow,
+        None => {
             return false;
         }
     };
-    web_sys::console::log_1(&JsValue::from(format!("soc owner: {}", hex::encode(owner))));
+    web_sys::console::log_1(&JsValue::from(format!(
+        "soc owner: {}",
+        hex::encode(&owner)
+    )));
 
-    let address_constructed = keccak256([soc_address, owner.as_slice().to_vec()].concat()).to_vec();
+    let soc_address = chunk_content[0..32].to_vec();
+    let address_constructed = keccak256([soc_address, owner].concat()).to_vec();
 
-    if *address == address_constructed {
-        return true;
+    if *address != address_constructed {
+        return false;
     };
 
-    return false;
+    // a payload pointing back at the soc itself would be followed forever
+
+    if soc_references_itself(chunk_content, address) {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "soc {} references itself",
+            hex::encode(address)
+        )));
+        return false;
+    }
+
+    return true;
     //
 }
 
+pub fn soc_references_itself(chunk_content: &Vec<u8>, address: &Vec<u8>) -> bool {
+    if chunk_content.len() < 97 + SPAN_SIZE {
+        return false;
+    }
+
+    let content = &chunk_content[97 + SPAN_SIZE..];
+    let reference = match content.len() {
+        32 | 64 => content,
+        40 | 72 => &content[8..],
+        _ => return false,
+    };
+
+    reference[0..REFERENCE_SIZE] == address[0..address.len().min(REFERENCE_SIZE)]
+}
+
+// feed owners are 20 byte ethereum addresses in hex, optionally 0x prefixed
+
+pub fn normalize_feed_owner(owner: &str) -> Option<String> {
+    let stripped = owner.strip_prefix("0x").unwrap_or(owner);
+    match hex::decode(stripped) {
+        Ok(bytes) if bytes.len() == 20 => Some(hex::encode(bytes)),
+        _ => None,
+    }
+}
+
+pub fn feed_owner_from_pubkey(pubkey: &[u8]) -> String {
+    // accepts compressed (33), uncompressed (65) or raw (64) secp256k1 public keys
+
+    let raw = match pubkey.len() {
+        64 => pubkey.to_vec(),
+        65 if pubkey[0] == 0x04 => pubkey[1..].to_vec(),
+        33 => match libp2p::identity::secp256k1::PublicKey::try_from_bytes(pubkey) {
+            Ok(key) => key.to_bytes_uncompressed()[1..].to_vec(),
+            _ => return "".to_string(),
+        },
+        _ => return "".to_string(),
+    };
+
+    hex::encode(&keccak256(raw)[12..])
+}
+
+pub fn feed_topic(topic_str: &str) -> [u8; 32] {
+    keccak256(topic_str.as_bytes()).0
+}
+
 pub fn get_feed_address(owner: &String, topic: &String, index: u64) -> Vec<u8> {
-    let index_bytes = index.to_le_bytes().to_vec();
+    feed_identifier_address(owner, topic, index.to_le_bytes().to_vec())
+}
+
+// a sequential feed search first looks initial_width indexes ahead, probes grow apart by powers
+// of two until the gap left to search is probe_density or less, then every index is probed
+
+pub const DEFAULT_FEED_REDUNDANCY: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedSearch {
+    pub initial_width: u64,
+    pub probe_density: u64,
+}
+
+impl FeedSearch {
+    pub fn new(initial_width: u64, probe_density: u64) -> Self {
+        Self {
+            initial_width: initial_width.max(1),
+            probe_density,
+        }
+    }
+
+    // one knob for both, 2^redundancy ahead and dense below a gap of redundancy
+
+    pub fn from_redundancy(redundancy: u8) -> Self {
+        Self::new(2_u64.pow(redundancy.min(63).into()), redundancy.into())
+    }
+}
+
+// epoch feeds address updates by the time range they cover, the root epoch spans the whole u64 range
+
+pub const EPOCH_MAX_LEVEL: u8 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedType {
+    Sequential = 0,
+    Epoch = 1,
+}
+
+pub fn epoch_start(at: u64, level: u8) -> u64 {
+    if level >= 64 {
+        return 0;
+    }
+    at & !((1_u64 << level) - 1)
+}
+
+pub fn get_epoch_feed_address(owner: &String, topic: &String, start: u64, level: u8) -> Vec<u8> {
+    let epoch_bytes = [start.to_be_bytes().to_vec(), vec![level]].concat();
+    feed_identifier_address(owner, topic, keccak256(epoch_bytes).to_vec())
+}
+
+fn feed_identifier_address(owner: &String, topic: &String, index_bytes: Vec<u8>) -> Vec<u8> {
     let owner_bytes = hex::decode(owner).unwrap();
-    let topic_bytes = hex::decode(topic).unwrap();
+
+    // a 32 byte hex topic is taken as already hashed, anything else is hashed like bee does
+
+    let topic_bytes = match hex::decode(topic) {
+        Ok(bytes) if bytes.len() == 32 => bytes,
+        _ => feed_topic(topic).to_vec(),
+    };
     let id_bytes = keccak256([topic_bytes, index_bytes].concat()).to_vec();
 
     keccak256([id_bytes, owner_bytes].concat()).to_vec()
 }
 
-pub fn encode_resources(data_array: Vec<(Vec<u8>, String, String)>, indx: String) -> Vec<u8> {
-    let mut output = vec![];
+// typed feed payloads are [magic: 4][type: u8][content], a payload without the magic is raw
+// so feeds written by other tools keep reading as before
 
-    let str_i = indx.as_bytes();
-    let len_i: u64 = str_i.len() as u64;
-    let i = len_i.to_le_bytes();
+pub const FEED_PAYLOAD_MAGIC: [u8; 4] = *b"wb3f";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedPayloadType {
+    Raw = 0,
+    Json = 1,
+    Text = 2,
+    Reference = 3,
+    Manifest = 4,
+}
+
+impl FeedPayloadType {
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(FeedPayloadType::Raw),
+            1 => Some(FeedPayloadType::Json),
+            2 => Some(FeedPayloadType::Text),
+            3 => Some(FeedPayloadType::Reference),
+            4 => Some(FeedPayloadType::Manifest),
+            _ => None,
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            FeedPayloadType::Raw => "application/octet-stream",
+            FeedPayloadType::Json => "application/json",
+            FeedPayloadType::Text => "text/plain",
+            FeedPayloadType::Reference => "application/x-swarm-reference",
+            FeedPayloadType::Manifest => "application/x-swarm-manifest",
+        }
+    }
+}
+
+pub fn encode_feed_payload(kind: FeedPayloadType, content: &[u8]) -> Vec<u8> {
+    if kind == FeedPayloadType::Raw {
+        return content.to_vec();
+    }
+    [&FEED_PAYLOAD_MAGIC[..], &[kind as u8], content].concat()
+}
 
-    output.append(&mut [i.as_slice(), str_i].concat());
+pub fn decode_feed_payload(payload: &[u8]) -> (FeedPayloadType, Vec<u8>) {
+    if payload.len() > FEED_PAYLOAD_MAGIC.len() && payload.starts_with(&FEED_PAYLOAD_MAGIC) {
+        if let Some(kind) = FeedPayloadType::from_code(payload[FEED_PAYLOAD_MAGIC.len()]) {
+            return (kind, payload[FEED_PAYLOAD_MAGIC.len() + 1..].to_vec());
+        }
+    }
+    (FeedPayloadType::Raw, payload.to_vec())
+}
+
+#[wasm_bindgen]
+pub fn frame_feed_payload(kind: u8, content: Vec<u8>) -> Option<Vec<u8>> {
+    FeedPayloadType::from_code(kind).map(|kind| encode_feed_payload(kind, &content))
+}
+
+#[wasm_bindgen]
+pub fn read_feed_payload(payload: Vec<u8>) -> JsValue {
+    feed_payload_object(&payload)
+}
+
+pub fn feed_payload_object(payload: &[u8]) -> JsValue {
+    let (kind, content) = decode_feed_payload(payload);
+
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(
+        &object,
+        &JsValue::from_str("type"),
+        &JsValue::from(kind as u8),
+    );
+    let _ = js_sys::Reflect::set(
+        &object,
+        &JsValue::from_str("mime"),
+        &JsValue::from(kind.mime()),
+    );
+    let _ = js_sys::Reflect::set(
+        &object,
+        &JsValue::from_str("content"),
+        &js_sys::Uint8Array::from(&content[..]),
+    );
+
+    JsValue::from(object)
+}
+
+// resource frame layout (all lengths u32 little endian):
+//
+// [version: u8][flags: u8][entry count: u32][index length: u32][index bytes]
+// then per entry: [mime length: u32][mime][path length: u32][path][encoding length: u32]
+// [encoding][data length: u32][data]
+//
+// flags bit 0 is set when the root was a manifest, otherwise the single entry is the raw file,
+// the encoding is the content encoding still applied to the data, empty when there is none
+
+pub const RESOURCE_FRAME_VERSION: u8 = 3;
+pub const RESOURCE_FRAME_MANIFEST: u8 = 1;
+
+pub fn encode_resources(
+    data_array: Vec<(Vec<u8>, String, String, String)>,
+    indx: String,
+    manifest: bool,
+) -> Vec<u8> {
+    let mut output = vec![RESOURCE_FRAME_VERSION];
+    output.push(match manifest {
+        true => RESOURCE_FRAME_MANIFEST,
+        false => 0,
+    });
+
+    output.extend_from_slice(&(data_array.len() as u32).to_le_bytes());
+
+    let str_i = indx.as_bytes();
+    output.extend_from_slice(&(str_i.len() as u32).to_le_bytes());
+    output.extend_from_slice(str_i);
 
-    for (data, str0, str1) in data_array {
+    for (data, str0, str1, str2) in data_array {
         let str_b = str0.as_bytes();
-        let len_b: u64 = str_b.len() as u64;
-        let a = len_b.to_le_bytes();
+        output.extend_from_slice(&(str_b.len() as u32).to_le_bytes());
+        output.extend_from_slice(str_b);
 
         let str_c = str1.as_bytes();
-        let len_c: u64 = str_c.len() as u64;
-        let ac = len_c.to_le_bytes();
-
-        let len_data: u64 = data.len() as u64;
-        let la = len_data.to_le_bytes();
-
-        output.append(
-            &mut [
-                a.as_slice(),
-                str_b,
-                ac.as_slice(),
-                str_c,
-                la.as_slice(),
-                &data,
-            ]
-            .concat(),
-        );
+        output.extend_from_slice(&(str_c.len() as u32).to_le_bytes());
+        output.extend_from_slice(str_c);
+
+        let str_d = str2.as_bytes();
+        output.extend_from_slice(&(str_d.len() as u32).to_le_bytes());
+        output.extend_from_slice(str_d);
+
+        output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        output.extend_from_slice(&data);
     }
 
     output
 }
 
-pub fn decode_resources(encoded_data: Vec<u8>) -> (Vec<(Vec<u8>, String, String)>, String) {
-    let mut output: Vec<(Vec<u8>, String, String)> = vec![];
-    let mut ind = "".to_string();
+fn read_frame_field<'a>(encoded_data: &'a [u8], start: &mut usize) -> Option<&'a [u8]> {
+    let length_end = start.checked_add(4)?;
+    let length_bytes: [u8; 4] = encoded_data.get(*start..length_end)?.try_into().ok()?;
+    let field_end = length_end.checked_add(u32::from_le_bytes(length_bytes) as usize)?;
+    let field = encoded_data.get(length_end..field_end)?;
+    *start = field_end;
+    Some(field)
+}
+
+// batched payloads are plain sequences of u32 length prefixed fields
+
+pub fn encode_frame_fields(fields: &Vec<Vec<u8>>) -> Vec<u8> {
+    let mut encoded: Vec<u8> = vec![];
+    for field in fields {
+        encoded.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(field);
+    }
+    encoded
+}
+
+pub fn decode_frame_fields(encoded_data: &[u8]) -> Vec<Vec<u8>> {
+    let mut fields: Vec<Vec<u8>> = vec![];
+    let mut start = 0;
+    while start < encoded_data.len() {
+        match read_frame_field(encoded_data, &mut start) {
+            Some(field) => fields.push(field.to_vec()),
+            None => return vec![],
+        }
+    }
+    fields
+}
+
+pub fn decode_resources(encoded_data: &[u8]) -> (Vec<(Vec<u8>, String, String, String)>, String) {
+    let mut output: Vec<(Vec<u8>, String, String, String)> = vec![];
+    let ind = "".to_string();
 
     web_sys::console::log_1(&JsValue::from(format!(
         "encoded_data_len: {:#?} ",
         encoded_data.len()
     )));
 
-    if encoded_data.len() < 8 {
+    if encoded_data.len() < 6 || encoded_data[0] != RESOURCE_FRAME_VERSION {
         return (vec![], ind);
     };
 
-    let indx_length: usize =
-        u64::from_le_bytes(encoded_data[0..8].try_into().unwrap_or([0; 8])) as usize;
+    let count = u32::from_le_bytes(encoded_data[2..6].try_into().unwrap_or([0; 4])) as usize;
+    let mut start = 6;
+
+    let ind = match read_frame_field(encoded_data, &mut start) {
+        Some(field) => String::from_utf8_lossy(field).to_string(),
+        None => return (vec![], ind),
+    };
+
+    for _ in 0..count {
+        let string0 = match read_frame_field(encoded_data, &mut start) {
+            Some(field) => String::from_utf8_lossy(field).to_string(),
+            None => return (vec![], ind),
+        };
+        let string1 = match read_frame_field(encoded_data, &mut start) {
+            Some(field) => String::from_utf8_lossy(field).to_string(),
+            None => return (vec![], ind),
+        };
+        let string2 = match read_frame_field(encoded_data, &mut start) {
+            Some(field) => String::from_utf8_lossy(field).to_string(),
+            None => return (vec![], ind),
+        };
+        let data = match read_frame_field(encoded_data, &mut start) {
+            Some(field) => field.to_vec(),
+            None => return (vec![], ind),
+        };
 
-    let mut start = 8 + indx_length;
+        output.push((data, string0, string1, string2));
+    }
 
-    if encoded_data.len() < start {
+    if start != encoded_data.len() {
         return (vec![], ind);
-    };
+    }
 
-    ind = String::from_utf8(encoded_data[8..start].to_vec()).unwrap_or("".to_string());
+    (output, ind)
+}
 
-    while start < encoded_data.len() {
-        let string0_length: usize =
-            u64::from_le_bytes(encoded_data[start..start + 8].try_into().unwrap_or([0; 8]))
-                as usize;
+pub fn resource_frame_is_manifest(encoded_data: &[u8]) -> bool {
+    encoded_data.len() >= 2
+        && encoded_data[0] == RESOURCE_FRAME_VERSION
+        && encoded_data[1] & RESOURCE_FRAME_MANIFEST == RESOURCE_FRAME_MANIFEST
+}
 
-        let string1_start = start + 8 + string0_length;
+#[wasm_bindgen]
+pub fn decode_resource_frame(encoded_data: Vec<u8>) -> JsValue {
+    let version = encoded_data.first().copied().unwrap_or(0);
+    let manifest = resource_frame_is_manifest(&encoded_data);
+    let (data, ind) = decode_resources(&encoded_data);
+
+    let entries = js_sys::Array::new();
+    for (data0, mime0, path0, encoding0) in data {
+        let entry = js_sys::Object::new();
+        let _ = js_sys::Reflect::set(
+            &entry,
+            &JsValue::from_str("data"),
+            &js_sys::Uint8Array::from(&data0[..]),
+        );
+        let _ = js_sys::Reflect::set(&entry, &JsValue::from_str("mime"), &JsValue::from(mime0));
+        let _ = js_sys::Reflect::set(&entry, &JsValue::from_str("path"), &JsValue::from(path0));
+        let _ = js_sys::Reflect::set(
+            &entry,
+            &JsValue::from_str("encoding"),
+            &JsValue::from(encoding0),
+        );
+        entries.push(&entry);
+    }
 
-        if encoded_data.len() < string1_start {
-            return (vec![], ind);
-        };
+    let frame = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(
+        &frame,
+        &JsValue::from_str("version"),
+        &JsValue::from(version),
+    );
+    let _ = js_sys::Reflect::set(
+        &frame,
+        &JsValue::from_str("manifest"),
+        &JsValue::from(manifest),
+    );
+    let _ = js_sys::Reflect::set(&frame, &JsValue::from_str("index"), &JsValue::from(ind));
+    let _ = js_sys::Reflect::set(&frame, &JsValue::from_str("entries"), &entries);
+
+    JsValue::from(frame)
+}
 
-        let string0 = String::from_utf8(encoded_data[start + 8..string1_start].to_vec())
-            .unwrap_or("".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    fn resource_frame_round_trips_unusual_bytes() {
+        // delimiter like bytes, a nul, a newline and multibyte text must all survive
+        let entries = vec![
+            (
+                vec![0, 255, 10, 13, 0],
+                "text/html; charset=\"utf-8\"\n".to_string(),
+                "dir/na\0me;with|delims/\u{1F980}.html".to_string(),
+                "gzip".to_string(),
+            ),
+            (vec![], "".to_string(), "".to_string(), "".to_string()),
+        ];
+
+        let encoded = encode_resources(entries.clone(), "index\u{7f}.html".to_string(), true);
+        assert!(resource_frame_is_manifest(&encoded));
+
+        let (decoded, index) = decode_resources(&encoded);
+        assert_eq!(decoded, entries);
+        assert_eq!(index, "index\u{7f}.html");
+    }
 
-        if encoded_data.len() < string1_start + 8 {
-            return (vec![], ind);
-        };
+    #[wasm_bindgen_test]
+    fn resource_frame_rejects_truncated_and_trailing_bytes() {
+        let encoded = encode_resources(
+            vec![(
+                vec![1, 2, 3],
+                "a".to_string(),
+                "b".to_string(),
+                "".to_string(),
+            )],
+            "".to_string(),
+            false,
+        );
 
-        let string1_length: usize = u64::from_le_bytes(
-            encoded_data[string1_start..string1_start + 8]
-                .try_into()
-                .unwrap_or([0; 8]),
-        ) as usize;
+        assert_eq!(decode_resources(&encoded[..encoded.len() - 1]).0, vec![]);
+        assert_eq!(
+            decode_resources(&[encoded.clone(), vec![0]].concat()).0,
+            vec![]
+        );
+        assert!(!resource_frame_is_manifest(&encoded));
+    }
 
-        let data_start = string1_start + 8 + string1_length;
+    #[wasm_bindgen_test]
+    fn evicts_least_recently_used() {
+        let mut lru = ChunkLru::default();
+        let (a, b, c) = (vec![1], vec![2], vec![3]);
 
-        if encoded_data.len() < string1_start {
-            return (vec![], ind);
-        };
+        assert!(lru.insert(&a, &vec![10], 2).is_empty());
+        assert!(lru.insert(&b, &vec![20], 2).is_empty());
+        assert_eq!(lru.get(&a), Some(vec![10]));
 
-        let string1 = String::from_utf8(encoded_data[string1_start + 8..data_start].to_vec())
-            .unwrap_or("".to_string());
+        assert_eq!(lru.insert(&c, &vec![30], 2), vec![b.clone()]);
+        assert_eq!(lru.get(&b), None);
+        assert_eq!(lru.get(&c), Some(vec![30]));
 
-        if encoded_data.len() < data_start + 8 {
-            return (vec![], ind);
-        };
+        // a lower capacity is reached on the next insert
+        assert_eq!(lru.insert(&b, &vec![20], 1), vec![a.clone(), c.clone()]);
+        assert_eq!(lru.insert(&a, &vec![10], 0), vec![b.clone()]);
+        assert_eq!(lru.get(&a), None);
+    }
+
+    // vectors follow bee's feed derivation, topic = keccak256(name),
+    // id = keccak256(topic | index), address = keccak256(id | owner)
+
+    const FEED_OWNER: &str = "8d3766440f0d7b949a5e32995d09619a7f86e632";
+    const FEED_TOPIC: &str = "3cf2dc17cd86a9eee3f2e9f503218cfe3f6d82ad00a60368be82585ac3f263c9";
+
+    #[wasm_bindgen_test]
+    fn feed_topic_hashes_like_bee() {
+        assert_eq!(hex::encode(feed_topic("weeb-3")), FEED_TOPIC);
+        assert_eq!(
+            hex::encode(feed_topic("")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn feed_address_matches_reference() {
+        let expected = "ec1779ed1aef91b008a41e065df0d21b35f80f97f31fd7c6c4bbb73d9f36db7e";
+        let owner = FEED_OWNER.to_string();
+
+        // a topic name and its 32 byte hash name the same feed
+        assert_eq!(
+            hex::encode(get_feed_address(&owner, &"weeb-3".to_string(), 0)),
+            expected
+        );
+        assert_eq!(
+            hex::encode(get_feed_address(&owner, &FEED_TOPIC.to_string(), 0)),
+            expected
+        );
+        assert_ne!(
+            get_feed_address(&owner, &"weeb-3".to_string(), 1),
+            get_feed_address(&owner, &"weeb-3".to_string(), 0)
+        );
+    }
 
-        let data_length: usize = u64::from_le_bytes(
-            encoded_data[data_start..data_start + 8]
-                .try_into()
-                .unwrap_or([0; 8]),
-        ) as usize;
-
-        let data: Vec<u8>;
-        start = data_start + 8 + data_length;
-        if encoded_data.len() > data_start + 8 {
-            data = encoded_data[data_start + 8..start].to_vec();
-        } else {
-            return (vec![], ind);
+    #[wasm_bindgen_test]
+    fn soc_follow_depth_counts_one_chain() {
+        let scope = RetrievalScope::new(1, 8);
+
+        // siblings followed from the same scope never add up
+        for _ in 0..2 * SOC_FOLLOW_MAX_DEPTH {
+            assert!(scope.following_soc().can_follow_soc());
+        }
+
+        let mut chain = scope;
+        for _ in 0..SOC_FOLLOW_MAX_DEPTH {
+            assert!(chain.can_follow_soc());
+            chain = chain.following_soc();
+        }
+        assert!(!chain.can_follow_soc());
+    }
+
+    #[wasm_bindgen_test]
+    fn resource_frame_decodes_to_js_entries() {
+        let encoded = encode_resources(
+            vec![
+                (
+                    b"<html></html>".to_vec(),
+                    "text/html".to_string(),
+                    "index.html".to_string(),
+                    "".to_string(),
+                ),
+                (
+                    vec![31, 139],
+                    "text/css".to_string(),
+                    "style.css".to_string(),
+                    "gzip".to_string(),
+                ),
+            ],
+            "index.html".to_string(),
+            true,
+        );
+
+        let frame = decode_resource_frame(encoded);
+        let field = |value: &JsValue, key: &str| {
+            js_sys::Reflect::get(value, &JsValue::from_str(key)).unwrap()
         };
 
-        output.push((data, string0, string1));
+        assert_eq!(
+            field(&frame, "version").as_f64(),
+            Some(RESOURCE_FRAME_VERSION as f64)
+        );
+        assert_eq!(field(&frame, "manifest").as_bool(), Some(true));
+        assert_eq!(field(&frame, "index").as_string().unwrap(), "index.html");
+
+        let entries: js_sys::Array = field(&frame, "entries").into();
+        assert_eq!(entries.length(), 2);
+
+        let style = entries.get(1);
+        assert_eq!(
+            js_sys::Uint8Array::new(&field(&style, "data")).to_vec(),
+            vec![31, 139]
+        );
+        assert_eq!(field(&style, "mime").as_string().unwrap(), "text/css");
+        assert_eq!(field(&style, "path").as_string().unwrap(), "style.css");
+        assert_eq!(field(&style, "encoding").as_string().unwrap(), "gzip");
+    }
+
+    #[wasm_bindgen_test]
+    fn failed_pin_keeps_an_earlier_pin() {
+        let pins = PinStore::default();
+        let (root, other) = (vec![1; 32], vec![2; 32]);
+        let (first, shared, added) = (vec![3; 32], vec![4; 32], vec![5; 32]);
+
+        pins.store(0, &root, &first, &vec![3]);
+        pins.store(0, &root, &shared, &vec![4]);
+        pins.store(0, &other, &shared, &vec![4]);
+        let held_before = pins.held(&root);
+
+        pins.store(0, &root, &added, &vec![5]);
+        pins.roll_back(0, &root, held_before);
+
+        assert_eq!(pins.held(&root).map(|held| held.len()), Some(2));
+        assert_eq!(pins.chunk(&first), Some(vec![3]));
+        assert_eq!(pins.chunk(&added), None);
+
+        assert!(pins.unpin(0, &root));
+        assert_eq!(pins.chunk(&first), None);
+        assert_eq!(pins.chunk(&shared), Some(vec![4]));
+
+        pins.store(0, &root, &added, &vec![5]);
+        pins.roll_back(0, &root, None);
+        assert_eq!(pins.held(&root), None);
+        assert_eq!(pins.chunk(&added), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn settled_leaves_are_kept_only_for_waiting_branches() {
+        let scope = RetrievalScope::new(1, 8);
+        let (leaf, intermediate) = (vec![1; 32], vec![2; 32]);
+
+        let mut leaf_data = 3_u64.to_le_bytes().to_vec();
+        leaf_data.extend_from_slice(b"abc");
+        let mut intermediate_data = 8192_u64.to_le_bytes().to_vec();
+        intermediate_data.extend_from_slice(&[7; 64]);
+
+        assert!(scope.claim_chunk(&leaf, false));
+        assert!(!scope.claim_chunk(&leaf, false));
+        assert!(!scope.claim_chunk(&leaf, true));
+        scope.settle_chunk(&leaf, &leaf_data, true);
+        assert_eq!(scope.fetched_chunk(&leaf), Some((leaf_data.clone(), true)));
+        assert_eq!(scope.fetched_chunk(&leaf), None);
+
+        assert!(scope.claim_chunk(&leaf, false));
+        scope.settle_chunk(&leaf, &leaf_data, true);
+        assert_eq!(scope.fetched_chunk(&leaf), None);
+
+        assert!(scope.claim_chunk(&intermediate, false));
+        scope.settle_chunk(&intermediate, &intermediate_data, false);
+        for _ in 0..3 {
+            assert_eq!(
+                scope.fetched_chunk(&intermediate),
+                Some((intermediate_data.clone(), false))
+            );
+        }
+        assert_eq!(scope.shared_fetches(), 4);
+    }
+
+    #[wasm_bindgen_test]
+    fn four_byte_entries_keep_their_content() {
+        let mut leaf = 4_u64.to_le_bytes().to_vec();
+        leaf.extend_from_slice(b"weeb");
+        assert_eq!(strip_span(&leaf), Some(b"weeb".to_vec()));
+
+        let mut eight = 8_u64.to_le_bytes().to_vec();
+        eight.extend_from_slice(b"8 bytes!");
+        assert_eq!(strip_span(&eight), Some(b"8 bytes!".to_vec()));
+
+        assert_eq!(strip_span(b"weeb"), None);
+        assert_eq!(strip_span(b"unspanned"), Some(b"unspanned".to_vec()));
+    }
+
+    #[wasm_bindgen_test]
+    fn peers_sort_by_full_xor_distance() {
+        let overlay = |hex_str: &str| OverlayAddress::from_str(hex_str).unwrap();
+        let target =
+            hex::decode("8000000000000000000000000000000000000000000000000000000000000000")
+                .unwrap();
+
+        let far = overlay("0000000000000000000000000000000000000000000000000000000000000000");
+        let opposite = overlay("ff00000000000000000000000000000000000000000000000000000000000000");
+        let near = overlay("8100000000000000000000000000000000000000000000000000000000000000");
+        let nearer = overlay("8000000000000000000000000000000000000000000000000000000000000002");
+        let nearest = overlay("8000000000000000000000000000000000000000000000000000000000000001");
+
+        let peers: Vec<(OverlayAddress, PeerId)> = [far, opposite, nearer, near, nearest]
+            .into_iter()
+            .map(|overlay| (overlay, PeerId::random()))
+            .collect();
+        let sorted: Vec<OverlayAddress> = sort_by_distance(&target, &peers)
+            .into_iter()
+            .map(|(overlay, _)| overlay)
+            .collect();
+
+        assert_eq!(sorted, vec![nearest, nearer, near, opposite, far]);
+        assert_eq!(xor_distance(&target, &near)[0], 0x01);
+        assert_eq!(xor_distance(&target, &opposite)[0], 0x7f);
+    }
+
+    #[wasm_bindgen_test]
+    async fn stream_permits_cap_a_redundancy_16_fan_out() {
+        let budget = StreamBudget::with_cap(4);
+        let open = Arc::new(Mutex::new((0_u64, 0_u64)));
+
+        let requests = (0..16).map(|_| {
+            let budget = budget.clone();
+            let open = open.clone();
+            async move {
+                let permit = budget.acquire().await;
+                {
+                    let mut open = open.lock_recover();
+                    open.0 += 1;
+                    open.1 = open.1.max(open.0);
+                }
+                async_std::task::sleep(std::time::Duration::from_millis(10)).await;
+                open.lock_recover().0 -= 1;
+                drop(permit);
+            }
+        });
+        libp2p::futures::future::join_all(requests).await;
+
+        assert_eq!(*open.lock_recover(), (0, 4));
+        // every slot came back, so a full round can be taken again without waiting
+        let permits: Vec<StreamPermit> =
+            libp2p::futures::future::join_all((0..4).map(|_| budget.acquire())).await;
+        assert_eq!(permits.len(), 4);
     }
-    (output, ind)
 }
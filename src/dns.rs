@@ -0,0 +1,122 @@
+#![cfg(target_arch = "wasm32")]
+
+use crate::JsValue;
+
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+pub const DNS_RESOLVER_URL: &str = "https://cloudflare-dns.com/dns-query";
+pub const DNSADDR_MAX_DEPTH: u8 = 4;
+
+// /dnsaddr is expanded through dns over https, /dns4 and /dns6 are left for the browser to resolve
+
+pub async fn resolve_multiaddr(address: Multiaddr, resolver_url: &str) -> Vec<Multiaddr> {
+    resolve_dnsaddr(address, resolver_url, DNSADDR_MAX_DEPTH).await
+}
+
+async fn resolve_dnsaddr(address: Multiaddr, resolver_url: &str, depth: u8) -> Vec<Multiaddr> {
+    let host = match address.iter().next() {
+        Some(Protocol::Dnsaddr(host)) => host.to_string(),
+        _ => return vec![address],
+    };
+
+    if depth == 0 {
+        return vec![];
+    }
+
+    let peer_suffix = match address.iter().last() {
+        Some(Protocol::P2p(peer)) => Some(peer),
+        _ => None,
+    };
+
+    let mut resolved: Vec<Multiaddr> = vec![];
+
+    for record in txt_records(&format!("_dnsaddr.{}", host), resolver_url).await {
+        let candidate = match record
+            .strip_prefix("dnsaddr=")
+            .and_then(|x| x.parse::<Multiaddr>().ok())
+        {
+            Some(candidate) => candidate,
+            None => continue,
+        };
+
+        // only records for the requested peer are kept when the address names one
+
+        if let Some(peer) = peer_suffix {
+            match candidate.iter().last() {
+                Some(Protocol::P2p(candidate_peer)) if candidate_peer == peer => (),
+                _ => continue,
+            }
+        }
+
+        resolved.append(&mut Box::pin(resolve_dnsaddr(candidate, resolver_url, depth - 1)).await);
+    }
+
+    web_sys::console::log_1(&JsValue::from(format!(
+        "Resolved {} to {} addresses",
+        host,
+        resolved.len()
+    )));
+
+    resolved
+}
+
+async fn txt_records(name: &str, resolver_url: &str) -> Vec<String> {
+    let opts = web_sys::RequestInit::new();
+    opts.set_method("GET");
+
+    let req_headers = match web_sys::Headers::new() {
+        Ok(headers) => headers,
+        _ => return vec![],
+    };
+    let _ = req_headers.append("Accept", "application/dns-json");
+    opts.set_headers(&req_headers);
+
+    let url = format!("{}?name={}&type=TXT", resolver_url, name);
+    let request = match web_sys::Request::new_with_str_and_init(&url, &opts) {
+        Ok(request) => request,
+        _ => return vec![],
+    };
+
+    // fetch is taken from the global scope since this runs inside a worker
+
+    let fetch = match js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("fetch")) {
+        Ok(fetch) => fetch.unchecked_into::<js_sys::Function>(),
+        _ => return vec![],
+    };
+    let promise = match fetch.call1(&js_sys::global(), &request) {
+        Ok(promise) => promise.unchecked_into::<js_sys::Promise>(),
+        _ => return vec![],
+    };
+
+    let response = match JsFuture::from(promise).await {
+        Ok(response) => response.unchecked_into::<web_sys::Response>(),
+        _ => return vec![],
+    };
+    let text = match response.text() {
+        Ok(text) => match JsFuture::from(text).await {
+            Ok(text) => text.as_string().unwrap_or_default(),
+            _ => return vec![],
+        },
+        _ => return vec![],
+    };
+
+    let answer: serde_json::Value = match serde_json::from_str(&text) {
+        Ok(answer) => answer,
+        _ => return vec![],
+    };
+
+    let mut records: Vec<String> = vec![];
+    if let Some(entries) = answer["Answer"].as_array() {
+        for entry in entries {
+            if let Some(data) = entry["data"].as_str() {
+                records.push(data.trim_matches('"').to_string());
+            }
+        }
+    }
+
+    records
+}
@@ -0,0 +1,71 @@
+#![cfg(target_arch = "wasm32")]
+
+use crate::{network_cache, JsValue};
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+// last published index of every feed, kept in the cache storage so it outlives the worker
+
+pub const FEED_CURSOR_CACHE: &str = "weeb3-feed-cursors";
+pub const FEED_CURSOR_TTL_MS: f64 = 60000.0;
+
+fn cursor_key(owner: &str, topic: &str) -> String {
+    format!("/feed-cursor/{}/{}", owner, topic)
+}
+
+async fn cursor_cache(network_id: u64) -> Option<web_sys::Cache> {
+    let caches = match js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("caches")) {
+        Ok(caches) if !caches.is_undefined() => caches.unchecked_into::<web_sys::CacheStorage>(),
+        _ => return None,
+    };
+
+    match JsFuture::from(caches.open(&network_cache(FEED_CURSOR_CACHE, network_id))).await {
+        Ok(cache) => Some(cache.unchecked_into::<web_sys::Cache>()),
+        _ => None,
+    }
+}
+
+// returns the stored index and when it was last checked against the network
+
+pub async fn load_feed_cursor(network_id: u64, owner: &str, topic: &str) -> Option<(u64, f64)> {
+    let cache = cursor_cache(network_id).await?;
+
+    let response = JsFuture::from(cache.match_with_str(&cursor_key(owner, topic)))
+        .await
+        .ok()?;
+    if response.is_undefined() {
+        return None;
+    }
+
+    let text = JsFuture::from(response.unchecked_into::<web_sys::Response>().text().ok()?)
+        .await
+        .ok()?
+        .as_string()?;
+    let cursor: serde_json::Value = serde_json::from_str(&text).ok()?;
+
+    Some((cursor["index"].as_u64()?, cursor["verified_at"].as_f64()?))
+}
+
+pub async fn store_feed_cursor(
+    network_id: u64,
+    owner: &str,
+    topic: &str,
+    index: u64,
+    verified_at: f64,
+) -> bool {
+    let cache = match cursor_cache(network_id).await {
+        Some(cache) => cache,
+        None => return false,
+    };
+
+    let body = serde_json::json!({ "index": index, "verified_at": verified_at }).to_string();
+    let response = match web_sys::Response::new_with_opt_str(Some(&body)) {
+        Ok(response) => response,
+        _ => return false,
+    };
+
+    JsFuture::from(cache.put_with_str(&cursor_key(owner, topic), &response))
+        .await
+        .is_ok()
+}
@@ -0,0 +1,48 @@
+#![cfg(target_arch = "wasm32")]
+
+use crate::JsValue;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+// raw chunk bytes as served by a bee gateway, the caller checks them against the address
+// since the gateway is not trusted with content addressing
+
+pub async fn gateway_chunk(gateway_url: &str, chunk_address: &Vec<u8>) -> Option<Vec<u8>> {
+    let opts = web_sys::RequestInit::new();
+    opts.set_method("GET");
+
+    let url = format!(
+        "{}/chunks/{}",
+        gateway_url.trim_end_matches('/'),
+        hex::encode(chunk_address)
+    );
+    let request = web_sys::Request::new_with_str_and_init(&url, &opts).ok()?;
+
+    // fetch is taken from the global scope since this runs inside a worker
+
+    let fetch = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("fetch"))
+        .ok()?
+        .unchecked_into::<js_sys::Function>();
+    let promise = fetch
+        .call1(&js_sys::global(), &request)
+        .ok()?
+        .unchecked_into::<js_sys::Promise>();
+
+    let response = JsFuture::from(promise)
+        .await
+        .ok()?
+        .unchecked_into::<web_sys::Response>();
+    if !response.ok() {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Gateway answered {} for {}",
+            response.status(),
+            hex::encode(chunk_address)
+        )));
+        return None;
+    }
+
+    let body = JsFuture::from(response.array_buffer().ok()?).await.ok()?;
+
+    Some(js_sys::Uint8Array::new(&body).to_vec())
+}
@@ -1,4 +1,5 @@
 use alloy::primitives::keccak256;
+use alloy::primitives::{normalize_v, PrimitiveSignature as Signature};
 use alloy::signers::local::PrivateKeySigner;
 use alloy::signers::Signer;
 
@@ -6,13 +7,21 @@ use byteorder::ByteOrder;
 use num::{BigUint, ToPrimitive};
 use prost::Message;
 
+use std::collections::HashMap;
+use std::future::Future;
 use std::io;
 use std::io::Cursor;
 use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use crate::stream;
 use libp2p::{
-    futures::{AsyncReadExt, AsyncWriteExt},
+    futures::{
+        future::{select, Either},
+        stream::FuturesUnordered,
+        AsyncReadExt, AsyncWriteExt, StreamExt,
+    },
     identity::ecdsa,
     PeerId, Stream,
 };
@@ -20,6 +29,7 @@ use libp2p::{
 use wasm_bindgen::JsValue;
 
 use crate::conventions::*;
+use crate::messages;
 
 use crate::weeb_3::etiquette_0;
 use crate::weeb_3::etiquette_1;
@@ -30,100 +40,279 @@ use crate::weeb_3::etiquette_5;
 use crate::weeb_3::etiquette_6;
 use crate::weeb_3::etiquette_7;
 
-use crate::HANDSHAKE_PROTOCOL;
 use crate::PSEUDOSETTLE_PROTOCOL;
 use crate::PUSHSYNC_PROTOCOL;
 use crate::RETRIEVAL_PROTOCOL;
 
+// every protocol message is length delimited, reads stop once a whole message is buffered
+// or the stream ends, so the buffer size only sets how much is taken per read
+
+pub const READ_BUFFER_SIZE: usize = 16384;
+
+// the length prefix comes from the peer, so it is held to a bound before anything is buffered,
+// a chunk delivery with its stamp is the largest message besides a batch of gossiped peers
+
+pub const MAX_MESSAGE_SIZE: usize = 8 * 1024;
+pub const MAX_PEERS_MESSAGE_SIZE: usize = 64 * 1024;
+
+const MAX_DELIMITER_LEN: usize = 10;
+
+fn delimited_len(buf: &[u8]) -> Option<usize> {
+    let len = prost::decode_length_delimiter(buf).ok()?;
+    Some(prost::length_delimiter_len(len) + len)
+}
+
+fn bounded_len(buf: &[u8], max: usize) -> io::Result<Option<usize>> {
+    match delimited_len(buf) {
+        Some(total) if total > max => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds {} bytes", total, max),
+        )),
+        Some(total) => Ok(Some(total)),
+        None if buf.len() >= MAX_DELIMITER_LEN => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid length prefix",
+        )),
+        None => Ok(None),
+    }
+}
+
+pub async fn read_delimited(stream: &mut Stream) -> io::Result<Vec<u8>> {
+    read_delimited_within(stream, MAX_MESSAGE_SIZE).await
+}
+
+pub async fn read_delimited_within(stream: &mut Stream, max: usize) -> io::Result<Vec<u8>> {
+    let mut buf_nondiscard = Vec::new();
+    let mut buf_discard = vec![0_u8; READ_BUFFER_SIZE];
+    loop {
+        if let Some(total) = bounded_len(&buf_nondiscard, max)? {
+            if buf_nondiscard.len() >= total {
+                break;
+            }
+            buf_nondiscard.reserve(total - buf_nondiscard.len());
+        }
+        let n = stream.read(&mut buf_discard).await?;
+        if n == 0 {
+            break;
+        }
+        buf_nondiscard.extend_from_slice(&buf_discard[..n]);
+    }
+    Ok(buf_nondiscard)
+}
+
+// takes exactly the declared message, a stream that ends before the prefix or the message
+// is complete is an error instead of a short and corrupt message
+
+pub async fn read_delimited_exact(stream: &mut Stream) -> io::Result<Vec<u8>> {
+    let mut message = read_delimited(stream).await?;
+    match delimited_len(&message) {
+        Some(total) if message.len() >= total => {
+            message.truncate(total);
+            Ok(message)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("stream closed after {} bytes of a message", message.len()),
+        )),
+    }
+}
+
+// inbound streams are handled side by side up to the cap, a stream past the cap or one that
+// serve declines is dropped unanswered
+
+pub async fn serve_streams<S, F, Fut>(mut streams: S, cap: usize, mut serve: F)
+where
+    S: libp2p::futures::Stream<Item = (PeerId, Stream)> + Unpin,
+    F: FnMut(PeerId, Stream) -> Option<Fut>,
+    Fut: Future<Output = ()>,
+{
+    let mut serving = FuturesUnordered::new();
+    loop {
+        let incoming = match serving.is_empty() {
+            true => streams.next().await,
+            false => match select(streams.next(), serving.next()).await {
+                Either::Left((incoming, _)) => incoming,
+                Either::Right(_) => continue,
+            },
+        };
+        let (peer, stream) = match incoming {
+            Some(incoming) => incoming,
+            None => break,
+        };
+
+        if serving.len() >= cap {
+            drop(stream);
+            continue;
+        }
+        if let Some(handling) = serve(peer, stream) {
+            serving.push(handling);
+        }
+    }
+
+    while serving.next().await.is_some() {}
+}
+
 pub async fn ceive(
     peer: PeerId,
     stream: &mut Stream,
     a: libp2p::core::Multiaddr,
     pk: &ecdsa::SecretKey,
     chan: &mpsc::Sender<PeerFile>,
-) -> io::Result<()> {
-    let mut step_0 = etiquette_1::Syn::default();
-
-    step_0.observed_underlay = a.clone().to_vec();
+    verified_peers: &Mutex<HashMap<PeerId, VerifiedPeer>>,
+    network_id: u64,
+    trace: Option<&js_sys::Function>,
+    advertised: &Vec<libp2p::core::Multiaddr>,
+    clock: &dyn Clock,
+) -> Result<(), HandshakeError> {
+    let step_0 = messages::Syn::with_underlay(a.clone().to_vec());
 
     let mut bufw_0 = Vec::new();
 
     let step_0_len = step_0.encoded_len();
 
     bufw_0.reserve(step_0_len + prost::length_delimiter_len(step_0_len));
-    step_0.encode_length_delimited(&mut bufw_0).unwrap();
+    step_0
+        .encode_length_delimited(&mut bufw_0)
+        .map_err(io::Error::from)?;
+
+    // a connection dropping mid handshake fails this handshake only
 
     stream.write_all(&bufw_0).await?;
+    stream.flush().await?;
+    trace_handshake(trace, &peer, "syn", &bufw_0);
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let buf_nondiscard_0 = read_delimited(stream).await?;
+    trace_handshake(trace, &peer, "synack", &buf_nondiscard_0);
 
+    let closed_after_syn = buf_nondiscard_0.is_empty();
     let rec_0_u = etiquette_1::SynAck::decode_length_delimited(&mut Cursor::new(buf_nondiscard_0));
 
     let rec_0 = match rec_0_u {
         Ok(x) => x,
-        Err(_x) => {
+        Err(x) => {
             let _ = stream.close().await;
-            return Ok(());
+            if closed_after_syn {
+                return Err(HandshakeError::ClosedAfterSyn);
+            }
+            return Err(HandshakeError::DecodeFailed(x.to_string()));
         }
     };
 
-    let underlay = libp2p::core::Multiaddr::try_from(rec_0.syn.unwrap().observed_underlay).unwrap();
+    let (observed_underlay, peer_ack) = match (rec_0.syn, rec_0.ack) {
+        (Some(syn), Some(ack)) => (syn.observed_underlay, ack),
+        _ => {
+            let _ = stream.close().await;
+            return Err(HandshakeError::DecodeFailed(
+                "synack without syn or ack".to_string(),
+            ));
+        }
+    };
+    let underlay = match libp2p::core::Multiaddr::try_from(observed_underlay.clone()) {
+        Ok(underlay) => underlay,
+        Err(e) => {
+            let _ = stream.close().await;
+            return Err(HandshakeError::DecodeFailed(e.to_string()));
+        }
+    };
 
-    let peer_overlay = rec_0.ack.unwrap().address.unwrap().overlay;
+    let peer_address = match peer_ack.address.clone() {
+        Some(peer_address) => peer_address,
+        None => {
+            let _ = stream.close().await;
+            return Err(HandshakeError::DecodeFailed(
+                "ack without address".to_string(),
+            ));
+        }
+    };
+    let peer_overlay = peer_address.overlay.clone();
+
+    if peer_ack.network_id != network_id {
+        let _ = stream.close().await;
+        return Err(HandshakeError::NetworkIdMismatch {
+            ours: network_id,
+            theirs: peer_ack.network_id,
+        });
+    }
 
     // web_sys::console::log_1(&JsValue::from(format!("Got underlay {}!", underlay)));
 
-    let mut step_1 = etiquette_1::Ack::default();
-
-    let signer: PrivateKeySigner = PrivateKeySigner::from_slice(&pk.to_bytes()).unwrap();
-    let addrep = signer.address();
-    let addre = addrep.to_vec();
-
-    let mut bufidl: [u8; 8] = [0; 8];
-    byteorder::LittleEndian::write_u64(&mut bufidl, 10_u64);
-    let byteslice = [addre.as_slice(), &bufidl].concat();
-    let nonce: [u8; 32] = [0; 32];
-    let byteslice2 = [byteslice, (&nonce).to_vec()].concat();
-    let overlayp = keccak256(byteslice2);
-    let overlay = &overlayp;
-
-    let hsprefix: &[u8] = &"bee-handshake-".to_string().into_bytes();
-
-    let mut bufidb: [u8; 8] = [0; 8];
-    byteorder::BigEndian::write_u64(&mut bufidb, 10_u64);
-    let byteslice3 = [hsprefix.to_vec(), underlay.to_vec()].concat();
-    let byteslice4 = [byteslice3, overlay.to_vec()].concat();
-    let byteslice5 = [byteslice4, bufidb.to_vec()].concat();
+    // a recently verified peer presenting the same handshake data skips signature recovery,
+    // any change drops the cached entry and the peer is verified again
+
+    let known = {
+        let mut verified = verified_peers.lock_recover();
+        match verified.get(&peer) {
+            Some(entry)
+                if clock.now_ms() - entry.verified_at < VERIFIED_PEER_TTL
+                    && entry.overlay == peer_address.overlay
+                    && entry.underlay == peer_address.underlay
+                    && entry.signature == peer_address.signature
+                    && entry.nonce == peer_ack.nonce =>
+            {
+                Some(entry.clone())
+            }
+            Some(_) => {
+                verified.remove(&peer);
+                None
+            }
+            None => None,
+        }
+    };
 
-    let signature = signer.sign_message(&byteslice5).await.unwrap();
+    if known.is_none()
+        && !verify_handshake_address(&peer_address, &peer_ack.nonce, peer_ack.network_id)
+    {
+        let _ = stream.close().await;
+        return Err(HandshakeError::SignatureInvalid);
+    }
 
-    let mut step_1_ad = etiquette_1::BzzAddress::default();
+    // our own ack only depends on the underlay it signs
 
-    step_1_ad.overlay = overlay.to_vec();
-    step_1_ad.underlay = underlay.to_vec();
-    step_1_ad.signature = signature.as_bytes().to_vec();
+    let (own, full_node) = own_underlay(&underlay, advertised);
+    let step_1 = match known {
+        Some(ref entry)
+            if entry.ack.full_node == full_node
+                && entry.ack.address.as_ref().map(|address| &address.underlay)
+                    == Some(&own.to_vec()) =>
+        {
+            entry.ack.clone()
+        }
+        _ => match compose_ack(pk, &own, [0; 32], network_id, full_node).await {
+            Ok(ack) => ack,
+            Err(e) => {
+                let _ = stream.close().await;
+                return Err(e);
+            }
+        },
+    };
 
-    step_1.address = Some(step_1_ad);
-    step_1.nonce = nonce.to_vec();
-    step_1.network_id = 10_u64;
-    step_1.full_node = false;
-    step_1.welcome_message = "... Ara Ara ...".to_string();
+    verified_peers.lock_recover().insert(
+        peer,
+        VerifiedPeer {
+            overlay: peer_address.overlay.clone(),
+            underlay: peer_address.underlay.clone(),
+            signature: peer_address.signature.clone(),
+            nonce: peer_ack.nonce.clone(),
+            observed_underlay,
+            ack: step_1.clone(),
+            verified_at: match known {
+                Some(ref entry) => entry.verified_at,
+                None => clock.now_ms(),
+            },
+        },
+    );
 
     let mut bufw_1 = Vec::new();
 
     let step_1_len = step_1.encoded_len();
 
     bufw_1.reserve(step_1_len + prost::length_delimiter_len(step_1_len));
-    step_1.encode_length_delimited(&mut bufw_1).unwrap();
+    step_1
+        .encode_length_delimited(&mut bufw_1)
+        .map_err(io::Error::from)?;
     stream.write_all(&bufw_1).await?;
+    stream.flush().await?;
+    trace_handshake(trace, &peer, "ack", &bufw_1);
 
     let _ = stream.close().await;
     web_sys::console::log_1(&JsValue::from(format!("Connected Peer {:#?}!", peer)));
@@ -132,11 +321,216 @@ pub async fn ceive(
         peer_id: peer,
         overlay: peer_overlay.clone(),
     })
-    .unwrap();
+    .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "peer channel closed"))?;
 
     Ok(())
 }
 
+// overlay = keccak256(eth address | network id u64 little endian | nonce)
+
+pub fn overlay_address(eth_address: &[u8], network_id: u64, nonce: &[u8; 32]) -> Vec<u8> {
+    let mut bufidl: [u8; 8] = [0; 8];
+    byteorder::LittleEndian::write_u64(&mut bufidl, network_id);
+
+    let mut preimage = Vec::with_capacity(eth_address.len() + bufidl.len() + nonce.len());
+    preimage.extend_from_slice(eth_address);
+    preimage.extend_from_slice(&bufidl);
+    preimage.extend_from_slice(nonce);
+
+    keccak256(preimage).to_vec()
+}
+
+// signed handshake payload = "bee-handshake-" | underlay | overlay | network id u64 big endian
+
+const HANDSHAKE_SIGNATURE_PREFIX: &[u8] = b"bee-handshake-";
+const REQUEST_SIGNATURE_HEADER: &str = "weeb3-request-signature";
+const REQUEST_SIGNATURE_PREFIX: &[u8] = b"weeb3-request-";
+
+// a signed request is only accepted this close to the server clock, so a captured one cannot
+// be replayed later
+
+pub const REQUEST_SIGNATURE_MAX_AGE_MS: f64 = 30000.0;
+
+fn handshake_signed_payload(underlay: &[u8], overlay: &[u8], network_id: u64) -> Vec<u8> {
+    let mut bufidb: [u8; 8] = [0; 8];
+    byteorder::BigEndian::write_u64(&mut bufidb, network_id);
+
+    let mut payload = Vec::with_capacity(
+        HANDSHAKE_SIGNATURE_PREFIX.len() + underlay.len() + overlay.len() + bufidb.len(),
+    );
+    payload.extend_from_slice(HANDSHAKE_SIGNATURE_PREFIX);
+    payload.extend_from_slice(underlay);
+    payload.extend_from_slice(overlay);
+    payload.extend_from_slice(&bufidb);
+
+    payload
+}
+
+pub fn verify_handshake_address(
+    address: &etiquette_1::BzzAddress,
+    nonce: &[u8],
+    network_id: u64,
+) -> bool {
+    if address.signature.len() != 65 || nonce.len() != 32 {
+        return false;
+    }
+
+    let signed = handshake_signed_payload(&address.underlay, &address.overlay, network_id);
+
+    let parity: bool = match normalize_v(address.signature[64] as u64) {
+        Some(par) => par,
+        _ => {
+            return false;
+        }
+    };
+    let sig = Signature::from_bytes_and_parity(&address.signature[0..64], parity);
+
+    let eth_address = match sig.recover_address_from_msg(signed) {
+        Ok(ea) => ea,
+        _ => {
+            return false;
+        }
+    };
+
+    let mut nonce_bytes: [u8; 32] = [0; 32];
+    nonce_bytes.copy_from_slice(nonce);
+
+    overlay_address(eth_address.as_slice(), network_id, &nonce_bytes) == address.overlay
+}
+
+// signed request payload = "weeb3-request-" | chunk address | requester overlay | timestamp
+// u64 big endian, signed as an ethereum message and sent as [timestamp][signature], the server
+// rebuilds it with the overlay the requester presented in its handshake
+
+fn request_signed_payload(chunk_address: &[u8], overlay: &[u8], timestamp: u64) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(
+        REQUEST_SIGNATURE_PREFIX.len() + chunk_address.len() + overlay.len() + 8,
+    );
+    payload.extend_from_slice(REQUEST_SIGNATURE_PREFIX);
+    payload.extend_from_slice(chunk_address);
+    payload.extend_from_slice(overlay);
+    payload.extend_from_slice(&timestamp.to_be_bytes());
+
+    payload
+}
+
+pub async fn sign_request(
+    pk: &ecdsa::SecretKey,
+    chunk_address: &Vec<u8>,
+    network_id: u64,
+    timestamp: u64,
+) -> Option<Vec<u8>> {
+    let signer = PrivateKeySigner::from_slice(&pk.to_bytes()).ok()?;
+    let overlay = overlay_address(signer.address().as_slice(), network_id, &[0; 32]);
+    let signature = signer
+        .sign_message(&request_signed_payload(chunk_address, &overlay, timestamp))
+        .await
+        .ok()?;
+    Some(
+        [
+            timestamp.to_be_bytes().to_vec(),
+            signature.as_bytes().to_vec(),
+        ]
+        .concat(),
+    )
+}
+
+pub fn verify_request_signature(
+    chunk_address: &Vec<u8>,
+    token: &Vec<u8>,
+    verified: &VerifiedPeer,
+    network_id: u64,
+    now_ms: f64,
+) -> bool {
+    if token.len() != 8 + 65 || verified.nonce.len() != 32 {
+        return false;
+    }
+
+    let (timestamp, signature) = token.split_at(8);
+    let timestamp = u64::from_be_bytes(timestamp.try_into().unwrap_or_default());
+    if (now_ms - timestamp as f64).abs() > REQUEST_SIGNATURE_MAX_AGE_MS {
+        return false;
+    }
+
+    let parity: bool = match normalize_v(signature[64] as u64) {
+        Some(par) => par,
+        _ => {
+            return false;
+        }
+    };
+    let sig = Signature::from_bytes_and_parity(&signature[0..64], parity);
+
+    let signed = request_signed_payload(chunk_address, &verified.overlay, timestamp);
+    let eth_address = match sig.recover_address_from_msg(signed) {
+        Ok(ea) => ea,
+        _ => {
+            return false;
+        }
+    };
+
+    let mut nonce_bytes: [u8; 32] = [0; 32];
+    nonce_bytes.copy_from_slice(&verified.nonce);
+
+    overlay_address(eth_address.as_slice(), network_id, &nonce_bytes) == verified.overlay
+}
+
+pub fn own_overlay(pk: &ecdsa::SecretKey, network_id: u64) -> Vec<u8> {
+    let signer: PrivateKeySigner = PrivateKeySigner::from_slice(&pk.to_bytes()).unwrap();
+    overlay_address(signer.address().as_slice(), network_id, &[0; 32])
+}
+
+// a full node signs the first address it advertises into its ack and says it is one, a light
+// node signs the underlay the peer observed
+
+fn own_underlay(
+    observed: &libp2p::core::Multiaddr,
+    advertised: &[libp2p::core::Multiaddr],
+) -> (libp2p::core::Multiaddr, bool) {
+    match advertised.first() {
+        Some(address) => (address.clone(), true),
+        None => (observed.clone(), false),
+    }
+}
+
+pub async fn compose_ack(
+    pk: &ecdsa::SecretKey,
+    underlay: &libp2p::core::Multiaddr,
+    nonce: [u8; 32],
+    network_id: u64,
+    full_node: bool,
+) -> Result<etiquette_1::Ack, HandshakeError> {
+    let mut step_1 = etiquette_1::Ack::default();
+
+    let signer: PrivateKeySigner = match PrivateKeySigner::from_slice(&pk.to_bytes()) {
+        Ok(signer) => signer,
+        Err(_) => return Err(HandshakeError::UnsupportedKeyType),
+    };
+    let addre = signer.address().to_vec();
+
+    let overlay = &overlay_address(&addre, network_id, &nonce);
+
+    let signed = handshake_signed_payload(&underlay.to_vec(), overlay, network_id);
+
+    let signature = match signer.sign_message(&signed).await {
+        Ok(signature) => signature,
+        Err(_) => return Err(HandshakeError::UnsupportedKeyType),
+    };
+
+    let mut step_1_ad = etiquette_1::BzzAddress::default();
+
+    step_1_ad.overlay = overlay.to_vec();
+    step_1_ad.underlay = underlay.to_vec();
+    step_1_ad.signature = signature.as_bytes().to_vec();
+
+    step_1.address = Some(step_1_ad);
+    step_1.nonce = nonce.to_vec();
+    step_1.network_id = network_id;
+    step_1.full_node = full_node;
+    step_1.welcome_message = "... Ara Ara ...".to_string();
+
+    Ok(step_1)
+}
+
 pub async fn pricing_handler(
     peer: PeerId,
     mut stream: Stream,
@@ -146,15 +540,7 @@ pub async fn pricing_handler(
         "Opened Pricing handle 2 for peer !",
     )));
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let _ = read_delimited(&mut stream).await?;
 
     let empty = etiquette_0::Headers::default();
 
@@ -167,15 +553,7 @@ pub async fn pricing_handler(
     stream.write_all(&buf_empty).await?;
     let _ = stream.flush().await;
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let buf_nondiscard_0 = read_delimited(&mut stream).await?;
 
     let _ = stream.flush().await;
     let _ = stream.close().await;
@@ -214,15 +592,7 @@ pub async fn gossip_handler(
         format!("Opened Gossip Handle 2 for peer !",),
     ));
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let _ = read_delimited(&mut stream).await?;
 
     let empty = etiquette_0::Headers::default();
 
@@ -235,15 +605,7 @@ pub async fn gossip_handler(
     stream.write_all(&buf_empty).await?;
     let _ = stream.flush().await;
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let buf_nondiscard_0 = read_delimited_within(&mut stream, MAX_PEERS_MESSAGE_SIZE).await?;
 
     let _ = stream.flush().await;
     let _ = stream.close().await;
@@ -270,12 +632,7 @@ pub async fn gossip_handler(
     Ok(())
 }
 
-pub async fn fresh(
-    peer: PeerId,
-    amount: u64,
-    stream: &mut Stream,
-    chan: &mpsc::Sender<(PeerId, u64)>,
-) -> io::Result<()> {
+pub async fn fresh(peer: PeerId, amount: u64, stream: &mut Stream) -> io::Result<u64> {
     web_sys::console::log_1(&JsValue::from(format!(
         "Opened Refresh Handle 2 for peer ! {}",
         amount
@@ -292,19 +649,9 @@ pub async fn fresh(
     stream.write_all(&buf_empty).await?;
     let _ = stream.flush().await;
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let _ = read_delimited(stream).await?;
 
-    let mut step_1 = etiquette_5::Payment::default();
-
-    step_1.amount = BigUint::from(amount).to_bytes_be();
+    let step_1 = messages::Payment::with_amount(BigUint::from(amount).to_bytes_be());
 
     let mut bufw_1 = Vec::new();
 
@@ -314,15 +661,7 @@ pub async fn fresh(
     step_1.encode_length_delimited(&mut bufw_1).unwrap();
     stream.write_all(&bufw_1).await?;
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let buf_nondiscard_0 = read_delimited(stream).await?;
 
     let _ = stream.close().await;
     let rec_0_u =
@@ -331,35 +670,44 @@ pub async fn fresh(
     let rec_0 = match rec_0_u {
         Ok(x) => x,
         Err(_x) => {
-            return Ok(());
+            return Ok(0);
         }
     };
 
-    let refr_am = BigUint::from_bytes_be(&rec_0.amount).to_u64().unwrap();
+    // the peer grants at most what its own time based allowance permits
+
+    let refr_am = BigUint::from_bytes_be(&rec_0.amount)
+        .to_u64()
+        .unwrap_or(0)
+        .min(amount);
 
     web_sys::console::log_1(&JsValue::from(format!(
-        "Accepted Refresh {:#?} from peer {:#?}!",
-        refr_am, peer
+        "Accepted Refresh {:#?} of {:#?} from peer {:#?} at {}!",
+        refr_am, amount, peer, rec_0.timestamp
     )));
 
-    if amount > 0 {
-        chan.send((peer, refr_am)).unwrap();
-    }
-
-    Ok(())
+    Ok(refr_am)
 }
 
 pub async fn trieve(
     peer: PeerId,
     chunk_address: Vec<u8>,
+    signature: &Vec<u8>,
     stream: &mut Stream,
-    chan: &mpsc::Sender<Vec<u8>>,
-) -> io::Result<()> {
+    chan: &mpsc::Sender<(Vec<u8>, Vec<u8>)>,
+    bandwidth: &BandwidthMeter,
+) -> io::Result<RetrieveOutcome> {
     web_sys::console::log_1(&JsValue::from(format!(
         "Opened Retrieve Handle 2 for peer !",
     )));
 
-    let empty = etiquette_0::Headers::default();
+    let mut empty = etiquette_0::Headers::default();
+    if signature.len() > 0 {
+        empty.headers.push(messages::Header::new(
+            REQUEST_SIGNATURE_HEADER,
+            signature.clone(),
+        ));
+    }
 
     let mut buf_empty = Vec::new();
 
@@ -369,20 +717,12 @@ pub async fn trieve(
 
     stream.write_all(&buf_empty).await?;
     let _ = stream.flush().await;
+    bandwidth.meter_out(buf_empty.len()).await;
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let buf_nondiscard_0 = read_delimited(stream).await?;
+    bandwidth.meter_in(buf_nondiscard_0.len()).await;
 
-    let mut step_1 = etiquette_6::Request::default();
-
-    step_1.addr = chunk_address;
+    let step_1 = messages::RetrievalRequest::for_address(chunk_address);
 
     let mut bufw_1 = Vec::new();
 
@@ -391,39 +731,55 @@ pub async fn trieve(
     bufw_1.reserve(step_1_len + prost::length_delimiter_len(step_1_len));
     step_1.encode_length_delimited(&mut bufw_1).unwrap();
     stream.write_all(&bufw_1).await?;
+    let _ = stream.flush().await;
+    bandwidth.meter_out(bufw_1.len()).await;
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
-
+    let buf_nondiscard_0 = read_delimited_exact(stream).await;
     let _ = stream.close().await;
+    let buf_nondiscard_0 = buf_nondiscard_0?;
+    bandwidth.meter_in(buf_nondiscard_0.len()).await;
 
     let rec_0_u =
         etiquette_6::Delivery::decode_length_delimited(&mut Cursor::new(buf_nondiscard_0));
 
+    // an undecodable delivery means the transfer was cut short
+
     let rec_0 = match rec_0_u {
         Ok(x) => x,
         Err(_x) => {
-            return Ok(());
+            return Ok(RetrieveOutcome::StreamReset);
         }
     };
 
-    let rec_1 = rec_0.data;
+    if rec_0.err.len() > 0 || rec_0.data.len() == 0 {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Peer {} does not have chunk: {}",
+            peer, rec_0.err
+        )));
+        return Ok(RetrieveOutcome::NotFound);
+    }
 
     web_sys::console::log_1(&JsValue::from(format!(
         "Got chunk {:#?} from peer {:#?}!",
         rec_0.stamp, peer
     )));
 
-    chan.send(rec_1).unwrap();
+    let _ = chan.send((rec_0.data, rec_0.stamp));
 
-    Ok(())
+    Ok(RetrieveOutcome::Delivered)
+}
+
+// raw bytes as they went over the wire, length prefix included, for diffing against other clients
+
+fn trace_handshake(trace: Option<&js_sys::Function>, peer: &PeerId, message: &str, bytes: &[u8]) {
+    if let Some(callback) = trace {
+        let _ = callback.call3(
+            &JsValue::NULL,
+            &JsValue::from(peer.to_string()),
+            &JsValue::from(message),
+            &JsValue::from(hex::encode(bytes)),
+        );
+    }
 }
 
 pub async fn connection_handler(
@@ -432,28 +788,202 @@ pub async fn connection_handler(
     a: &libp2p::core::Multiaddr,
     pk: &ecdsa::SecretKey,
     chan: &mpsc::Sender<PeerFile>,
-) {
-    let mut stream = match control.open_stream(peer, HANDSHAKE_PROTOCOL).await {
-        Ok(stream) => stream,
-        Err(error @ stream::OpenStreamError::UnsupportedProtocol(_)) => {
-            web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, error)));
-            return;
+    verified_peers: &Mutex<HashMap<PeerId, VerifiedPeer>>,
+    handshake: &HandshakeConfig,
+) -> Result<(), HandshakeError> {
+    // transient failures are retried after a short pause, everything else ends the attempt
+
+    let mut attempt = 1;
+    loop {
+        match handshake_once(peer, control, a, pk, chan, verified_peers, handshake).await {
+            Err(e) if e.retryable() && attempt < HANDSHAKE_ATTEMPTS => {
+                web_sys::console::log_1(&JsValue::from(format!(
+                    "{} handshake attempt {} failed ({}), retrying",
+                    peer,
+                    attempt,
+                    e.kind()
+                )));
+                attempt += 1;
+                async_std::task::sleep(Duration::from_millis(HANDSHAKE_RETRY_BACKOFF_MS)).await;
+            }
+            outcome => return outcome,
         }
-        Err(error) => {
-            web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, error)));
-            return;
+    }
+}
+
+async fn handshake_once(
+    peer: PeerId,
+    control: &mut stream::Control,
+    a: &libp2p::core::Multiaddr,
+    pk: &ecdsa::SecretKey,
+    chan: &mpsc::Sender<PeerFile>,
+    verified_peers: &Mutex<HashMap<PeerId, VerifiedPeer>>,
+    handshake: &HandshakeConfig,
+) -> Result<(), HandshakeError> {
+    // bee versions differ in the handshake version they speak, an unsupported one moves on to
+    // the next configured version and only the last refusal ends the attempt
+
+    let mut unsupported = None;
+    let mut opened = None;
+    for protocol in handshake.protocols.iter() {
+        match control.open_stream(peer, protocol.clone()).await {
+            Ok(stream) => {
+                opened = Some((stream, protocol));
+                break;
+            }
+            Err(error @ stream::OpenStreamError::UnsupportedProtocol(_)) => {
+                web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, error)));
+                unsupported = Some(error);
+            }
+            Err(error) => {
+                web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, error)));
+                return Err(HandshakeError::Io(io::Error::other(error.to_string())));
+            }
+        }
+    }
+
+    let (mut stream, protocol) = match opened {
+        Some(opened) => opened,
+        None => {
+            return Err(HandshakeError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                unsupported
+                    .map(|error| error.to_string())
+                    .unwrap_or("no handshake protocol configured".to_string()),
+            )));
         }
     };
 
-    if let Err(e) = ceive(peer, &mut stream, a.clone(), &pk.clone(), chan).await {
+    // a half open stream would otherwise leave the read loop waiting forever
+
+    let outcome = match async_std::future::timeout(
+        Duration::from_millis(handshake.timeout_ms as u64),
+        ceive(
+            peer,
+            &mut stream,
+            a.clone(),
+            &pk.clone(),
+            chan,
+            verified_peers,
+            handshake.network_id,
+            handshake.trace.as_ref(),
+            &handshake.advertised,
+            handshake.clock.as_ref(),
+        ),
+    )
+    .await
+    {
+        Ok(outcome) => outcome,
+        Err(_) => {
+            let _ = stream.close().await;
+            Err(HandshakeError::Timeout)
+        }
+    };
+
+    if let Err(e) = outcome {
         web_sys::console::log_1(&JsValue::from("Handshake protocol failed"));
-        web_sys::console::log_1(&JsValue::from(format!("{}", e)));
-        return;
+        web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, e)));
+        return Err(e);
     }
 
-    web_sys::console::log_1(&JsValue::from(format!("{} Handshake complete!", peer)));
+    web_sys::console::log_1(&JsValue::from(format!(
+        "{} Handshake complete over {}!",
+        peer, protocol
+    )));
 
     web_sys::console::log_1(&JsValue::from(format!("Closing handler 1")));
+
+    Ok(())
+}
+
+// the dialer's side of the exchange, its syn names the underlay it reached us on which our ack
+// signs, its ack is verified like the one of a peer we dialed
+
+pub async fn handshake_inbound_handler(
+    peer: PeerId,
+    stream: &mut Stream,
+    remote: libp2p::core::Multiaddr,
+    pk: &ecdsa::SecretKey,
+    chan: &mpsc::Sender<PeerFile>,
+    verified_peers: &Mutex<HashMap<PeerId, VerifiedPeer>>,
+    handshake: &HandshakeConfig,
+) -> Result<(), HandshakeError> {
+    let trace = handshake.trace.as_ref();
+
+    let buf_syn = read_delimited(stream).await?;
+    trace_handshake(trace, &peer, "syn", &buf_syn);
+
+    let syn = etiquette_1::Syn::decode_length_delimited(&mut Cursor::new(buf_syn))
+        .map_err(|e| HandshakeError::DecodeFailed(e.to_string()))?;
+    let underlay = libp2p::core::Multiaddr::try_from(syn.observed_underlay.clone())
+        .map_err(|e| HandshakeError::DecodeFailed(e.to_string()))?;
+
+    let (own, full_node) = own_underlay(&underlay, &handshake.advertised);
+    let ack = compose_ack(pk, &own, [0; 32], handshake.network_id, full_node).await?;
+    let step_0 = messages::SynAck {
+        syn: Some(messages::Syn::with_underlay(remote.to_vec())),
+        ack: Some(ack.clone()),
+    };
+
+    let mut bufw_0 = Vec::new();
+
+    let step_0_len = step_0.encoded_len();
+
+    bufw_0.reserve(step_0_len + prost::length_delimiter_len(step_0_len));
+    step_0
+        .encode_length_delimited(&mut bufw_0)
+        .map_err(io::Error::from)?;
+    stream.write_all(&bufw_0).await?;
+    stream.flush().await?;
+    trace_handshake(trace, &peer, "synack", &bufw_0);
+
+    let buf_ack = read_delimited(stream).await?;
+    trace_handshake(trace, &peer, "ack", &buf_ack);
+    let _ = stream.close().await;
+
+    let peer_ack = etiquette_1::Ack::decode_length_delimited(&mut Cursor::new(buf_ack))
+        .map_err(|e| HandshakeError::DecodeFailed(e.to_string()))?;
+    let peer_address = match peer_ack.address {
+        Some(peer_address) => peer_address,
+        None => {
+            return Err(HandshakeError::DecodeFailed(
+                "ack without address".to_string(),
+            ))
+        }
+    };
+
+    if peer_ack.network_id != handshake.network_id {
+        return Err(HandshakeError::NetworkIdMismatch {
+            ours: handshake.network_id,
+            theirs: peer_ack.network_id,
+        });
+    }
+    if !verify_handshake_address(&peer_address, &peer_ack.nonce, peer_ack.network_id) {
+        return Err(HandshakeError::SignatureInvalid);
+    }
+
+    verified_peers.lock_recover().insert(
+        peer,
+        VerifiedPeer {
+            overlay: peer_address.overlay.clone(),
+            underlay: peer_address.underlay.clone(),
+            signature: peer_address.signature.clone(),
+            nonce: peer_ack.nonce.clone(),
+            observed_underlay: syn.observed_underlay,
+            ack,
+            verified_at: handshake.clock.now_ms(),
+        },
+    );
+
+    web_sys::console::log_1(&JsValue::from(format!("Accepted Peer {:#?}!", peer)));
+
+    chan.send(PeerFile {
+        peer_id: peer,
+        overlay: peer_address.overlay,
+    })
+    .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "peer channel closed"))?;
+
+    Ok(())
 }
 
 pub async fn refresh_handler(
@@ -462,23 +992,32 @@ pub async fn refresh_handler(
     control: &mut stream::Control,
     chan: &mpsc::Sender<(PeerId, u64)>,
 ) {
+    // the granted amount is always reported, zero on failure, so the refresh is no longer ongoing
+
     let mut stream = match control.open_stream(peer, PSEUDOSETTLE_PROTOCOL).await {
         Ok(stream) => stream,
         Err(error @ stream::OpenStreamError::UnsupportedProtocol(_)) => {
             web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, error)));
+            let _ = chan.send((peer, 0));
             return;
         }
         Err(error) => {
             web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, error)));
+            let _ = chan.send((peer, 0));
             return;
         }
     };
 
-    if let Err(e) = fresh(peer, amount, &mut stream, chan).await {
-        web_sys::console::log_1(&JsValue::from("Refresh protocol failed"));
-        web_sys::console::log_1(&JsValue::from(format!("{}", e)));
-        return;
-    }
+    let granted = match fresh(peer, amount, &mut stream).await {
+        Ok(granted) => granted,
+        Err(e) => {
+            web_sys::console::log_1(&JsValue::from("Refresh protocol failed"));
+            web_sys::console::log_1(&JsValue::from(format!("{}", e)));
+            0
+        }
+    };
+
+    let _ = chan.send((peer, granted));
 
     web_sys::console::log_1(&JsValue::from(format!("Refresh complete for {}!", peer)));
 }
@@ -486,28 +1025,35 @@ pub async fn refresh_handler(
 pub async fn retrieve_handler(
     peer: PeerId,
     chunk_address: Vec<u8>,
+    signature: &Vec<u8>,
     control: &mut stream::Control,
-    chan: &mpsc::Sender<Vec<u8>>,
-) {
+    chan: &mpsc::Sender<(Vec<u8>, Vec<u8>)>,
+    bandwidth: &BandwidthMeter,
+) -> RetrieveOutcome {
     let mut stream = match control.open_stream(peer, RETRIEVAL_PROTOCOL).await {
         Ok(stream) => stream,
         Err(error @ stream::OpenStreamError::UnsupportedProtocol(_)) => {
             web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, error)));
-            return;
+            return RetrieveOutcome::Unsupported;
         }
         Err(error) => {
             web_sys::console::log_1(&JsValue::from(format!("{} {}", peer, error)));
-            return;
+            return RetrieveOutcome::StreamReset;
         }
     };
 
-    if let Err(e) = trieve(peer, chunk_address, &mut stream, chan).await {
-        web_sys::console::log_1(&JsValue::from("Retrieve protocol failed"));
-        web_sys::console::log_1(&JsValue::from(format!("{}", e)));
-        return;
-    }
+    let outcome = match trieve(peer, chunk_address, signature, &mut stream, chan, bandwidth).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            web_sys::console::log_1(&JsValue::from("Retrieve protocol failed"));
+            web_sys::console::log_1(&JsValue::from(format!("{}", e)));
+            return RetrieveOutcome::StreamReset;
+        }
+    };
 
     web_sys::console::log_1(&JsValue::from(format!("{} Retrieve complete!", peer)));
+
+    outcome
 }
 
 pub async fn pushsync_handler(
@@ -570,21 +1116,9 @@ pub async fn sync(
     stream.write_all(&buf_empty).await?;
     let _ = stream.flush().await;
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let _ = read_delimited(stream).await?;
 
-    let mut step_1 = etiquette_7::Delivery::default();
-
-    step_1.address = chunk_address;
-    step_1.data = chunk_content;
-    step_1.stamp = chunk_stamp;
+    let step_1 = messages::PushDelivery::new(chunk_address, chunk_content, chunk_stamp);
 
     let mut bufw_1 = Vec::new();
 
@@ -594,15 +1128,7 @@ pub async fn sync(
     step_1.encode_length_delimited(&mut bufw_1).unwrap();
     stream.write_all(&bufw_1).await?;
 
-    let mut buf_nondiscard_0 = Vec::new();
-    let mut buf_discard_0: [u8; 255] = [0; 255];
-    loop {
-        let n = stream.read(&mut buf_discard_0).await?;
-        buf_nondiscard_0.extend_from_slice(&buf_discard_0[..n]);
-        if n < 255 {
-            break;
-        }
-    }
+    let buf_nondiscard_0 = read_delimited(stream).await?;
 
     let _ = stream.close().await;
 
@@ -624,3 +1150,318 @@ pub async fn sync(
 
     Ok(())
 }
+
+pub async fn pushsync_inbound_handler(
+    peer: PeerId,
+    mut stream: Stream,
+    pk: &ecdsa::SecretKey,
+    overlay: &Vec<u8>,
+    depth: u8,
+    chan: &mpsc::Sender<(Vec<u8>, Vec<u8>)>,
+) -> io::Result<()> {
+    web_sys::console::log_1(&JsValue::from(format!(
+        "Opened Pushsync inbound handle for peer {}!",
+        peer
+    )));
+
+    let _ = read_delimited(&mut stream).await?;
+
+    let empty = etiquette_0::Headers::default();
+
+    let mut buf_empty = Vec::new();
+
+    let empty_len = empty.encoded_len();
+    buf_empty.reserve(empty_len + prost::length_delimiter_len(empty_len));
+    empty.encode_length_delimited(&mut buf_empty).unwrap();
+
+    stream.write_all(&buf_empty).await?;
+    let _ = stream.flush().await;
+
+    let buf_nondiscard_0 = read_delimited(&mut stream).await?;
+
+    let rec_0_u =
+        etiquette_7::Delivery::decode_length_delimited(&mut Cursor::new(buf_nondiscard_0));
+
+    let rec_0 = match rec_0_u {
+        Ok(x) => x,
+        Err(_x) => {
+            let _ = stream.close().await;
+            return Ok(());
+        }
+    };
+
+    let mut step_1 = etiquette_7::Receipt::default();
+    step_1.address = rec_0.address.clone();
+
+    let within = is_within_neighborhood(&rec_0.address, overlay, depth);
+    let valid = valid_cac(&rec_0.data, &rec_0.address) || valid_soc(&rec_0.data, &rec_0.address);
+
+    if !within {
+        step_1.err = "chunk outside of neighborhood".to_string();
+    } else if !valid {
+        step_1.err = "invalid chunk".to_string();
+    } else {
+        let signer: PrivateKeySigner = PrivateKeySigner::from_slice(&pk.to_bytes()).unwrap();
+        let signature = match signer.sign_message(&rec_0.address).await {
+            Ok(signature) => signature,
+            Err(_x) => {
+                let _ = stream.close().await;
+                return Ok(());
+            }
+        };
+        step_1.signature = signature.as_bytes().to_vec();
+        step_1.nonce = vec![0; 32];
+        step_1.storage_radius = depth.into();
+    }
+
+    let mut bufw_1 = Vec::new();
+
+    let step_1_len = step_1.encoded_len();
+
+    bufw_1.reserve(step_1_len + prost::length_delimiter_len(step_1_len));
+    step_1.encode_length_delimited(&mut bufw_1).unwrap();
+    stream.write_all(&bufw_1).await?;
+
+    let _ = stream.flush().await;
+    let _ = stream.close().await;
+
+    if within && valid {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Stored pushed chunk {} from peer {}!",
+            hex::encode(&rec_0.address),
+            peer
+        )));
+        let _ = chan.send((rec_0.address, rec_0.data));
+    }
+
+    Ok(())
+}
+
+// a browser node does not forward, a chunk it does not hold is answered with an error,
+// authorize sees the chunk address and the request signature, empty when unsigned
+
+pub async fn retrieval_inbound_handler<F, A>(
+    peer: PeerId,
+    mut stream: Stream,
+    find: F,
+    authorize: A,
+    bandwidth: &BandwidthMeter,
+) -> io::Result<Option<(Vec<u8>, usize)>>
+where
+    F: Fn(&Vec<u8>) -> Option<Vec<u8>>,
+    A: Fn(&Vec<u8>, &Vec<u8>) -> bool,
+{
+    web_sys::console::log_1(&JsValue::from(format!(
+        "Opened Retrieve inbound handle for peer {}!",
+        peer
+    )));
+
+    let buf_headers = read_delimited(&mut stream).await?;
+    bandwidth.meter_in(buf_headers.len()).await;
+
+    let signature = etiquette_0::Headers::decode_length_delimited(&mut Cursor::new(buf_headers))
+        .ok()
+        .and_then(|headers| {
+            headers
+                .headers
+                .into_iter()
+                .find(|header| header.key == REQUEST_SIGNATURE_HEADER)
+        })
+        .map(|header| header.value)
+        .unwrap_or_default();
+
+    let empty = etiquette_0::Headers::default();
+
+    let mut buf_empty = Vec::new();
+
+    let empty_len = empty.encoded_len();
+    buf_empty.reserve(empty_len + prost::length_delimiter_len(empty_len));
+    empty.encode_length_delimited(&mut buf_empty).unwrap();
+
+    stream.write_all(&buf_empty).await?;
+    let _ = stream.flush().await;
+    bandwidth.meter_out(buf_empty.len()).await;
+
+    let buf_nondiscard_0 = read_delimited_exact(&mut stream).await?;
+    bandwidth.meter_in(buf_nondiscard_0.len()).await;
+
+    let rec_0_u = etiquette_6::Request::decode_length_delimited(&mut Cursor::new(buf_nondiscard_0));
+
+    let rec_0 = match rec_0_u {
+        Ok(x) => x,
+        Err(_x) => {
+            let _ = stream.close().await;
+            return Ok(None);
+        }
+    };
+
+    let authorized = authorize(&rec_0.addr, &signature);
+    let found = match authorized {
+        true => find(&rec_0.addr),
+        false => None,
+    };
+
+    let mut step_1 = etiquette_6::Delivery::default();
+    match &found {
+        Some(data) => step_1.data = data.clone(),
+        None if !authorized => step_1.err = "request not authorized".to_string(),
+        None => step_1.err = "chunk not found".to_string(),
+    }
+
+    let mut bufw_1 = Vec::new();
+
+    let step_1_len = step_1.encoded_len();
+
+    bufw_1.reserve(step_1_len + prost::length_delimiter_len(step_1_len));
+    step_1.encode_length_delimited(&mut bufw_1).unwrap();
+    stream.write_all(&bufw_1).await?;
+    bandwidth.meter_out(bufw_1.len()).await;
+
+    let _ = stream.flush().await;
+    let _ = stream.close().await;
+
+    Ok(found.map(|data| (rec_0.addr, data.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    // vectors computed independently of alloy with the bee handshake rules, key 634fb5a8...
+    // whose ethereum address is 8d3766440f0d7b949a5e32995d09619a7f86e632
+
+    const KEY: &str = "634fb5a872396d9693e5c9f9d7233cfa93f395c093371017ff44aa9ae6564cdd";
+    const OVERLAY: &str = "cfa6c5a9f7b048b8255ae390c8d108578851a490eb45f4f13325221a875ab853";
+    const SIGNATURE: &str = "dc0d1cba0fd5b6974e2c94d8b17257d832347c8067bf0a50b49271d4ada5b65f\
+                             6a333985f6ab1290903a0725d2a74a3a9e620194ebab86efa096217b9a4f1ecf1b";
+    const ACK: &str = "0a6f0a08047f0000010606621241dc0d1cba0fd5b6974e2c94d8b17257d832347c8067\
+                       bf0a50b49271d4ada5b65f6a333985f6ab1290903a0725d2a74a3a9e620194ebab86ef\
+                       a096217b9a4f1ecf1b1a20cfa6c5a9f7b048b8255ae390c8d108578851a490eb45f4f1\
+                       3325221a875ab853100a22200000000000000000000000000000000000000000000000\
+                       0000000000000000009a060f2e2e2e2041726120417261202e2e2e";
+
+    fn fixed_key() -> ecdsa::SecretKey {
+        ecdsa::SecretKey::try_from_bytes(hex::decode(KEY).unwrap()).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn overlay_matches_reference() {
+        assert_eq!(hex::encode(own_overlay(&fixed_key(), 10)), OVERLAY);
+    }
+
+    #[wasm_bindgen_test]
+    async fn ack_matches_reference() {
+        let underlay: libp2p::core::Multiaddr = "/ip4/127.0.0.1/tcp/1634".parse().unwrap();
+        let ack = compose_ack(&fixed_key(), &underlay, [0; 32], 10, false)
+            .await
+            .unwrap();
+
+        let address = ack.address.clone().unwrap();
+        assert_eq!(hex::encode(&address.overlay), OVERLAY);
+        assert_eq!(hex::encode(&address.signature), SIGNATURE);
+        assert_eq!(hex::encode(ack.encode_to_vec()), ACK);
+        assert!(verify_handshake_address(&address, &[0; 32], 10));
+    }
+
+    #[wasm_bindgen_test]
+    fn oversized_length_prefixes_are_rejected() {
+        let mut chunk = Vec::new();
+        prost::encode_length_delimiter(4096 + 8 + 113, &mut chunk).unwrap();
+        assert_eq!(
+            bounded_len(&chunk, MAX_MESSAGE_SIZE).unwrap(),
+            Some(chunk.len() + 4096 + 8 + 113)
+        );
+
+        let mut huge = Vec::new();
+        prost::encode_length_delimiter(u32::MAX as usize, &mut huge).unwrap();
+        assert!(bounded_len(&huge, MAX_MESSAGE_SIZE).is_err());
+        assert!(bounded_len(&huge, MAX_PEERS_MESSAGE_SIZE).is_err());
+
+        assert_eq!(bounded_len(&[0x80, 0x80], MAX_MESSAGE_SIZE).unwrap(), None);
+        assert!(bounded_len(&[0xff; 10], MAX_MESSAGE_SIZE).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_unsigned_and_foreign_requests() {
+        let requester = VerifiedPeer {
+            overlay: hex::decode(OVERLAY).unwrap(),
+            underlay: vec![],
+            signature: vec![],
+            nonce: vec![0; 32],
+            observed_underlay: vec![],
+            ack: messages::Ack::default(),
+            verified_at: 0.0,
+        };
+        let chunk_address = vec![7; 32];
+        let now = 1_700_000_000_000.0;
+        let other = ecdsa::SecretKey::try_from_bytes(
+            hex::decode("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+                .unwrap(),
+        )
+        .unwrap();
+
+        let signed = sign_request(&fixed_key(), &chunk_address, 10, now as u64)
+            .await
+            .unwrap();
+        assert!(verify_request_signature(
+            &chunk_address,
+            &signed,
+            &requester,
+            10,
+            now
+        ));
+
+        // unsigned, signed by another key, for another chunk and too old to be taken
+        assert!(!verify_request_signature(
+            &chunk_address,
+            &vec![],
+            &requester,
+            10,
+            now
+        ));
+        let foreign = sign_request(&other, &chunk_address, 10, now as u64)
+            .await
+            .unwrap();
+        assert!(!verify_request_signature(
+            &chunk_address,
+            &foreign,
+            &requester,
+            10,
+            now
+        ));
+        assert!(!verify_request_signature(
+            &vec![8; 32],
+            &signed,
+            &requester,
+            10,
+            now
+        ));
+        let stale = now + REQUEST_SIGNATURE_MAX_AGE_MS + 1.0;
+        assert!(!verify_request_signature(
+            &chunk_address,
+            &signed,
+            &requester,
+            10,
+            stale
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn full_nodes_sign_the_address_they_advertise() {
+        let observed: libp2p::core::Multiaddr = "/ip4/10.0.0.2/tcp/1634".parse().unwrap();
+        let external: libp2p::core::Multiaddr = "/ip4/203.0.113.7/tcp/1634/ws".parse().unwrap();
+
+        let (own, full_node) = own_underlay(&observed, &[external.clone()]);
+        let ack = compose_ack(&fixed_key(), &own, [0; 32], 10, full_node)
+            .await
+            .unwrap();
+        let address = ack.address.clone().unwrap();
+        assert!(ack.full_node);
+        assert_eq!(address.underlay, external.to_vec());
+        assert!(verify_handshake_address(&address, &ack.nonce, 10));
+
+        // a light node advertises nothing and signs what the peer observed
+        assert_eq!(own_underlay(&observed, &[]), (observed, false));
+    }
+}
@@ -50,6 +50,8 @@ pub async fn interweeb(_st: String) -> Result<(), JsError> {
 
     let body = Body::from_current_window()?;
 
+    display_network_info();
+
     let (r_out, r_in) = mpsc::channel::<Vec<u8>>();
 
     let worker_handle = Rc::new(RefCell::new(
@@ -298,7 +300,7 @@ pub async fn interweeb(_st: String) -> Result<(), JsError> {
         #[allow(irrefutable_let_patterns)]
         while let data0 = r_in.try_recv() {
             if !data0.is_err() {
-                let (data, indx) = decode_resources(data0.unwrap());
+                let (data, indx) = decode_resources(&data0.unwrap());
                 web_sys::console::log_1(&JsValue::from(format!(
                     "data array length {:#?}",
                     data.len()
@@ -395,7 +397,7 @@ pub async fn interweeb(_st: String) -> Result<(), JsError> {
                         }
                     };
 
-                    for (data3, mime3, path3) in data {
+                    for (data3, mime3, path3, _encoding3) in data {
                         let opts = RequestInit::new();
 
                         opts.set_method("GET");
@@ -503,6 +505,39 @@ fn create_ielement(indx: String) -> Element {
     return i;
 }
 
+fn display_network_info() {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let info = match document.get_element_by_id("networkInfo") {
+        Some(info) => info,
+        _ => return,
+    };
+
+    // placeholders are only substituted when served by the local server
+
+    let mut network_id = info
+        .get_attribute("data-network-id")
+        .unwrap_or("".to_string());
+    if network_id.starts_with("__") || network_id.parse::<u64>().is_err() {
+        network_id = "10".to_string();
+    }
+
+    let mut welcome = info.get_attribute("data-welcome").unwrap_or("".to_string());
+    if welcome.starts_with("__") {
+        welcome = "".to_string();
+    }
+
+    web_sys::console::log_1(&JsValue::from(format!(
+        "Network id {} welcome {}",
+        network_id, welcome
+    )));
+
+    if welcome.len() > 0 {
+        info.set_text_content(Some(&format!("Network {} - {}", network_id, welcome)));
+    } else {
+        info.set_text_content(Some(&format!("Network {}", network_id)));
+    }
+}
+
 fn service_worker_missing() {
     let document = web_sys::window().unwrap().document().unwrap();
     let errod = document.create_element("div").unwrap();
@@ -5,29 +5,30 @@ use rand::rngs::OsRng;
 
 use std::collections::{HashMap, HashSet};
 use std::num::NonZero;
+use std::pin::pin;
 use std::str::FromStr;
 use std::sync::mpsc;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use libp2p::{
     autonat,
-    core::{self, Multiaddr, Transport},
+    connection_limits::{self, ConnectionLimits},
+    core::{self, multiaddr::Protocol, Multiaddr, Transport},
     dcutr,
     futures::{
-        future::join_all, //
+        future::{join_all, select, Either}, //
         join,
         StreamExt,
     },
     identify, identity,
     identity::{ecdsa, ecdsa::SecretKey},
-    noise, ping,
-    swarm::{NetworkBehaviour, SwarmEvent},
+    noise, ping, relay,
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmEvent},
     websocket_websys, yamux, PeerId, StreamProtocol, Swarm,
 };
 use libp2p_stream as stream;
 
-use js_sys::Date;
 use wasm_bindgen::{prelude::*, JsValue};
 
 mod accounting;
@@ -49,9 +50,28 @@ use retrieval::*;
 mod upload;
 use upload::*;
 
+mod postage;
+use postage::*;
+
 mod ens;
 use ens::*;
 
+mod dns;
+use dns::*;
+
+mod feed_cursor;
+use feed_cursor::*;
+
+mod chunk_storage;
+use chunk_storage::*;
+
+mod gateway;
+use gateway::*;
+
+mod builder;
+
+pub mod messages;
+
 pub mod weeb_3 {
     pub mod etiquette_0 {
         include!(concat!(env!("OUT_DIR"), "/weeb_3.etiquette_0.rs"));
@@ -102,452 +122,1973 @@ const PUSHSYNC_PROTOCOL: StreamProtocol = StreamProtocol::new("/swarm/pushsync/1
 // const PULL_PROTOCOL: StreamProtocol = StreamProtocol::new("/swarm/pullsync/1.4.0/pullsync");
 // const PUSH_PROTOCOL: StreamProtocol = StreamProtocol::new("/swarm/pushsync/1.3.0/pushsync");
 
+const IDENTIFY_PROTOCOL_VERSION: &str = "/weeb-3";
+const IDENTIFY_AGENT_VERSION: &str = concat!("weeb-3/", env!("CARGO_PKG_VERSION"));
+
 const RETRIEVE_ROUND_TIME: f64 = 600.0;
+const DEFAULT_NEIGHBORHOOD_DEPTH: u8 = 8;
+const DEFAULT_CHUNK_STORE_CAPACITY: usize = 16384;
+const FETCH_CACHE_CONTROL: &str = "public, max-age=3600";
+const UNDECODED_ENCODING_HEADER: &str = "weeb3-content-encoding";
+const DEFAULT_MAX_ESTABLISHED: u32 = 64;
+const DEFAULT_MAX_PENDING: u32 = 16;
+const DEFAULT_NETWORK_ID: u64 = 10;
+const DEFAULT_BOOTSTRAP_ATTEMPTS: u32 = 5;
+const IMPORTED_DIAL_BATCH: usize = 16;
+const BOOTSTRAP_BACKOFF_MS: f64 = 1000.0;
+const BOOTSTRAP_MAX_BACKOFF_MS: f64 = 60000.0;
+const BOOTNODE_ADDRESS: &str =
+    "/ip4/192.168.0.101/tcp/18634/ws/p2p/QmaniMaU5kNYzk7pQPWnBmB7Qp1o28FUW9cG4xVC4tGJbK";
 const EVENT_LOOP_INTERRUPTOR: f64 = 600.0;
 const PROTO_LOOP_INTERRUPTOR: f64 = 600.0;
+const MAX_INBOUND_HANDSHAKES: usize = 16;
+const MAX_INBOUND_RETRIEVALS: usize = 32;
+const RETRIEVAL_INBOUND_TIMEOUT_MS: u64 = 10000;
 
 #[wasm_bindgen]
 pub fn init_panic_hook() {
     console_error_panic_hook::set_once();
 }
 
+fn global_constructor(name: &str) -> bool {
+    match js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str(name)) {
+        Ok(value) => value.is_function(),
+        _ => false,
+    }
+}
+
+// the client dials over websockets, this is the only browser api it can not run without
+
+#[wasm_bindgen]
+pub fn is_supported() -> bool {
+    global_constructor("WebSocket")
+}
+
+#[wasm_bindgen]
+pub fn webrtc_supported() -> bool {
+    global_constructor("RTCPeerConnection")
+}
+
 #[wasm_bindgen]
 pub struct Sekirei {
     swarm: Mutex<Swarm<Behaviour>>,
     secret_key: Mutex<SecretKey>,
     wings: Mutex<Wings>,
+    retrieval_config: Mutex<RetrievalConfig>,
+    #[cfg(feature = "manual-clock")]
+    manual_clock: Mutex<Option<ManualClock>>,
+    neighborhood_depth: Mutex<u8>,
+    chunk_store_capacity: Mutex<usize>,
+    on_chunk_stored: Mutex<Option<js_sys::Function>>,
+    on_handshake_error: Mutex<Option<js_sys::Function>>,
+    on_protocol_trace: Mutex<Option<js_sys::Function>>,
+    bootnode_address: Mutex<String>,
+    relay_addresses: Mutex<Vec<Multiaddr>>,
+    imported_peers: Mutex<HashMap<OverlayAddress, Multiaddr>>,
+    bootstrap_attempts: Mutex<u32>,
+    bootstrap_attempts_made: Mutex<u32>,
+    bootstrap_error: Mutex<Option<String>>,
+    dns_resolver: Mutex<String>,
+    max_established: Mutex<u32>,
+    max_pending: Mutex<u32>,
+    network_id: u64,
+    handshake_timeout_ms: Mutex<f64>,
+    handshake_protocols: Mutex<Vec<StreamProtocol>>,
+    connect_timeout_ms: Mutex<f64>,
+    ready_peers: Mutex<usize>,
+    rebootstrap: Mutex<Option<mpsc::Sender<Vec<u8>>>>,
+    shutdown: Mutex<bool>,
+    serving: Mutex<bool>,
+    full_node: Mutex<bool>,
+    external_addresses: Mutex<Vec<Multiaddr>>,
+    permissioned_serving: Mutex<bool>,
+    bytes_served: Mutex<u64>,
+    active_retrievals: Mutex<HashMap<u64, ActiveRetrieval>>,
+    next_retrieval_id: Mutex<u64>,
+    feed_watches: Mutex<HashMap<(String, String), FeedWatch>>,
+    next_feed_watch_id: Mutex<u64>,
+    connection_stats: Mutex<HashMap<PeerId, ConnectionStats>>,
+    behaviour_config: BehaviourConfig,
     message_port: (
-        mpsc::Sender<(Vec<u8>, mpsc::Sender<Vec<u8>>)>,
-        mpsc::Receiver<(Vec<u8>, mpsc::Sender<Vec<u8>>)>,
+        mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+        mpsc::Receiver<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
     ),
 }
 
 #[wasm_bindgen]
 pub struct Wings {
     connected_peers: Mutex<HashMap<PeerId, PeerFile>>,
-    overlay_peers: Mutex<HashMap<String, PeerId>>,
+    overlay_peers: Mutex<HashMap<OverlayAddress, PeerId>>,
     accounting_peers: Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
     ongoing_refreshments: Mutex<HashSet<PeerId>>,
+    chunk_store: Mutex<ChunkLru>,
+    leaf_cache: Mutex<ChunkLru>,
+    verified_peers: Mutex<HashMap<PeerId, VerifiedPeer>>,
 }
 
-#[wasm_bindgen]
-impl Sekirei {
-    pub async fn acquire(&self, address: String) -> Vec<u8> {
-        let (chan_out, chan_in) = mpsc::channel::<Vec<u8>>();
-        let valaddr_0 = hex::decode(&address);
-        let valaddr = match valaddr_0 {
-            Ok(hex) => hex,
-            _ => prt(address, "".to_string()).await,
-        };
+fn not_modified(etag: &str) -> Result<web_sys::Response, JsValue> {
+    let headers = web_sys::Headers::new()?;
+    headers.set("ETag", etag)?;
+    headers.set("Cache-Control", FETCH_CACHE_CONTROL)?;
 
-        let _ = self.message_port.0.send((valaddr, chan_out));
+    let init = web_sys::ResponseInit::new();
+    init.set_status(304);
+    init.set_headers(&headers);
 
-        // 3ab408eea4f095bde55c1caeeac8e7fcff49477660f0a28f652f0a6d9c60d05f
-        let k0 = async {
-            let mut timelast: f64;
-            #[allow(irrefutable_let_patterns)]
-            while let that = chan_in.try_recv() {
-                let timenow = Date::now();
-                timelast = timenow;
-                if !that.is_err() {
-                    return that.unwrap();
-                }
+    web_sys::Response::new_with_opt_str_and_init(None, &init)
+}
 
-                let timenow = Date::now();
-                let seg = timenow - timelast;
-                if seg < EVENT_LOOP_INTERRUPTOR {
-                    //                web_sys::console::log_1(&JsValue::from(format!(
-                    //                    "Ease event handle loop for {}",
-                    //                    EVENT_LOOP_INTERRUPTOR - seg
-                    //                )));
-                    async_std::task::sleep(Duration::from_millis(
-                        (EVENT_LOOP_INTERRUPTOR - seg) as u64,
-                    ))
-                    .await;
-                };
-            }
+// an address that is not hex, not a chunk reference or the zero address rejects with the
+// error name instead of resolving to empty data
 
-            return vec![];
-        };
+fn chunk_address(address: &str) -> Result<Vec<u8>, JsValue> {
+    match decode_address(address) {
+        Some(valaddr) if valid_chunk_address(&valaddr) => Ok(valaddr),
+        _ => Err(JsValue::from(format!(
+            "{:?}",
+            RetrievalError::InvalidAddress
+        ))),
+    }
+}
 
-        let result = k0.await;
+#[wasm_bindgen]
+impl Sekirei {
+    pub async fn acquire(&self, address: String) -> Result<Vec<u8>, JsValue> {
+        let valaddr = self.resolve_chunk_address(address).await?;
 
-        return result;
+        let scope = self.register_retrieval(&valaddr);
+        Ok(self.port_request(valaddr, 0, scope).await)
     }
 
-    pub fn new(_st: String) -> Sekirei {
-        // tracing_wasm::set_as_global_default(); // uncomment to turn on tracing
-        init_panic_hook();
+    pub async fn fetch_website(&self, address_or_name: String, path: String) -> JsValue {
+        let valaddr = self.resolve_address(address_or_name).await;
+        let (body, mime, encoding, status, etag, stamped) =
+            self.fetch_website_parts(valaddr, path).await;
 
-        let idle_duration = Duration::from_secs(60);
+        let response = js_sys::Object::new();
+        let _ = js_sys::Reflect::set(
+            &response,
+            &JsValue::from_str("body"),
+            &js_sys::Uint8Array::from(&body[..]),
+        );
+        let _ = js_sys::Reflect::set(&response, &JsValue::from_str("mime"), &JsValue::from(mime));
+        let _ = js_sys::Reflect::set(
+            &response,
+            &JsValue::from_str("encoding"),
+            &JsValue::from(encoding),
+        );
+        let _ = js_sys::Reflect::set(
+            &response,
+            &JsValue::from_str("status"),
+            &JsValue::from(status),
+        );
+        let _ = js_sys::Reflect::set(&response, &JsValue::from_str("etag"), &JsValue::from(etag));
+        let _ = js_sys::Reflect::set(
+            &response,
+            &JsValue::from_str("stamped"),
+            &JsValue::from(stamped),
+        );
 
-        // let body = Body::from_current_window()?;
-        // body.append_p(&format!("Attempt to establish connection over websocket"))?;
+        return response.into();
+    }
 
-        let secret_key_o = ecdsa::SecretKey::generate();
-        let secret_key = secret_key_o.clone();
-        let keypair: ecdsa::Keypair = secret_key_o.into();
+    pub async fn handle_fetch(
+        &self,
+        request_url: String,
+        if_none_match: Option<String>,
+    ) -> Result<web_sys::Response, JsValue> {
+        let (address_or_name, path) = match parse_bzz_url(&request_url) {
+            Some(parts) => parts,
+            None => {
+                let init = web_sys::ResponseInit::new();
+                init.set_status(400);
+                return web_sys::Response::new_with_opt_str_and_init(Some("bad request"), &init);
+            }
+        };
 
-        let swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone().into())
-            .with_wasm_bindgen()
-            .with_other_transport(|key| {
-                websocket_websys::Transport::default()
-                    .upgrade(core::upgrade::Version::V1)
-                    .authenticate(noise::Config::new(&key).unwrap())
-                    .multiplex(yamux::Config::default())
-                    .boxed()
-            })
-            .expect("Failed to create WebSocket transport")
-            .with_behaviour(|key| Behaviour::new(key.public()))
-            .unwrap()
-            .with_swarm_config(|_| {
-                libp2p::swarm::Config::with_wasm_executor()
-                    .with_idle_connection_timeout(idle_duration)
-                    .with_max_negotiating_inbound_streams(NonZero::new(10000_usize).unwrap().into())
-                    .with_per_connection_event_buffer_size(10000_usize)
-                    .with_notify_handler_buffer_size(NonZero::new(10000_usize).unwrap().into())
-            })
-            .build();
+        let valaddr = self.resolve_address(address_or_name).await;
+        let if_none_match = if_none_match.unwrap_or_default();
 
-        let connected_peers: Mutex<HashMap<PeerId, PeerFile>> = Mutex::new(HashMap::new());
-        let overlay_peers: Mutex<HashMap<String, PeerId>> = Mutex::new(HashMap::new());
-        let accounting_peers: Mutex<HashMap<PeerId, Mutex<PeerAccounting>>> =
-            Mutex::new(HashMap::new());
-        let ongoing_refreshments: Mutex<HashSet<PeerId>> = Mutex::new(HashSet::new());
+        // a tag naming the root itself can only belong to immutable content, so it is
+        // answered before anything is retrieved, feed tags need the feed resolved first
 
-        let (m_out, m_in) = mpsc::channel::<(Vec<u8>, mpsc::Sender<Vec<u8>>)>();
+        let root_etag = content_etag(&valaddr, &[]);
+        if etag_matches(&if_none_match, &root_etag) {
+            return not_modified(&root_etag);
+        }
 
-        return Sekirei {
-            secret_key: Mutex::new(secret_key),
-            swarm: Mutex::new(swarm),
-            wings: Mutex::new(Wings {
-                connected_peers: connected_peers,
-                overlay_peers: overlay_peers,
-                accounting_peers: accounting_peers,
-                ongoing_refreshments: ongoing_refreshments,
-            }),
-            message_port: (m_out, m_in),
-        };
-    }
+        let (mut body, mime, encoding, status, etag, _stamped) =
+            self.fetch_website_parts(valaddr, path).await;
 
-    pub async fn run(&self, _st: String) -> () {
-        init_panic_hook();
+        if status == 200 && etag_matches(&if_none_match, &etag) {
+            return not_modified(&etag);
+        }
 
-        prt("".to_string(), "".to_string()).await;
+        let headers = web_sys::Headers::new()?;
+        headers.set("Content-Type", &mime)?;
+        headers.set("Content-Length", &body.len().to_string())?;
+        // a constructed response is never decoded by the browser, so content left encoded is
+        // only labelled for the page to decode itself
+        if encoding.len() > 0 {
+            headers.set(UNDECODED_ENCODING_HEADER, &encoding)?;
+        }
+        if status == 200 {
+            headers.set("Cache-Control", FETCH_CACHE_CONTROL)?;
+            headers.set("ETag", &etag)?;
+        } else {
+            headers.set("Cache-Control", "no-store")?;
+        }
 
-        let wings = self.wings.lock().unwrap();
+        let init = web_sys::ResponseInit::new();
+        init.set_status(status);
+        init.set_headers(&headers);
 
-        let peer_id =
-            libp2p::PeerId::from_str("QmaniMaU5kNYzk7pQPWnBmB7Qp1o28FUW9cG4xVC4tGJbK").unwrap();
-        // libp2p::PeerId::from_str("QmPxeVPawnzvhsSaiZ1pphPYhWMroiPS3VdiDXRtzrbJXA").unwrap();
+        return web_sys::Response::new_with_opt_u8_array_and_init(Some(&mut body), &init);
+    }
 
-        let (peers_instructions_chan_outgoing, peers_instructions_chan_incoming) = mpsc::channel();
-        let (connections_instructions_chan_outgoing, connections_instructions_chan_incoming) =
-            mpsc::channel::<etiquette_2::BzzAddress>();
+    pub async fn acquire_tracked(
+        &self,
+        address: String,
+        on_id: js_sys::Function,
+    ) -> Result<Vec<u8>, JsValue> {
+        let valaddr = self.resolve_chunk_address(address).await?;
 
-        let (accounting_peer_chan_outgoing, accounting_peer_chan_incoming) = mpsc::channel();
+        let scope = self.register_retrieval(&valaddr);
+        let _ = on_id.call1(&JsValue::NULL, &JsValue::from(scope.id()));
+        Ok(self.port_request(valaddr, 0, scope).await)
+    }
 
-        let (pricing_chan_outgoing, pricing_chan_incoming) = mpsc::channel::<(PeerId, u64)>();
+    pub fn list_active(&self) -> JsValue {
+        let output = js_sys::Array::new();
+
+        let active_retrievals = self.active_retrievals.lock_recover();
+        let mut ids: Vec<&u64> = active_retrievals.keys().collect();
+        ids.sort();
+
+        for id in ids {
+            let active = &active_retrievals[id];
+            let entry = js_sys::Object::new();
+            let _ = js_sys::Reflect::set(&entry, &JsValue::from_str("id"), &JsValue::from(*id));
+            let _ = js_sys::Reflect::set(
+                &entry,
+                &JsValue::from_str("address"),
+                &JsValue::from(hex::encode(&active.address)),
+            );
+            let _ = js_sys::Reflect::set(
+                &entry,
+                &JsValue::from_str("started_at"),
+                &JsValue::from(active.started_at),
+            );
+            let _ = js_sys::Reflect::set(
+                &entry,
+                &JsValue::from_str("bytes"),
+                &JsValue::from(active.scope.bytes_retrieved()),
+            );
+            let _ = js_sys::Reflect::set(
+                &entry,
+                &JsValue::from_str("cancelled"),
+                &JsValue::from(active.scope.cancelled()),
+            );
+            output.push(&entry);
+        }
 
-        let (refreshment_instructions_chan_outgoing, refreshment_instructions_chan_incoming) =
-            mpsc::channel::<(PeerId, u64)>();
+        return output.into();
+    }
 
-        let (refreshment_chan_outgoing, refreshment_chan_incoming) =
-            mpsc::channel::<(PeerId, u64)>();
+    pub fn watch_feed(
+        &self,
+        owner: String,
+        topic: String,
+        interval_ms: f64,
+        callback: js_sys::Function,
+    ) -> u64 {
+        let owner = match normalize_feed_owner(&owner) {
+            Some(normalized) => normalized,
+            None => return 0,
+        };
 
-        let (data_retrieve_chan_outgoing, data_retrieve_chan_incoming) =
-            mpsc::channel::<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>)>();
+        let id = {
+            let mut next_feed_watch_id = self.next_feed_watch_id.lock_recover();
+            *next_feed_watch_id += 1;
+            *next_feed_watch_id
+        };
 
-        let mut ctrl;
-        let mut incoming_pricing_streams;
-        let mut incoming_gossip_streams;
+        // a watcher joining a feed that is already polled is served on the next round
+
+        let interval_ms = interval_ms.max(PROTO_LOOP_INTERRUPTOR);
+        let now = self.retrieval_config.lock_recover().clock.now_ms();
+        let mut feed_watches = self.feed_watches.lock_recover();
+        let watch = feed_watches
+            .entry((owner.clone(), topic.clone()))
+            .or_insert(FeedWatch {
+                owner,
+                topic,
+                interval_ms,
+                subscribers: HashMap::new(),
+                last_index: None,
+                next_poll: now,
+                idle_rounds: 0,
+            });
+        watch.interval_ms = watch.interval_ms.min(interval_ms);
+        watch.next_poll = now;
+        watch.subscribers.insert(id, (callback, None));
+
+        id
+    }
 
+    pub fn unwatch_feed(&self, id: u64) -> bool {
+        let mut feed_watches = self.feed_watches.lock_recover();
+        let key = match feed_watches
+            .iter()
+            .find(|(_, watch)| watch.subscribers.contains_key(&id))
         {
-            let mut swarm = self.swarm.lock().unwrap();
-            ctrl = swarm.behaviour_mut().stream.new_control();
+            Some((key, _)) => key.clone(),
+            None => return false,
+        };
 
-            incoming_pricing_streams = swarm
-                .behaviour_mut()
-                .stream
-                .new_control()
-                .accept(PRICING_PROTOCOL)
-                .unwrap();
+        // polling stops with the last subscriber
 
-            incoming_gossip_streams = swarm
-                .behaviour_mut()
-                .stream
-                .new_control()
-                .accept(GOSSIP_PROTOCOL)
-                .unwrap();
+        if let Some(watch) = feed_watches.get_mut(&key) {
+            watch.subscribers.remove(&id);
+            if watch.subscribers.is_empty() {
+                feed_watches.remove(&key);
+            }
         }
+        true
+    }
 
-        let mut ctrl3 = ctrl.clone();
-        let ctrl4 = ctrl.clone();
-        let ctrl6 = ctrl.clone();
+    // a recently checked cursor is trusted as is, an older one is reconciled by searching the
+    // feed from the stored index on, which picks up updates made by another writer
 
-        let pricing_inbound_handle = async move {
-            web_sys::console::log_1(&JsValue::from(format!("Opened Pricing handler 1")));
-            while let Some((peer, stream)) = incoming_pricing_streams.next().await {
-                web_sys::console::log_1(&JsValue::from(format!("Entered Pricing handler 1")));
-                pricing_handler(peer, stream, &pricing_chan_outgoing)
-                    .await
-                    .unwrap();
+    pub async fn next_feed_index(&self, owner: String, topic: String) -> Option<u64> {
+        let owner = normalize_feed_owner(&owner)?;
+
+        let cursor = load_feed_cursor(self.network_id, &owner, &topic).await;
+        if let Some((index, verified_at)) = cursor {
+            if self.now_ms() - verified_at < FEED_CURSOR_TTL_MS {
+                return index.checked_add(1);
             }
+        }
+
+        let start = cursor.map(|(index, _)| index).unwrap_or(0);
+        let scope = self.register_retrieval(&get_feed_address(&owner, &topic, start));
+        let found = self
+            .port_request(
+                encode_frame_fields(&vec![
+                    owner.clone().into_bytes(),
+                    topic.clone().into_bytes(),
+                    start.to_le_bytes().to_vec(),
+                ]),
+                7,
+                scope,
+            )
+            .await;
+
+        let latest = match (found.try_into().ok().map(u64::from_le_bytes), cursor) {
+            (Some(found), Some((index, _))) => Some(found.max(index)),
+            (Some(found), None) => Some(found),
+            (None, Some((index, _))) => Some(index),
+            (None, None) => None,
         };
 
-        let gossip_inbound_handle = async move {
-            web_sys::console::log_1(&JsValue::from(format!("Opened Gossip handler 1")));
-            while let Some((peer, stream)) = incoming_gossip_streams.next().await {
-                web_sys::console::log_1(&JsValue::from(format!("Entered Gossip handler 1")));
-                gossip_handler(peer, stream, &peers_instructions_chan_outgoing)
-                    .await
-                    .unwrap();
+        match latest {
+            Some(index) => {
+                store_feed_cursor(self.network_id, &owner, &topic, index, self.now_ms()).await;
+                index.checked_add(1)
             }
-        };
+            None => Some(0),
+        }
+    }
 
-        let conn_handle = async {
-            let addr2 =
-            "/ip4/192.168.0.101/tcp/18634/ws/p2p/QmaniMaU5kNYzk7pQPWnBmB7Qp1o28FUW9cG4xVC4tGJbK"
-                .parse::<Multiaddr>()
-                .unwrap();
+    // resolves to {type, mime, content} for the latest update, null when the feed has none
 
-            let mut bootnode_connected = false;
-            while bootnode_connected == false {
-                {
-                    let mut swarm = self.swarm.lock().unwrap();
-                    bootnode_connected = match swarm.dial(addr2.clone()) {
-                        Ok(()) => true,
-                        _ => false,
-                    };
-                }
-                async_std::task::sleep(Duration::from_millis((EVENT_LOOP_INTERRUPTOR) as u64))
-                    .await;
-            }
+    pub async fn fetch_feed_update(&self, owner: String, topic: String) -> JsValue {
+        let owner = match normalize_feed_owner(&owner) {
+            Some(owner) => owner,
+            None => return JsValue::NULL,
+        };
 
-            connection_handler(
-                peer_id,
-                &mut ctrl,
-                &addr2,
-                &self.secret_key.lock().unwrap(),
-                &accounting_peer_chan_outgoing,
+        let scope = self.register_retrieval(&get_feed_address(&owner, &topic, 0));
+        let update = self
+            .port_request(
+                encode_frame_fields(&vec![owner.into_bytes(), topic.into_bytes()]),
+                8,
+                scope,
             )
             .await;
-        };
 
-        let swarm_event_handle = async {
-            loop {
-                let mut swarm = self.swarm.lock().unwrap();
-                #[allow(irrefutable_let_patterns)]
-                while let paddr = peers_instructions_chan_incoming.try_recv() {
-                    if !paddr.is_err() {
-                        // web_sys::console::log_1(&JsValue::from(format!(
-                        //     "Current Conn Handled {:#?}",
-                        //     paddr
-                        // )));
-                        let addr4 =
-                            libp2p::core::Multiaddr::try_from(paddr.clone().unwrap().underlay)
-                                .unwrap();
-                        swarm.dial(addr4).unwrap_or(());
-                        let _ = connections_instructions_chan_outgoing.send(paddr.unwrap());
-                    } else {
-                        break;
-                    };
-                }
+        match update.split_first() {
+            Some((kind, content)) => {
+                let kind = FeedPayloadType::from_code(*kind).unwrap_or(FeedPayloadType::Raw);
+                feed_payload_object(&encode_feed_payload(kind, content))
+            }
+            None => JsValue::NULL,
+        }
+    }
 
-                let event = async_std::future::timeout(
-                    Duration::from_millis(EVENT_LOOP_INTERRUPTOR as u64),
-                    swarm.next(),
-                )
-                .await;
+    // publishers record every index they write so the next one needs no search
 
-                if !event.is_err() {
-                    // web_sys::console::log_1(&JsValue::from(format!(
-                    //     "Current Event Handled {:#?}",
-                    //     event
-                    // )));
-                    match event.unwrap() {
-                        Some(SwarmEvent::ConnectionEstablished {
-                            // peer_id,
-                            // established_in,
-                            ..
-                        }) => {
-                            //
-                        }
-                        Some(SwarmEvent::ConnectionClosed { peer_id, .. }) => {
-                            {
-                                let mut connected_peers_map = wings.connected_peers.lock().unwrap();
-                                let mut overlay_peers_map = wings.overlay_peers.lock().unwrap();
-                                if connected_peers_map.contains_key(&peer_id) {
-                                    let ol0 = hex::encode(connected_peers_map.get(&peer_id).unwrap().overlay.clone());
-                                    if overlay_peers_map.contains_key(&ol0) {
-                                        overlay_peers_map.remove(&ol0);
-                                    };
-                                    connected_peers_map.remove(&peer_id);
-                                };
-                            }
-                            let mut accounting = wings.accounting_peers.lock().unwrap();
-                            if accounting.contains_key(&peer_id) {
-                                accounting.remove(&peer_id);
-                            };
-                        }
-                        _ => {}
-                    }
-                }
+    pub async fn record_feed_index(&self, owner: String, topic: String, index: u64) -> bool {
+        match normalize_feed_owner(&owner) {
+            Some(owner) => {
+                store_feed_cursor(self.network_id, &owner, &topic, index, self.now_ms()).await
             }
-        };
+            None => false,
+        }
+    }
 
-        let event_handle = async {
-            let mut timelast = Date::now();
-            let mut interrupt_last = Date::now();
-            loop {
-                let k0 = async {
-                    #[allow(irrefutable_let_patterns)]
-                    while let that = connections_instructions_chan_incoming.try_recv() {
-                        if !that.is_err() {
-                            let addr3 =
-                                libp2p::core::Multiaddr::try_from(that.unwrap().underlay).unwrap();
-                            let id = try_from_multiaddr(&addr3);
-                            if id.is_some() {
-                                connection_handler(
-                                    id.expect("not"),
-                                    &mut ctrl3,
-                                    &addr3.clone(),
-                                    &self.secret_key.lock().unwrap(),
-                                    &accounting_peer_chan_outgoing,
-                                )
-                                .await;
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                };
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.active_retrievals.lock_recover().get(&id) {
+            Some(active) => {
+                active.scope.cancel();
+                true
+            }
+            None => false,
+        }
+    }
 
-                let k1 = async {
-                    #[allow(irrefutable_let_patterns)]
-                    while let incoming_peer = accounting_peer_chan_incoming.try_recv() {
-                        if !incoming_peer.is_err() {
-                            // Accounting connect
-                            let peer_file: PeerFile = incoming_peer.unwrap();
-                            let ol = hex::encode(peer_file.overlay.clone());
-                            {
-                                let mut accounting = wings.accounting_peers.lock().unwrap();
-                                if !accounting.contains_key(&peer_file.peer_id) {
-                                    web_sys::console::log_1(&JsValue::from(format!(
-                                        "Accounting Connecting Peer {:#?} {:#?}!",
-                                        ol, peer_file.peer_id
-                                    )));
-                                    accounting.insert(
-                                        peer_file.peer_id,
-                                        Mutex::new(PeerAccounting {
-                                            balance: 0,
-                                            threshold: 0,
-                                            reserve: 0,
-                                            refreshment: 0.0,
-                                            id: peer_file.peer_id,
-                                        }),
-                                    );
-                                }
-                            }
-                            {
-                                let mut overlay_peers_map = wings.overlay_peers.lock().unwrap();
-                                overlay_peers_map.insert(ol, peer_file.peer_id);
-                            }
-                            {
-                                let mut connected_peers_map = wings.connected_peers.lock().unwrap();
-                                connected_peers_map.insert(peer_file.peer_id, peer_file);
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                };
+    // rejects with the error name for an unusable address, so it is not mistaken for content
+    // that is entirely unavailable
 
-                let k2 = async {
-                    #[allow(irrefutable_let_patterns)]
-                    while let pt_in = pricing_chan_incoming.try_recv() {
-                        if !pt_in.is_err() {
-                            let (peer, amount) = pt_in.unwrap();
-                            let accounting = wings.accounting_peers.lock().unwrap();
-                            let accounting_peer = accounting.get(&peer).unwrap();
-                            set_payment_threshold(accounting_peer, amount);
-                        } else {
-                            break;
-                        }
-                    }
-                };
+    pub async fn check_availability(
+        &self,
+        address: String,
+        sample_rate: f32,
+    ) -> Result<f32, JsValue> {
+        let valaddr = chunk_address(&address)?;
 
-                let k3 = async {
-                    let mut refresh_joiner = Vec::new();
+        let scope = self.register_retrieval(&valaddr);
+        let payload = [valaddr, sample_rate.to_le_bytes().to_vec()].concat();
+        let result = self.port_request(payload, 1, scope).await;
 
-                    #[allow(irrefutable_let_patterns)]
-                    while let re_out = refreshment_instructions_chan_incoming.try_recv() {
-                        if !re_out.is_err() {
-                            web_sys::console::log_1(&JsValue::from(format!("Refresh attempt")));
-                            let (peer, amount) = re_out.unwrap();
-                            {
-                                let map = wings.ongoing_refreshments.lock().unwrap();
-                                if map.contains(&peer) {
-                                    continue;
-                                }
-                            }
-                            #[allow(unused_assignments)]
-                            let mut daten = Date::now();
-                            let datenow = Date::now();
-                            {
-                                let accounting = wings.accounting_peers.lock().unwrap();
-                                let accounting_peer_lock = accounting.get(&peer).unwrap();
-                                let mut accounting_peer = accounting_peer_lock.lock().unwrap();
-                                daten = accounting_peer.refreshment;
-                                if datenow > accounting_peer.refreshment + 1000.0 {
-                                    accounting_peer.refreshment = datenow;
-                                }
-                            }
-                            if datenow > daten + 1000.0 {
-                                {
-                                    let mut map = wings.ongoing_refreshments.lock().unwrap();
-                                    map.insert(peer);
-                                }
-                                let mut ctrl7 = ctrl4.clone();
-                                let rco = refreshment_chan_outgoing.clone();
-                                let handle = async move {
-                                    refresh_handler(peer, amount, &mut ctrl7, &rco).await;
-                                };
-                                refresh_joiner.push(handle);
-                            }
-                        } else {
-                            break;
-                        }
-                    }
+        Ok(f32::from_le_bytes(result.try_into().unwrap_or([0; 4])))
+    }
 
-                    join_all(refresh_joiner).await;
-                };
+    pub async fn estimate_cost(&self, address: String) -> Result<u64, JsValue> {
+        let valaddr = chunk_address(&address)?;
 
-                let k4 = async {
-                    #[allow(irrefutable_let_patterns)]
-                    while let re_in = refreshment_chan_incoming.try_recv() {
-                        if !re_in.is_err() {
-                            let (peer, amount) = re_in.unwrap();
-                            {
-                                let accounting = wings.accounting_peers.lock().unwrap();
-                                let accounting_peer = accounting.get(&peer).unwrap();
-                                apply_refreshment(accounting_peer, amount);
-                            }
-                            let mut map = wings.ongoing_refreshments.lock().unwrap();
-                            if map.contains(&peer) {
-                                map.remove(&peer);
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                };
+        let scope = self.register_retrieval(&valaddr);
+        let result = self.port_request(valaddr, 3, scope).await;
 
-                join!(k0, k1, k2, k3, k4);
+        Ok(u64::from_le_bytes(result.try_into().unwrap_or([0; 8])))
+    }
 
-                let timenow = Date::now();
-                let seg = timenow - interrupt_last;
-                if seg < EVENT_LOOP_INTERRUPTOR {
+    // addresses under a root as [{depth, address}], leaves are listed without being retrieved so
+    // the list shows which chunks to look for when a retrieval fails
+
+    pub async fn chunk_tree(&self, address: String) -> Result<JsValue, JsValue> {
+        let valaddr = chunk_address(&address)?;
+
+        // the intermediate chunks under an encrypted root can not be read without decrypting them
+
+        if valaddr.len() != REFERENCE_SIZE {
+            return Err(JsValue::from(format!(
+                "{:?}",
+                RetrievalError::InvalidAddress
+            )));
+        }
+
+        let scope = self.register_retrieval(&valaddr);
+        let result = self.port_request(valaddr, 12, scope).await;
+
+        let entries: Vec<serde_json::Value> = decode_frame_fields(&result)
+            .iter()
+            .filter_map(|field| field.split_first())
+            .map(|(depth, address)| {
+                serde_json::json!({ "depth": depth, "address": hex::encode(address) })
+            })
+            .collect();
+        js_sys::JSON::parse(&serde_json::Value::from(entries).to_string())
+    }
+
+    pub async fn export_accounting(&self) -> JsValue {
+        let result = self
+            .port_request(vec![], 5, RetrievalScope::new(0, 0))
+            .await;
+
+        let snapshot = String::from_utf8(result).unwrap_or_default();
+        match js_sys::JSON::parse(&snapshot) {
+            Ok(value) => value,
+            _ => JsValue::NULL,
+        }
+    }
+
+    // snapshot entries are [{overlay, underlay}], they stay unverified until a request near
+    // them dials them and the handshake completes
+
+    pub fn import_peers(&self, snapshot: JsValue) -> u32 {
+        let text: String = match js_sys::JSON::stringify(&snapshot) {
+            Ok(text) => text.into(),
+            _ => return 0,
+        };
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap_or_default();
+
+        let mut imported_peers = self.imported_peers.lock_recover();
+        let mut imported = 0;
+        for entry in entries.iter() {
+            let overlay = entry["overlay"]
+                .as_str()
+                .and_then(|overlay| overlay.parse::<OverlayAddress>().ok());
+            let underlay = entry["underlay"]
+                .as_str()
+                .and_then(|underlay| underlay.parse::<Multiaddr>().ok())
+                .filter(|underlay| try_from_multiaddr(underlay).is_some());
+            if let (Some(overlay), Some(underlay)) = (overlay, underlay) {
+                imported_peers.insert(overlay, underlay);
+                imported += 1;
+            }
+        }
+
+        imported
+    }
+
+    pub async fn export_peers(&self) -> JsValue {
+        let result = self
+            .port_request(vec![], 9, RetrievalScope::new(0, 0))
+            .await;
+
+        let snapshot = String::from_utf8(result).unwrap_or_default();
+        match js_sys::JSON::parse(&snapshot) {
+            Ok(value) => value,
+            _ => JsValue::NULL,
+        }
+    }
+
+    pub async fn acquire_many(&self, addresses: Vec<String>) -> js_sys::Array {
+        let output = js_sys::Array::new();
+
+        let valaddrs: Vec<Vec<u8>> = addresses
+            .iter()
+            .map(|address| decode_address(address).unwrap_or_default())
+            .collect();
+
+        let scope = self.register_retrieval(&valaddrs.concat());
+        let result = self
+            .port_request(encode_frame_fields(&valaddrs), 4, scope)
+            .await;
+
+        // each entry is the retrieved data or the name of the error
+
+        for field in decode_frame_fields(&result) {
+            if field.len() > 0 && field[0] == 0 {
+                output.push(&js_sys::Uint8Array::from(&field[1..]));
+                continue;
+            }
+            let error = match field
+                .get(0)
+                .and_then(|code| RetrievalError::from_code(*code))
+            {
+                Some(e) => format!("{:?}", e),
+                None => "Unknown".to_string(),
+            };
+            output.push(&JsValue::from(error));
+        }
+
+        return output;
+    }
+
+    // asks exactly this peer, for checking who serves a chunk, rejects with the error name
+
+    pub async fn retrieve_chunk_from(
+        &self,
+        peer_id: String,
+        address: String,
+    ) -> Result<Vec<u8>, JsValue> {
+        let peer = match peer_id.parse::<PeerId>() {
+            Ok(peer) => peer,
+            Err(_) => return Err(JsValue::from(format!("{:?}", RetrievalError::UnknownPeer))),
+        };
+        let valaddr = chunk_address(&address)?;
+
+        let scope = self.register_retrieval(&valaddr);
+        let result = self
+            .port_request(
+                encode_frame_fields(&vec![peer.to_bytes(), valaddr]),
+                10,
+                scope,
+            )
+            .await;
+
+        match result.split_first() {
+            Some((0, chunk)) => Ok(chunk.to_vec()),
+            Some((code, _)) => Err(JsValue::from(
+                RetrievalError::from_code(*code)
+                    .map(|e| format!("{:?}", e))
+                    .unwrap_or("Unknown".to_string()),
+            )),
+            None => Err(JsValue::from(format!("{:?}", RetrievalError::NotFound))),
+        }
+    }
+
+    // resolves to {kind, data}, a single owner chunk also carries its owner and id and has its
+    // wrapper stripped from the data, rejects with the error name
+
+    pub async fn retrieve_chunk_typed(&self, address: String) -> Result<JsValue, JsValue> {
+        let valaddr = chunk_address(&address)?;
+
+        let scope = self.register_retrieval(&valaddr);
+        let result = self.port_request(valaddr, 13, scope).await;
+
+        let fields = match result.split_first() {
+            Some((0, typed)) => decode_frame_fields(typed),
+            Some((code, _)) => {
+                return Err(JsValue::from(
+                    RetrievalError::from_code(*code)
+                        .map(|e| format!("{:?}", e))
+                        .unwrap_or("Unknown".to_string()),
+                ))
+            }
+            None => return Err(JsValue::from(format!("{:?}", RetrievalError::NotFound))),
+        };
+
+        let typed = js_sys::Object::new();
+        let kind = match fields.as_slice() {
+            [data] => {
+                let _ = js_sys::Reflect::set(
+                    &typed,
+                    &JsValue::from_str("data"),
+                    &js_sys::Uint8Array::from(&data[..]),
+                );
+                "cac"
+            }
+            [data, owner, id] => {
+                let _ = js_sys::Reflect::set(
+                    &typed,
+                    &JsValue::from_str("data"),
+                    &js_sys::Uint8Array::from(&data[..]),
+                );
+                let _ = js_sys::Reflect::set(
+                    &typed,
+                    &JsValue::from_str("owner"),
+                    &JsValue::from(hex::encode(owner)),
+                );
+                let _ = js_sys::Reflect::set(
+                    &typed,
+                    &JsValue::from_str("id"),
+                    &JsValue::from(hex::encode(id)),
+                );
+                "soc"
+            }
+            _ => return Err(JsValue::from(format!("{:?}", RetrievalError::NotFound))),
+        };
+        let _ = js_sys::Reflect::set(&typed, &JsValue::from_str("kind"), &JsValue::from(kind));
+
+        Ok(typed.into())
+    }
+
+    pub async fn acquire_stream(
+        &self,
+        address: String,
+        sink: js_sys::Function,
+    ) -> Result<bool, JsValue> {
+        let valaddr = chunk_address(&address)?;
+
+        let (chan_out, chan_in) = mpsc::channel::<Vec<u8>>();
+
+        let scope = self.register_retrieval(&valaddr);
+        let id = scope.id();
+        let _ = self.message_port.0.send((valaddr, 2, chan_out, scope));
+
+        let streamed = 'stream: loop {
+            #[allow(irrefutable_let_patterns)]
+            while let that = chan_in.try_recv() {
+                match that {
+                    Ok(segment) => {
+                        if segment.len() == 0 || segment[0] == STREAM_FAILED {
+                            break 'stream false;
+                        }
+                        if segment[0] == STREAM_END {
+                            break 'stream true;
+                        }
+                        let _ =
+                            sink.call1(&JsValue::NULL, &js_sys::Uint8Array::from(&segment[1..]));
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => break 'stream false,
+                }
+            }
+
+            async_std::task::sleep(Duration::from_millis(EVENT_LOOP_INTERRUPTOR as u64)).await;
+        };
+
+        self.unregister_retrieval(id);
+
+        Ok(streamed)
+    }
+
+    // resolves with the number of handshaken peers once there is at least one, rejects when
+    // none completes within the timeout
+
+    pub async fn wait_until_ready(&self, timeout_ms: f64) -> Result<u32, JsValue> {
+        let started = self.now_ms();
+        loop {
+            let ready_peers = *self.ready_peers.lock_recover();
+            if ready_peers > 0 {
+                return Ok(ready_peers as u32);
+            }
+            if self.now_ms() - started >= timeout_ms {
+                return Err(JsValue::from(format!(
+                    "no peer handshaken within {}ms",
+                    timeout_ms
+                )));
+            }
+            async_std::task::sleep(Duration::from_millis(EVENT_LOOP_INTERRUPTOR as u64)).await;
+        }
+    }
+
+    // drops every peer, balance and cached chunk while the swarm and identity stay, in-flight
+    // retrievals are cancelled and the bootnode is dialed again, false when that bootstrap
+    // failed, the node keeps running either way
+
+    pub async fn reset(&self) -> bool {
+        for active in self.active_retrievals.lock_recover().values() {
+            active.scope.cancel();
+        }
+        self.imported_peers.lock_recover().clear();
+        self.retrieval_config.lock_recover().manifest_nodes.clear();
+
+        let result = self
+            .port_request(vec![], 11, RetrievalScope::new(0, 0))
+            .await;
+
+        result == vec![1]
+    }
+
+    pub fn set_neighborhood_depth(&self, depth: u8) {
+        let mut neighborhood_depth = self.neighborhood_depth.lock_recover();
+        *neighborhood_depth = depth.min(MAX_PO);
+    }
+
+    // pushed chunks past this many evict the least recently used ones, a lower capacity is
+    // reached as the next chunks arrive
+
+    pub fn set_chunk_store_capacity(&self, capacity: usize) {
+        *self.chunk_store_capacity.lock_recover() = capacity;
+    }
+
+    pub fn set_on_chunk_stored(&self, callback: js_sys::Function) {
+        let mut on_chunk_stored = self.on_chunk_stored.lock_recover();
+        *on_chunk_stored = Some(callback);
+    }
+
+    pub fn set_on_handshake_error(&self, callback: js_sys::Function) {
+        let mut on_handshake_error = self.on_handshake_error.lock_recover();
+        *on_handshake_error = Some(callback);
+    }
+
+    // passing no callback turns the trace off again
+
+    pub fn set_protocol_trace(&self, callback: Option<js_sys::Function>) {
+        let mut on_protocol_trace = self.on_protocol_trace.lock_recover();
+        *on_protocol_trace = callback;
+    }
+
+    pub fn network_id(&self) -> u64 {
+        self.network_id
+    }
+
+    pub fn set_handshake_timeout(&self, timeout_ms: f64) {
+        let mut handshake_timeout_ms = self.handshake_timeout_ms.lock_recover();
+        *handshake_timeout_ms = timeout_ms.max(0.0);
+    }
+
+    // handshake protocol strings tried in order against every peer, rejected as a whole when
+    // one is not a valid protocol name, an empty list restores the default version
+
+    pub fn set_handshake_protocols(&self, protocols: Vec<String>) -> bool {
+        let mut parsed = vec![];
+        for protocol in protocols {
+            match StreamProtocol::try_from_owned(protocol) {
+                Ok(protocol) => parsed.push(protocol),
+                Err(_) => return false,
+            }
+        }
+        if parsed.is_empty() {
+            parsed.push(HANDSHAKE_PROTOCOL);
+        }
+
+        *self.handshake_protocols.lock_recover() = parsed;
+        true
+    }
+
+    pub fn set_connect_timeout(&self, timeout_ms: f64) {
+        let mut connect_timeout_ms = self.connect_timeout_ms.lock_recover();
+        *connect_timeout_ms = timeout_ms.max(0.0);
+    }
+
+    pub fn set_retry_budget(&self, budget: u64) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.retry_budget = budget;
+    }
+
+    pub fn set_verify_root(&self, verify_root: bool) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.verify_root = verify_root;
+    }
+
+    pub fn set_proximity_clamp(&self, floor: u8, after_rounds: u32) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.proximity_floor = floor;
+        config.clamp_after_rounds = after_rounds;
+    }
+
+    pub fn set_forwarding(&self, max_hops: u8, direct_timeout_ms: f64, hop_timeout_ms: f64) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.max_forward_hops = max_hops;
+        config.direct_timeout_ms = direct_timeout_ms.max(0.0);
+        config.forward_hop_timeout_ms = hop_timeout_ms.max(0.0);
+    }
+
+    pub fn set_selection_strategy(&self, strategy: u8) -> bool {
+        match SelectionStrategy::from_code(strategy) {
+            Some(strategy) => {
+                self.retrieval_config.lock_recover().strategy = strategy;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_max_bytes_per_sec(&self, rate: u64) {
+        let config = self.retrieval_config.lock_recover();
+        config.bandwidth.set_max_bytes_per_sec(rate);
+    }
+
+    pub fn bandwidth(&self) -> JsValue {
+        let bandwidth = self.retrieval_config.lock_recover().bandwidth.clone();
+        let metrics = serde_json::json!({
+            "bytes_in": bandwidth.bytes_in(),
+            "bytes_out": bandwidth.bytes_out(),
+            "max_bytes_per_sec": bandwidth.max_bytes_per_sec(),
+        });
+        match js_sys::JSON::parse(&metrics.to_string()) {
+            Ok(value) => value,
+            _ => JsValue::NULL,
+        }
+    }
+
+    // chunks are served to other peers only while enabled, off by default
+
+    pub fn enable_serving(&self, enabled: bool) {
+        *self.serving.lock_recover() = enabled;
+    }
+
+    // open by default, permissioned answers only requests signed by a handshaken peer
+
+    pub fn set_permissioned_serving(&self, permissioned: bool) {
+        *self.permissioned_serving.lock_recover() = permissioned;
+    }
+
+    // signs outgoing retrieval requests with the node identity for permissioned servers
+
+    pub fn set_request_signing(&self, enabled: bool) {
+        let key = match enabled {
+            true => Some(self.secret_key.lock_recover().clone()),
+            false => None,
+        };
+        self.retrieval_config.lock_recover().request_key = key;
+    }
+
+    // a full node advertises a confirmed external address in every handshake ack
+
+    pub fn set_full_node(&self, full_node: bool) {
+        *self.full_node.lock_recover() = full_node;
+    }
+
+    pub fn bytes_served(&self) -> u64 {
+        *self.bytes_served.lock_recover()
+    }
+
+    // totals over every peer, per peer volumes are part of export_accounting
+
+    pub fn refresh_stats(&self) -> JsValue {
+        let refresh = self.retrieval_config.lock_recover().refresh.clone();
+        let (asked, granted, refreshes, throttled) = refresh.stats();
+        let (max_amount, window_ms) = refresh.cap();
+        let metrics = serde_json::json!({
+            "asked": asked,
+            "granted": granted,
+            "refreshes": refreshes,
+            "throttled": throttled,
+            "max_per_window": max_amount,
+            "window_ms": window_ms,
+        });
+        match js_sys::JSON::parse(&metrics.to_string()) {
+            Ok(value) => value,
+            _ => JsValue::NULL,
+        }
+    }
+
+    // zero lifts the cap
+
+    pub fn set_refresh_cap(&self, max_amount: u64, window_ms: f64) {
+        let config = self.retrieval_config.lock_recover();
+        config.refresh.set_cap(max_amount, window_ms);
+    }
+
+    // round trips are measured by ping, throughput is only known for the client as a whole
+
+    pub fn connection_stats(&self) -> JsValue {
+        let now = self.now_ms();
+        let connections: Vec<serde_json::Value> = self
+            .connection_stats
+            .lock_recover()
+            .iter()
+            .map(|(peer_id, stats)| {
+                serde_json::json!({
+                    "peer_id": peer_id.to_string(),
+                    "rtt_ms": stats.rtt_ms,
+                    "connected_ms": now - stats.established_at,
+                    "last_ping_ms_ago": stats.rtt_ms.map(|_| now - stats.last_ping),
+                })
+            })
+            .collect();
+
+        let bandwidth = self.retrieval_config.lock_recover().bandwidth.clone();
+        let stats = serde_json::json!({
+            "connections": connections,
+            "bytes_in": bandwidth.bytes_in(),
+            "bytes_out": bandwidth.bytes_out(),
+        });
+        match js_sys::JSON::parse(&stats.to_string()) {
+            Ok(value) => value,
+            _ => JsValue::NULL,
+        }
+    }
+
+    pub fn set_latency_weight(&self, weight: f64) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.latency_weight = weight.max(0.0);
+    }
+
+    pub fn track_batch(&self, batch_id: String, owner: String, depth: u8) -> bool {
+        let batch_id = match hex::decode(batch_id.trim_start_matches("0x")) {
+            Ok(batch_id) if batch_id.len() == 32 => batch_id,
+            _ => return false,
+        };
+        let owner = match normalize_feed_owner(&owner) {
+            Some(owner) => hex::decode(owner).unwrap_or_default(),
+            None => return false,
+        };
+
+        let config = self.retrieval_config.lock_recover();
+        config.batches.track(batch_id, owner, depth);
+        true
+    }
+
+    pub fn forget_batch(&self, batch_id: String) -> bool {
+        let batch_id = match hex::decode(batch_id.trim_start_matches("0x")) {
+            Ok(batch_id) => batch_id,
+            _ => return false,
+        };
+
+        let config = self.retrieval_config.lock_recover();
+        config.batches.forget(&batch_id)
+    }
+
+    // the whole data tree of the root is retrieved and every chunk kept until unpinned,
+    // on_progress is called with the number of chunks stored so far
+
+    pub async fn pin(&self, address: String, on_progress: Option<js_sys::Function>) -> bool {
+        let root = match decode_address(&address) {
+            Some(root) if root.len() == 32 || root.len() == 64 => root,
+            _ => return false,
+        };
+
+        let pins = self.retrieval_config.lock_recover().pins.clone();
+        let held_before = pins.held(&root);
+
+        let scope = self.register_retrieval(&root).with_pin_root(root.clone());
+        let done = std::cell::Cell::new(false);
+
+        let k0 = async {
+            let result = self
+                .port_request(encode_frame_fields(&vec![root.clone()]), 4, scope.clone())
+                .await;
+            done.set(true);
+            result
+        };
+
+        let k1 = async {
+            let mut reported = 0;
+            while !done.get() {
+                async_std::task::sleep(Duration::from_millis(EVENT_LOOP_INTERRUPTOR as u64)).await;
+                let pinned = scope.pinned_chunks();
+                if pinned != reported {
+                    reported = pinned;
+                    if let Some(callback) = on_progress.as_ref() {
+                        let _ = callback.call1(&JsValue::NULL, &JsValue::from(pinned));
+                    }
+                }
+            }
+        };
+
+        let (result, _) = join!(k0, k1);
+
+        let pinned = matches!(decode_frame_fields(&result).first(), Some(field) if field.first() == Some(&0));
+
+        if !pinned {
+            pins.roll_back(self.network_id, &root, held_before);
+            return false;
+        }
+
+        // the pin holds for this session even where the cache storage is unavailable
+
+        if !pins.persist(self.network_id, &root).await {
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Pin of {} is not kept past this session",
+                address
+            )));
+        }
+
+        true
+    }
+
+    pub fn unpin(&self, address: String) -> bool {
+        let root = match decode_address(&address) {
+            Some(root) => root,
+            _ => return false,
+        };
+
+        let pins = self.retrieval_config.lock_recover().pins.clone();
+        pins.unpin(self.network_id, &root)
+    }
+
+    pub fn list_pins(&self) -> JsValue {
+        let pins = self.retrieval_config.lock_recover().pins.clone();
+        let roots: Vec<String> = pins.roots().iter().map(hex::encode).collect();
+        match js_sys::JSON::parse(&serde_json::json!(roots).to_string()) {
+            Ok(value) => value,
+            _ => JsValue::NULL,
+        }
+    }
+
+    pub fn set_require_stamp(&self, require: bool) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.require_stamp = require;
+    }
+
+    pub fn set_min_peers(&self, min_peers: usize, timeout_ms: f64) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.min_peers = min_peers;
+        config.min_peers_timeout_ms = timeout_ms.max(0.0);
+    }
+
+    pub fn set_chunk_validator(&self, validator: Option<js_sys::Function>) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.validator = validator;
+    }
+
+    pub fn set_gateway(&self, url: Option<String>) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.gateway = url.filter(|url| !url.is_empty());
+    }
+
+    // wide and sparse finds the head of a long feed in fewer rounds, narrow and dense costs
+    // fewer probes on a short one
+
+    pub fn set_feed_search(&self, initial_width: u64, probe_density: u64) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.feed_search = FeedSearch::new(initial_width, probe_density);
+    }
+
+    pub fn set_max_concurrent_streams(&self, cap: u64) {
+        self.retrieval_config.lock_recover().streams.set_cap(cap);
+    }
+
+    pub fn set_max_join_fanout(&self, cap: usize) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.max_join_fanout = cap;
+    }
+
+    pub fn set_max_in_flight_per_peer(&self, cap: u32) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.max_in_flight_per_peer = cap;
+    }
+
+    pub fn set_score_weight(&self, weight: f64) {
+        let mut config = self.retrieval_config.lock_recover();
+        config.score_weight = weight.max(0.0);
+    }
+
+    pub fn set_bootnode(&self, address: String) {
+        let mut bootnode_address = self.bootnode_address.lock_recover();
+        *bootnode_address = address;
+    }
+
+    // relays are dialed when run starts and tried for peers that can not be dialed directly
+
+    pub fn add_relay(&self, address: String) -> bool {
+        match address.parse::<Multiaddr>() {
+            Ok(relay) if try_from_multiaddr(&relay).is_some() => {
+                self.relay_addresses.lock_recover().push(relay);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn clear_relays(&self) {
+        self.relay_addresses.lock_recover().clear();
+    }
+
+    // dial and handshake with the bootnode are tried this many times before run gives up
+
+    pub fn set_bootstrap_attempts(&self, attempts: u32) {
+        let mut bootstrap_attempts = self.bootstrap_attempts.lock_recover();
+        *bootstrap_attempts = attempts.max(1);
+    }
+
+    pub fn bootstrap_attempts_made(&self) -> u32 {
+        *self.bootstrap_attempts_made.lock_recover()
+    }
+
+    pub fn last_bootstrap_error(&self) -> Option<String> {
+        self.bootstrap_error.lock_recover().clone()
+    }
+
+    // stops every loop of a running client, the promise of run then resolves
+
+    pub fn shutdown(&self) {
+        *self.shutdown.lock_recover() = true;
+    }
+
+    pub fn set_dns_resolver(&self, url: String) {
+        let mut dns_resolver = self.dns_resolver.lock_recover();
+        *dns_resolver = url;
+    }
+
+    pub fn new(_st: String) -> Sekirei {
+        // tracing_wasm::set_as_global_default(); // uncomment to turn on tracing
+        init_panic_hook();
+
+        Sekirei::from_secret_key(
+            ecdsa::SecretKey::generate(),
+            IDENTIFY_PROTOCOL_VERSION.to_string(),
+            IDENTIFY_AGENT_VERSION.to_string(),
+            BehaviourConfig::default(),
+        )
+    }
+
+    pub fn new_with_identify(
+        _st: String,
+        protocol_version: String,
+        agent_version: String,
+    ) -> Sekirei {
+        init_panic_hook();
+
+        Sekirei::from_secret_key(
+            ecdsa::SecretKey::generate(),
+            protocol_version,
+            agent_version,
+            BehaviourConfig::default(),
+        )
+    }
+
+    // rejects when the bootstrap fails, a client that joined the swarm keeps running until
+    // shutdown is called and the promise then resolves
+
+    pub async fn run(
+        &self,
+        _st: String,
+        max_established: Option<u32>,
+        max_pending: Option<u32>,
+        relays: Option<Vec<String>>,
+    ) -> Result<(), JsValue> {
+        init_panic_hook();
+
+        if !is_supported() {
+            web_sys::console::log_1(&JsValue::from(
+                "WebSocket is not available in this context, the client can not start",
+            ));
+            return Err(JsValue::from("WebSocket is not available"));
+        }
+
+        // a limit left out or zero keeps the configured one, so run("") still starts the client
+
+        {
+            let max_established = match max_established {
+                None | Some(0) => *self.max_established.lock_recover(),
+                Some(x) => x,
+            };
+            let max_pending = match max_pending {
+                None | Some(0) => *self.max_pending.lock_recover(),
+                Some(x) => x,
+            };
+            *self.max_established.lock_recover() = max_established;
+            *self.max_pending.lock_recover() = max_pending;
+            let mut swarm = self.swarm.lock_recover();
+            *swarm.behaviour_mut().limits.limits_mut() =
+                limits_config(max_established, max_pending);
+        }
+
+        // relays given to run are added to those of the builder and add_relay
+
+        for relay in relays.unwrap_or_default() {
+            if !self.add_relay(relay.clone()) {
+                web_sys::console::log_1(&JsValue::from(format!("Invalid relay address {}", relay)));
+            }
+        }
+
+        prt("".to_string(), "".to_string()).await;
+
+        // leaves stored by earlier sessions are trimmed to what this one may keep in memory
+
+        let leaf_cache_capacity = self.retrieval_config.lock_recover().leaf_cache_capacity;
+        let leaf_store = network_cache(LEAF_CHUNK_CACHE, self.network_id);
+        prune_chunks(&leaf_store, leaf_cache_capacity).await;
+
+        let pins = self.retrieval_config.lock_recover().pins.clone();
+        pins.restore(self.network_id).await;
+
+        let wings = self.wings.lock_recover();
+
+        let peer_id =
+            libp2p::PeerId::from_str("QmaniMaU5kNYzk7pQPWnBmB7Qp1o28FUW9cG4xVC4tGJbK").unwrap();
+        // libp2p::PeerId::from_str("QmPxeVPawnzvhsSaiZ1pphPYhWMroiPS3VdiDXRtzrbJXA").unwrap();
+
+        let (peers_instructions_chan_outgoing, peers_instructions_chan_incoming) = mpsc::channel();
+        let (connections_instructions_chan_outgoing, connections_instructions_chan_incoming) =
+            mpsc::channel::<etiquette_2::BzzAddress>();
+
+        let (accounting_peer_chan_outgoing, accounting_peer_chan_incoming) = mpsc::channel();
+
+        let (pricing_chan_outgoing, pricing_chan_incoming) = mpsc::channel::<(PeerId, u64)>();
+
+        let (refreshment_instructions_chan_outgoing, refreshment_instructions_chan_incoming) =
+            mpsc::channel::<(PeerId, u64)>();
+
+        let (refreshment_chan_outgoing, refreshment_chan_incoming) =
+            mpsc::channel::<(PeerId, u64)>();
+
+        let (data_retrieve_chan_outgoing, data_retrieve_chan_incoming) =
+            mpsc::channel::<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>();
+
+        let (pushed_chunk_chan_outgoing, pushed_chunk_chan_incoming) =
+            mpsc::channel::<(Vec<u8>, Vec<u8>)>();
+
+        let secret_key = self.secret_key.lock_recover().clone();
+        let overlay = own_overlay(&secret_key, self.network_id);
+
+        let ctrl;
+        let mut incoming_pricing_streams = None;
+        let mut incoming_gossip_streams = None;
+        let mut incoming_pushsync_streams = None;
+        let incoming_retrieval_streams;
+        let incoming_handshake_streams;
+
+        // only the stream protocols enabled in the behaviour config are accepted
+
+        let handshake_protocols = self.handshake_protocols.lock_recover().clone();
+        {
+            let mut swarm = self.swarm.lock_recover();
+            ctrl = swarm.behaviour_mut().stream.new_control();
+
+            // peers dialing us handshake on any of the versions we speak
+
+            incoming_handshake_streams = libp2p::futures::stream::select_all(
+                handshake_protocols.iter().filter_map(|protocol| {
+                    swarm
+                        .behaviour_mut()
+                        .stream
+                        .new_control()
+                        .accept(protocol.clone())
+                        .ok()
+                }),
+            );
+
+            // retrieval requests are always accepted, enable_serving decides if they are answered
+
+            incoming_retrieval_streams = swarm
+                .behaviour_mut()
+                .stream
+                .new_control()
+                .accept(RETRIEVAL_PROTOCOL)
+                .unwrap();
+
+            if self.behaviour_config.pricing {
+                incoming_pricing_streams = Some(
+                    swarm
+                        .behaviour_mut()
+                        .stream
+                        .new_control()
+                        .accept(PRICING_PROTOCOL)
+                        .unwrap(),
+                );
+            }
+
+            if self.behaviour_config.gossip {
+                incoming_gossip_streams = Some(
+                    swarm
+                        .behaviour_mut()
+                        .stream
+                        .new_control()
+                        .accept(GOSSIP_PROTOCOL)
+                        .unwrap(),
+                );
+            }
+
+            if self.behaviour_config.pushsync {
+                incoming_pushsync_streams = Some(
+                    swarm
+                        .behaviour_mut()
+                        .stream
+                        .new_control()
+                        .accept(PUSHSYNC_PROTOCOL)
+                        .unwrap(),
+                );
+            }
+        }
+
+        let mut ctrl3 = ctrl.clone();
+        let ctrl4 = ctrl.clone();
+        let ctrl6 = ctrl.clone();
+
+        let pricing_inbound_handle = async move {
+            let mut incoming_pricing_streams = match incoming_pricing_streams {
+                Some(incoming) => incoming,
+                None => return,
+            };
+            web_sys::console::log_1(&JsValue::from(format!("Opened Pricing handler 1")));
+            while let Some((peer, stream)) = incoming_pricing_streams.next().await {
+                web_sys::console::log_1(&JsValue::from(format!("Entered Pricing handler 1")));
+                if let Err(e) = pricing_handler(peer, stream, &pricing_chan_outgoing).await {
+                    web_sys::console::log_1(&JsValue::from(format!(
+                        "Pricing inbound failed {}",
+                        e
+                    )));
+                }
+            }
+        };
+
+        let imported_peers_chan = peers_instructions_chan_outgoing.clone();
+
+        let gossip_inbound_handle = async move {
+            let mut incoming_gossip_streams = match incoming_gossip_streams {
+                Some(incoming) => incoming,
+                None => return,
+            };
+            web_sys::console::log_1(&JsValue::from(format!("Opened Gossip handler 1")));
+            while let Some((peer, stream)) = incoming_gossip_streams.next().await {
+                web_sys::console::log_1(&JsValue::from(format!("Entered Gossip handler 1")));
+                if let Err(e) =
+                    gossip_handler(peer, stream, &peers_instructions_chan_outgoing).await
+                {
+                    web_sys::console::log_1(&JsValue::from(format!("Gossip inbound failed {}", e)));
+                }
+            }
+        };
+
+        // configured depth is a floor, a saturated neighborhood pushes it deeper
+
+        let storage_depth = || {
+            let peer_overlays: Vec<Vec<u8>> = wings
+                .overlay_peers
+                .lock_recover()
+                .keys()
+                .map(|ov| ov.to_vec())
+                .collect();
+            (*self.neighborhood_depth.lock_recover()).max(saturated_depth(
+                &overlay,
+                &peer_overlays,
+                NEIGHBORHOOD_SATURATION,
+            ))
+        };
+
+        let pushsync_inbound_handle = async {
+            let mut incoming_pushsync_streams = match incoming_pushsync_streams {
+                Some(incoming) => incoming,
+                None => return,
+            };
+            web_sys::console::log_1(&JsValue::from(format!("Opened Pushsync handler 1")));
+            while let Some((peer, stream)) = incoming_pushsync_streams.next().await {
+                if let Err(e) = pushsync_inbound_handler(
+                    peer,
+                    stream,
+                    &secret_key,
+                    &overlay,
+                    storage_depth(),
+                    &pushed_chunk_chan_outgoing,
+                )
+                .await
+                {
+                    web_sys::console::log_1(&JsValue::from(format!(
+                        "Pushsync inbound failed {}",
+                        e
+                    )));
+                }
+            }
+        };
+
+        // the bootstrap runs again after a reset, each run handshakes on its own control
+
+        let verified_peers = &wings.verified_peers;
+        let accounting_chan = &accounting_peer_chan_outgoing;
+        // requests are answered side by side, each within a time limit so a peer that stops
+        // mid request does not hold its slot, and only for chunks in our neighborhood
+
+        let retrieval_inbound_handle = async {
+            let served_from = &OverlayAddress::from_slice(&overlay).unwrap_or_default();
+            let (wings, overlay, storage_depth) = (&*wings, &overlay, &storage_depth);
+            serve_streams(
+                incoming_retrieval_streams,
+                MAX_INBOUND_RETRIEVALS,
+                |peer, stream| {
+                    if !*self.serving.lock_recover() {
+                        return None;
+                    }
+
+                    Some(async move {
+                        // pinned and pushed chunks are served as stored, cached leaves only
+                        // while they still hash to the requested address
+
+                        let (pins, bandwidth) = {
+                            let config = self.retrieval_config.lock_recover();
+                            (config.pins.clone(), config.bandwidth.clone())
+                        };
+                        let depth = storage_depth();
+                        let find = |address: &Vec<u8>| {
+                            if !is_within_neighborhood(address, overlay, depth) {
+                                return None;
+                            }
+                            if let Some(data) = pins.chunk(address) {
+                                return Some(data);
+                            }
+                            if let Some(data) = wings.chunk_store.lock_recover().get(address) {
+                                return Some(data);
+                            }
+                            wings
+                                .leaf_cache
+                                .lock_recover()
+                                .get(address)
+                                .filter(|data| valid_cac(data, address))
+                        };
+
+                        // in permissioned mode only recent requests signed for the overlay the
+                        // peer handshaked with are answered, whichever side dialed
+
+                        let permissioned = *self.permissioned_serving.lock_recover();
+                        let authorize = |address: &Vec<u8>, signature: &Vec<u8>| {
+                            if !permissioned {
+                                return true;
+                            }
+                            let now = self.now_ms();
+                            match wings.verified_peers.lock_recover().get(&peer) {
+                                Some(verified) => verify_request_signature(
+                                    address,
+                                    signature,
+                                    verified,
+                                    self.network_id,
+                                    now,
+                                ),
+                                None => false,
+                            }
+                        };
+
+                        match async_std::future::timeout(
+                            Duration::from_millis(RETRIEVAL_INBOUND_TIMEOUT_MS),
+                            retrieval_inbound_handler(peer, stream, find, authorize, &bandwidth),
+                        )
+                        .await
+                        {
+                            Ok(Ok(Some((address, served)))) => {
+                                *self.bytes_served.lock_recover() += served as u64;
+                                let accounting = wings.accounting_peers.lock_recover();
+                                if let Some(accounting_peer) = accounting.get(&peer) {
+                                    apply_debit(accounting_peer, price(served_from, &address));
+                                }
+                            }
+                            Ok(Ok(None)) => {}
+                            Ok(Err(e)) => {
+                                web_sys::console::log_1(&JsValue::from(format!(
+                                    "Retrieve inbound failed {}",
+                                    e
+                                )));
+                            }
+                            Err(_) => {
+                                web_sys::console::log_1(&JsValue::from(format!(
+                                    "Retrieve inbound from {} timed out",
+                                    peer
+                                )));
+                            }
+                        }
+                    })
+                },
+            )
+            .await;
+        };
+
+        // peers dialing us are answered like a bootnode answers us, the remote address the
+        // swarm saw is the underlay they are told we observed
+
+        let handshake_inbound_handle = async {
+            let secret_key = &secret_key;
+            serve_streams(
+                incoming_handshake_streams,
+                MAX_INBOUND_HANDSHAKES,
+                |peer, mut stream| {
+                    Some(async move {
+                        let handshake = self.handshake_config();
+                        let remote = self
+                            .connection_stats
+                            .lock_recover()
+                            .get(&peer)
+                            .and_then(|stats| stats.address.clone())
+                            .unwrap_or_else(|| Multiaddr::empty().with(Protocol::P2p(peer)));
+
+                        let outcome = match async_std::future::timeout(
+                            Duration::from_millis(handshake.timeout_ms as u64),
+                            handshake_inbound_handler(
+                                peer,
+                                &mut stream,
+                                remote,
+                                secret_key,
+                                accounting_chan,
+                                verified_peers,
+                                &handshake,
+                            ),
+                        )
+                        .await
+                        {
+                            Ok(outcome) => outcome,
+                            Err(_) => Err(HandshakeError::Timeout),
+                        };
+
+                        if let Err(e) = outcome {
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Inbound handshake with {} failed {}",
+                                peer, e
+                            )));
+                            self.report_handshake_error(&e);
+                        }
+                    })
+                },
+            )
+            .await;
+        };
+
+        let conn_handle = || {
+            let mut ctrl = ctrl.clone();
+            async move {
+                let bootnode_address = self.bootnode_address.lock_recover().clone();
+                let addr1 = match bootnode_address.parse::<Multiaddr>() {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        return Err(format!(
+                            "Invalid bootnode address {} {}",
+                            bootnode_address, e
+                        ));
+                    }
+                };
+
+                // hostnames given as /dnsaddr are expanded to dialable addresses first
+
+                let dns_resolver = self.dns_resolver.lock_recover().clone();
+                let candidates = resolve_multiaddr(addr1, &dns_resolver).await;
+                if candidates.is_empty() {
+                    return Err(format!(
+                        "No dialable address for bootnode {}",
+                        bootnode_address
+                    ));
+                }
+
+                // a reservation on every relay lets peers reach this node through it
+
+                let relays = self.relay_addresses.lock_recover().clone();
+                if self.behaviour_config.relay {
+                    let mut swarm = self.swarm.lock_recover();
+                    for relay in relays.iter() {
+                        let _ = swarm.dial(relay.clone());
+                        if let Err(e) = swarm.listen_on(relay.clone().with(Protocol::P2pCircuit)) {
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "No reservation on relay {} {}",
+                                relay, e
+                            )));
+                        }
+                    }
+                }
+
+                // every attempt dials the first candidate that accepts and runs the handshake,
+                // failed attempts back off exponentially
+
+                let attempts = *self.bootstrap_attempts.lock_recover();
+                let mut last_error = String::new();
+                for attempt in 0..attempts {
+                    *self.bootstrap_attempts_made.lock_recover() = attempt + 1;
+                    if attempt > 0 {
+                        let backoff =
+                            BOOTSTRAP_BACKOFF_MS * 2_u32.saturating_pow(attempt - 1) as f64;
+                        async_std::task::sleep(Duration::from_millis(
+                            backoff.min(BOOTSTRAP_MAX_BACKOFF_MS) as u64,
+                        ))
+                        .await;
+                    }
+
+                    let mut dialed = None;
+                    for candidate in candidates.iter() {
+                        let dial = self.swarm.lock_recover().dial(candidate.clone());
+                        let outcome = match dial {
+                            Ok(()) => self.await_connection(candidate).await,
+                            Err(e) => Err(ConnectError::Dial(format!("{} {}", candidate, e))),
+                        };
+                        match outcome {
+                            Ok(()) => {
+                                dialed = Some(candidate.clone());
+                                break;
+                            }
+                            Err(e) => {
+                                web_sys::console::log_1(&JsValue::from(format!(
+                                    "Bootnode candidate unreachable ({}): {}",
+                                    e.kind(),
+                                    e
+                                )));
+                                last_error = format!("Connect {} failed {}", candidate, e);
+                            }
+                        }
+                    }
+                    let addr2 = match dialed {
+                        Some(addr2) => addr2,
+                        None => continue,
+                    };
+
+                    match connection_handler(
+                        try_from_multiaddr(&addr2).unwrap_or(peer_id),
+                        &mut ctrl,
+                        &addr2,
+                        &self.secret_key.lock_recover(),
+                        accounting_chan,
+                        verified_peers,
+                        &self.handshake_config(),
+                    )
+                    .await
+                    {
+                        Ok(()) => {
+                            *self.bootstrap_error.lock_recover() = None;
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            self.report_handshake_error(&e);
+                            last_error = format!("Handshake with {} failed {}", addr2, e);
+                            if !e.retryable() {
+                                return Err(last_error);
+                            }
+                        }
+                    }
+
+                    web_sys::console::log_1(&JsValue::from(format!(
+                        "Bootstrap attempt {} of {}: {}",
+                        attempt + 1,
+                        attempts,
+                        last_error
+                    )));
+                }
+
+                Err(last_error)
+            }
+        };
+
+        let swarm_event_handle = async {
+            let mut relay_dialed: HashSet<PeerId> = HashSet::new();
+            loop {
+                let mut swarm = self.swarm.lock_recover();
+                while let Ok(paddr) = peers_instructions_chan_incoming.try_recv() {
+                    // web_sys::console::log_1(&JsValue::from(format!(
+                    //     "Current Conn Handled {:#?}",
+                    //     paddr
+                    // )));
+                    // at the cap a newcomer is only dialed when closer than the least valuable connection
+
+                    let max_established = *self.max_established.lock_recover();
+                    let connected = wings.connected_peers.lock_recover().len() as u32;
+                    if connected >= max_established {
+                        let newcomer_po = get_proximity(&overlay, &paddr.overlay);
+                        match eviction_candidate(
+                            &wings.connected_peers,
+                            &wings.accounting_peers,
+                            &overlay,
+                            self.now_ms(),
+                        ) {
+                            Some((victim, victim_po)) if newcomer_po > victim_po => {
+                                let _ = swarm.disconnect_peer_id(victim);
+                            }
+                            _ => continue,
+                        }
+                    }
+                    let addr4 = match libp2p::core::Multiaddr::try_from(paddr.underlay.clone()) {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Not dialing {}, invalid underlay {}",
+                                hex::encode(&paddr.overlay),
+                                e
+                            )));
+                            continue;
+                        }
+                    };
+                    if swarm.dial(addr4.clone()).is_err() && self.behaviour_config.relay {
+                        if let Some(peer) = try_from_multiaddr(&addr4) {
+                            if relay_dialed.insert(peer) {
+                                relay_dial(&mut swarm, peer, &self.relay_addresses);
+                            }
+                        }
+                    }
+                    let _ = connections_instructions_chan_outgoing.send(paddr);
+                }
+
+                let event = async_std::future::timeout(
+                    Duration::from_millis(EVENT_LOOP_INTERRUPTOR as u64),
+                    swarm.next(),
+                )
+                .await;
+
+                if let Ok(event) = event {
+                    // web_sys::console::log_1(&JsValue::from(format!(
+                    //     "Current Event Handled {:#?}",
+                    //     event
+                    // )));
+                    match event {
+                        // a failed direct dial is tried once more through the relays, again
+                        // after the peer was connected and dropped
+                        Some(SwarmEvent::OutgoingConnectionError {
+                            peer_id: Some(peer),
+                            ..
+                        }) => {
+                            if self.behaviour_config.relay && relay_dialed.insert(peer) {
+                                relay_dial(&mut swarm, peer, &self.relay_addresses);
+                            }
+                        }
+                        // kept apart from the swarm so handshakes can read them without its lock
+                        Some(SwarmEvent::ExternalAddrConfirmed { address }) => {
+                            let mut external = self.external_addresses.lock_recover();
+                            if advertisable(&address) && !external.contains(&address) {
+                                external.push(address);
+                            }
+                        }
+                        Some(SwarmEvent::ExternalAddrExpired { address }) => {
+                            self.external_addresses
+                                .lock_recover()
+                                .retain(|known| *known != address);
+                        }
+                        Some(SwarmEvent::ConnectionEstablished {
+                            peer_id,
+                            endpoint,
+                            // established_in,
+                            ..
+                        }) => {
+                            relay_dialed.remove(&peer_id);
+                            self.connection_stats
+                                .lock_recover()
+                                .entry(peer_id)
+                                .or_insert(ConnectionStats {
+                                    established_at: self.now_ms(),
+                                    address: Some(endpoint.get_remote_address().clone()),
+                                    ..Default::default()
+                                });
+                        }
+                        Some(SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event {
+                            peer,
+                            result: Ok(rtt),
+                            ..
+                        }))) => {
+                            if let Some(stats) = self.connection_stats.lock_recover().get_mut(&peer)
+                            {
+                                stats.rtt_ms = Some(rtt.as_secs_f64() * 1000.0);
+                                stats.last_ping = self.now_ms();
+                            }
+                        }
+                        Some(SwarmEvent::ConnectionClosed {
+                            peer_id,
+                            num_established,
+                            ..
+                        }) => {
+                            // a peer that dialed back in is verified again on its next handshake
+
+                            if num_established == 0 {
+                                relay_dialed.remove(&peer_id);
+                                self.connection_stats.lock_recover().remove(&peer_id);
+                                wings.verified_peers.lock_recover().remove(&peer_id);
+                            }
+                            {
+                                let mut connected_peers_map = wings.connected_peers.lock_recover();
+                                let mut overlay_peers_map = wings.overlay_peers.lock_recover();
+                                if let Some(peer_file) = connected_peers_map.remove(&peer_id) {
+                                    if let Some(ol0) =
+                                        OverlayAddress::from_slice(&peer_file.overlay)
+                                    {
+                                        if overlay_peers_map.get(&ol0) == Some(&peer_id) {
+                                            overlay_peers_map.remove(&ol0);
+                                        }
+                                    };
+                                };
+                            }
+                            let mut accounting = wings.accounting_peers.lock_recover();
+                            if accounting.contains_key(&peer_id) {
+                                accounting.remove(&peer_id);
+                            };
+                            *self.ready_peers.lock_recover() =
+                                wings.overlay_peers.lock_recover().len();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        };
+
+        let event_handle = async {
+            let mut timelast = self.now_ms();
+            let mut interrupt_last = self.now_ms();
+            loop {
+                let k0 = async {
+                    while let Ok(that) = connections_instructions_chan_incoming.try_recv() {
+                        let addr3 = match libp2p::core::Multiaddr::try_from(that.underlay) {
+                            Ok(addr) => addr,
+                            Err(_) => continue,
+                        };
+                        let id = try_from_multiaddr(&addr3);
+                        if id.is_some() {
+                            if let Err(e) = connection_handler(
+                                id.expect("not"),
+                                &mut ctrl3,
+                                &addr3.clone(),
+                                &self.secret_key.lock_recover(),
+                                &accounting_peer_chan_outgoing,
+                                &wings.verified_peers,
+                                &self.handshake_config(),
+                            )
+                            .await
+                            {
+                                self.report_handshake_error(&e);
+                            }
+                        }
+                    }
+                };
+
+                let k1 = async {
+                    while let Ok(incoming_peer) = accounting_peer_chan_incoming.try_recv() {
+                        // Accounting connect
+                        let peer_file: PeerFile = incoming_peer;
+                        let ol = hex::encode(peer_file.overlay.clone());
+                        let overlay_address = match OverlayAddress::from_slice(&peer_file.overlay) {
+                            Some(overlay_address) => overlay_address,
+                            None => {
+                                web_sys::console::log_1(&JsValue::from(format!(
+                                    "Rejecting malformed overlay {} for {}",
+                                    ol, peer_file.peer_id
+                                )));
+                                continue;
+                            }
+                        };
+                        web_sys::console::log_1(&JsValue::from(format!(
+                            "Accounting Connecting Peer {:#?} {:#?}!",
+                            ol, peer_file.peer_id
+                        )));
+                        if let Some(stale) = insert_peer(
+                            &wings.overlay_peers,
+                            &wings.connected_peers,
+                            &wings.accounting_peers,
+                            overlay_address,
+                            peer_file,
+                        ) {
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Replacing stale peer {} for overlay {}",
+                                stale, ol
+                            )));
+                            wings.verified_peers.lock_recover().remove(&stale);
+                        }
+                        *self.ready_peers.lock_recover() = wings.overlay_peers.lock_recover().len();
+                    }
+                };
+
+                let k2 = async {
+                    while let Ok((peer, amount)) = pricing_chan_incoming.try_recv() {
+                        let accounting = wings.accounting_peers.lock_recover();
+                        if let Some(accounting_peer) = accounting.get(&peer) {
+                            set_payment_threshold(accounting_peer, amount);
+                        }
+                    }
+                };
+
+                let k3 = async {
+                    let mut refresh_joiner = Vec::new();
+
+                    while let Ok((peer, amount)) = refreshment_instructions_chan_incoming.try_recv()
+                    {
+                        web_sys::console::log_1(&JsValue::from(format!("Refresh attempt")));
+                        {
+                            let map = wings.ongoing_refreshments.lock_recover();
+                            if map.contains(&peer) {
+                                continue;
+                            }
+                        }
+                        let (datenow, refresh_budget) = {
+                            let config = self.retrieval_config.lock_recover();
+                            (config.clock.now_ms(), config.refresh.clone())
+                        };
+                        let amount = {
+                            let accounting = wings.accounting_peers.lock_recover();
+                            match accounting.get(&peer) {
+                                Some(accounting_peer) => {
+                                    plan_refresh(accounting_peer, amount, &refresh_budget, datenow)
+                                }
+                                None => continue,
+                            }
+                        };
+                        if amount > 0 {
+                            {
+                                let mut map = wings.ongoing_refreshments.lock_recover();
+                                map.insert(peer);
+                            }
+                            let mut ctrl7 = ctrl4.clone();
+                            let rco = refreshment_chan_outgoing.clone();
+                            let handle = async move {
+                                refresh_handler(peer, amount, &mut ctrl7, &rco).await;
+                            };
+                            refresh_joiner.push(handle);
+                        }
+                    }
+
+                    join_all(refresh_joiner).await;
+                };
+
+                let k4 = async {
+                    while let Ok((peer, amount)) = refreshment_chan_incoming.try_recv() {
+                        self.retrieval_config
+                            .lock_recover()
+                            .refresh
+                            .record_grant(amount);
+                        {
+                            let accounting = wings.accounting_peers.lock_recover();
+                            if let Some(accounting_peer) = accounting.get(&peer) {
+                                apply_refreshment(accounting_peer, amount);
+                            }
+                        }
+                        let mut map = wings.ongoing_refreshments.lock_recover();
+                        if map.contains(&peer) {
+                            map.remove(&peer);
+                        }
+                    }
+                };
+
+                let k5 = async {
+                    while let Ok((address, data)) = pushed_chunk_chan_incoming.try_recv() {
+                        {
+                            let capacity = *self.chunk_store_capacity.lock_recover();
+                            let mut chunk_store = wings.chunk_store.lock_recover();
+                            chunk_store.insert(&address, &data, capacity);
+                        }
+                        let on_chunk_stored = self.on_chunk_stored.lock_recover();
+                        if let Some(callback) = on_chunk_stored.as_ref() {
+                            let _ = callback
+                                .call1(&JsValue::NULL, &JsValue::from(hex::encode(&address)));
+                        }
+                    }
+                };
+
+                join!(k0, k1, k2, k3, k4, k5);
+
+                let timenow = self.now_ms();
+                let seg = timenow - interrupt_last;
+                if seg < EVENT_LOOP_INTERRUPTOR {
                     //                web_sys::console::log_1(&JsValue::from(format!(
                     //                    "Ease event handle loop for {}",
                     //                    EVENT_LOOP_INTERRUPTOR - seg
@@ -557,7 +2098,7 @@ impl Sekirei {
                     ))
                     .await;
                 }
-                let timenow = Date::now();
+                let timenow = self.now_ms();
                 interrupt_last = timenow;
 
                 if timelast + EVENT_LOOP_INTERRUPTOR < timenow {
@@ -567,27 +2108,172 @@ impl Sekirei {
             }
         };
 
-        let retrieve_handle = async {
-            let mut timelast = Date::now();
-            loop {
-                #[allow(irrefutable_let_patterns)]
-                while let incoming_request = self.message_port.1.try_recv() {
-                    if !incoming_request.is_err() {
-                        web_sys::console::log_1(&JsValue::from(format!("retrieve triggered")));
-                        let (n, chan) = incoming_request.unwrap();
-                        let encoded_data =
-                            retrieve_resource(&n, &data_retrieve_chan_outgoing).await;
-                        web_sys::console::log_1(&JsValue::from(format!(
-                            "Writing response to interface request"
-                        )));
+        let retrieve_handle = async {
+            let mut timelast = self.now_ms();
+            loop {
+                while let Ok((n, mode, chan, scope)) = self.message_port.1.try_recv() {
+                    web_sys::console::log_1(&JsValue::from(format!("retrieve triggered")));
+                    if mode <= 3 || mode == 12 || mode == 13 {
+                        self.dial_imported_near(&n, &imported_peers_chan);
+                    }
+                    match mode {
+                        0 => {
+                            let encoded_data =
+                                retrieve_resource(&n, &data_retrieve_chan_outgoing, &scope).await;
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Writing response to interface request"
+                            )));
+
+                            let _ = chan.send(encoded_data);
+                        }
+                        // availability probes, streams and cost estimates are answered by the data retrieve loop
+                        1 => {
+                            let _ = data_retrieve_chan_outgoing.send((n, 2, chan, scope));
+                        }
+                        2 => {
+                            let _ = data_retrieve_chan_outgoing.send((n, 3, chan, scope));
+                        }
+                        3 => {
+                            let _ = data_retrieve_chan_outgoing.send((n, 4, chan, scope));
+                        }
+                        5 => {
+                            let snapshot = accounting_snapshot(
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                self.now_ms(),
+                            );
+                            let _ = chan.send(serde_json::to_vec(&snapshot).unwrap_or_default());
+                        }
+                        9 => {
+                            let snapshot = self.peers_snapshot(&wings.overlay_peers);
+                            let _ = chan.send(serde_json::to_vec(&snapshot).unwrap_or_default());
+                        }
+                        6 => {
+                            let fields = decode_frame_fields(&n);
+                            let (body, mime, encoding, status) = match fields.as_slice() {
+                                [address, path] => {
+                                    fetch_website(
+                                        address,
+                                        &String::from_utf8_lossy(path),
+                                        &data_retrieve_chan_outgoing,
+                                        &scope,
+                                    )
+                                    .await
+                                }
+                                _ => (vec![], "text/plain".to_string(), "".to_string(), 400),
+                            };
+
+                            let _ = chan.send(encode_frame_fields(&vec![
+                                body,
+                                mime.into_bytes(),
+                                encoding.into_bytes(),
+                                status.to_le_bytes().to_vec(),
+                            ]));
+                        }
+                        // latest feed update as [type][content], empty when the feed has none
+                        8 => {
+                            let fields = decode_frame_fields(&n);
+                            let search = self.retrieval_config.lock_recover().feed_search;
+                            let update = match fields.as_slice() {
+                                [owner, topic] => {
+                                    seek_latest_typed_feed_update(
+                                        String::from_utf8_lossy(owner).to_string(),
+                                        String::from_utf8_lossy(topic).to_string(),
+                                        &data_retrieve_chan_outgoing,
+                                        &scope,
+                                        search,
+                                    )
+                                    .await
+                                }
+                                _ => None,
+                            };
+
+                            let _ = chan.send(match update {
+                                Some((kind, content)) => [vec![kind as u8], content].concat(),
+                                None => vec![],
+                            });
+                        }
+                        // latest feed index at or past a start index, empty when none exists
+                        7 => {
+                            let fields = decode_frame_fields(&n);
+                            let search = self.retrieval_config.lock_recover().feed_search;
+                            let found = match fields.as_slice() {
+                                [owner, topic, start] => {
+                                    seek_latest_feed_index(
+                                        &String::from_utf8_lossy(owner).to_string(),
+                                        &String::from_utf8_lossy(topic).to_string(),
+                                        &data_retrieve_chan_outgoing,
+                                        &scope,
+                                        search,
+                                        u64::from_le_bytes(
+                                            start.clone().try_into().unwrap_or([0; 8]),
+                                        ),
+                                    )
+                                    .await
+                                }
+                                _ => None,
+                            };
 
-                        chan.send(encoded_data).unwrap();
-                    } else {
-                        break;
+                            let _ = chan.send(match found {
+                                Some(index) => index.to_le_bytes().to_vec(),
+                                None => vec![],
+                            });
+                        }
+                        // one chunk from one named peer, [0][chunk] or [error code]
+                        10 => {
+                            let _ = data_retrieve_chan_outgoing.send((n, 6, chan, scope));
+                        }
+                        // chunk tree as [depth][address] fields, walked by the data retrieve loop
+                        12 => {
+                            let _ = data_retrieve_chan_outgoing.send((n, 7, chan, scope));
+                        }
+                        // one chunk with its kind, [0][data, owner, id fields] or [error code]
+                        13 => {
+                            let _ = data_retrieve_chan_outgoing.send((n, 8, chan, scope));
+                        }
+                        // reset, every peer is dropped before its state so no handshake
+                        // refills what is cleared, the bootstrap then runs again and
+                        // answers once it is done
+                        11 => {
+                            {
+                                let mut swarm = self.swarm.lock_recover();
+                                for peer in wings.connected_peers.lock_recover().keys() {
+                                    let _ = swarm.disconnect_peer_id(*peer);
+                                }
+                            }
+                            wings.overlay_peers.lock_recover().clear();
+                            wings.connected_peers.lock_recover().clear();
+                            wings.accounting_peers.lock_recover().clear();
+                            wings.ongoing_refreshments.lock_recover().clear();
+                            wings.verified_peers.lock_recover().clear();
+                            *wings.leaf_cache.lock_recover() = ChunkLru::default();
+                            prune_chunks(&leaf_store, 0).await;
+                            *self.ready_peers.lock_recover() = 0;
+                            *self.rebootstrap.lock_recover() = Some(chan);
+                        }
+                        4 => {
+                            let results = retrieve_many(
+                                decode_frame_fields(&n),
+                                &data_retrieve_chan_outgoing,
+                                &scope,
+                            )
+                            .await;
+
+                            let fields: Vec<Vec<u8>> = results
+                                .into_iter()
+                                .map(|result| match result {
+                                    Ok(data) => [vec![0], data].concat(),
+                                    Err(e) => vec![e as u8],
+                                })
+                                .collect();
+
+                            let _ = chan.send(encode_frame_fields(&fields));
+                        }
+                        _ => {}
                     }
                 }
 
-                let timenow = Date::now();
+                let timenow = self.now_ms();
                 let seg = timenow - timelast;
                 if seg < PROTO_LOOP_INTERRUPTOR {
                     // web_sys::console::log_1(&JsValue::from(format!(
@@ -599,62 +2285,246 @@ impl Sekirei {
                     ))
                     .await;
                 }
-                timelast = Date::now();
+                timelast = self.now_ms();
             }
         };
 
         let retrieve_data_handle = async {
-            let mut timelast = Date::now();
+            let mut timelast = self.now_ms();
+            let gate_started = self.now_ms();
+            let mut gate_open = false;
             loop {
+                // requests stay queued until discovery has found enough candidates
+
+                if !gate_open {
+                    let (min_peers, min_peers_timeout_ms) = {
+                        let config = self.retrieval_config.lock_recover();
+                        (config.min_peers, config.min_peers_timeout_ms)
+                    };
+                    let known = wings.overlay_peers.lock_recover().len();
+                    if known >= min_peers {
+                        gate_open = true;
+                    } else if self.now_ms() - gate_started >= min_peers_timeout_ms {
+                        web_sys::console::log_1(&JsValue::from(format!(
+                            "Only {} of {} peers known after {}ms, retrieving anyway",
+                            known, min_peers, min_peers_timeout_ms
+                        )));
+                        gate_open = true;
+                    } else {
+                        async_std::task::sleep(Duration::from_millis(
+                            PROTO_LOOP_INTERRUPTOR as u64,
+                        ))
+                        .await;
+                        continue;
+                    }
+                }
+
                 let mut request_joiner = Vec::new();
 
-                #[allow(irrefutable_let_patterns)]
-                while let incoming_request = data_retrieve_chan_incoming.try_recv() {
-                    if !incoming_request.is_err() {
-                        let handle = async {
-                            let mut ctrl9 = ctrl6.clone();
-                            web_sys::console::log_1(&JsValue::from(format!("retrieve triggered")));
-                            let (n, mode, chan) = incoming_request.unwrap();
-                            if mode == 1 {
-                                let chunk_data = retrieve_data(
-                                    &n,
-                                    &mut ctrl9,
-                                    &wings.overlay_peers,
-                                    &wings.accounting_peers,
-                                    &refreshment_instructions_chan_outgoing,
-                                )
-                                .await;
-                                web_sys::console::log_1(&JsValue::from(format!(
-                                    "Writing response to retrieve request"
-                                )));
+                while let Ok(incoming_request) = data_retrieve_chan_incoming.try_recv() {
+                    let handle = async {
+                        let mut ctrl9 = ctrl6.clone();
+                        let retrieval_config = self.retrieval_config.lock_recover().clone();
+                        web_sys::console::log_1(&JsValue::from(format!("retrieve triggered")));
+                        let (n, mode, chan, scope) = incoming_request;
+                        if mode == 1 {
+                            let chunk_data = match retrieve_data_verified(
+                                &n,
+                                &mut ctrl9,
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                &refreshment_instructions_chan_outgoing,
+                                &retrieval_config,
+                                &scope,
+                                &wings.leaf_cache,
+                            )
+                            .await
+                            {
+                                Ok(data) => data,
+                                Err(RetrievalError::IntegrityMismatch) => {
+                                    scope.flag_integrity_mismatch(&n);
+                                    vec![]
+                                }
+                                Err(_) => vec![],
+                            };
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Writing response to retrieve request"
+                            )));
 
-                                chan.send(chunk_data).unwrap();
+                            let _ = chan.send(chunk_data);
+                        }
+                        if mode == 3 {
+                            let streamed = retrieve_data_stream(
+                                &n,
+                                &mut ctrl9,
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                &refreshment_instructions_chan_outgoing,
+                                &retrieval_config,
+                                &scope,
+                                &wings.leaf_cache,
+                                &chan,
+                            )
+                            .await;
+
+                            if streamed {
+                                let _ = chan.send(vec![STREAM_END]);
+                            } else {
+                                let _ = chan.send(vec![STREAM_FAILED]);
                             }
-                            if mode == 0 {
-                                let chunk_data = retrieve_chunk(
-                                    &n,
-                                    &mut ctrl9,
-                                    &wings.overlay_peers,
-                                    &wings.accounting_peers,
-                                    &refreshment_instructions_chan_outgoing,
-                                )
-                                .await;
-                                web_sys::console::log_1(&JsValue::from(format!(
-                                    "Writing response to retrieve request"
-                                )));
+                        }
+                        if mode == 4 {
+                            let cost = estimate_cost(
+                                &n,
+                                &mut ctrl9,
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                &refreshment_instructions_chan_outgoing,
+                                &retrieval_config,
+                                &scope,
+                            )
+                            .await;
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Estimated cost of {} is {}",
+                                hex::encode(&n),
+                                cost
+                            )));
+
+                            let _ = chan.send(cost.to_le_bytes().to_vec());
+                        }
+                        if mode == 2 && n.len() > 4 {
+                            let (address, rate) = n.split_at(n.len() - 4);
+                            let sample_rate = f32::from_le_bytes(rate.try_into().unwrap_or([0; 4]));
+                            let ratio = check_availability(
+                                &address.to_vec(),
+                                sample_rate,
+                                &mut ctrl9,
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                &refreshment_instructions_chan_outgoing,
+                                &retrieval_config,
+                                &scope,
+                            )
+                            .await;
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Availability of {} is {}",
+                                hex::encode(address),
+                                ratio
+                            )));
+
+                            let _ = chan.send(ratio.to_le_bytes().to_vec());
+                        }
+                        if mode == 6 {
+                            let fields = decode_frame_fields(&n);
+                            let result = match fields.as_slice() {
+                                [peer, address] => match PeerId::from_bytes(peer) {
+                                    Ok(peer) => {
+                                        retrieve_chunk_from(
+                                            peer,
+                                            address,
+                                            &mut ctrl9,
+                                            &wings.overlay_peers,
+                                            &wings.accounting_peers,
+                                            &refreshment_instructions_chan_outgoing,
+                                            &retrieval_config,
+                                        )
+                                        .await
+                                    }
+                                    Err(_) => Err(RetrievalError::UnknownPeer),
+                                },
+                                _ => Err(RetrievalError::InvalidAddress),
+                            };
 
-                                chan.send(chunk_data).unwrap();
-                            }
-                        };
-                        request_joiner.push(handle);
-                    } else {
-                        break;
-                    }
+                            let _ = chan.send(match result {
+                                Ok(data) => [vec![0], data].concat(),
+                                Err(e) => vec![e as u8],
+                            });
+                        }
+                        if mode == 7 {
+                            let tree = chunk_tree(
+                                &n,
+                                &mut ctrl9,
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                &refreshment_instructions_chan_outgoing,
+                                &retrieval_config,
+                                &scope,
+                            )
+                            .await;
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Chunk tree of {} lists {} addresses",
+                                hex::encode(&n),
+                                tree.len()
+                            )));
+
+                            let fields: Vec<Vec<u8>> = tree
+                                .into_iter()
+                                .map(|(depth, address)| [vec![depth], address].concat())
+                                .collect();
+                            let _ = chan.send(encode_frame_fields(&fields));
+                        }
+                        if mode == 8 {
+                            let (kind, data) = retrieve_chunk_typed(
+                                &n,
+                                &mut ctrl9,
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                &refreshment_instructions_chan_outgoing,
+                                &retrieval_config,
+                                &scope,
+                            )
+                            .await;
+
+                            let fields = match kind {
+                                _ if data.is_empty() => vec![],
+                                ChunkKind::Cac => vec![data],
+                                ChunkKind::Soc { owner, id } => vec![data, owner, id],
+                            };
+                            let _ = chan.send(match fields.is_empty() {
+                                true => vec![RetrievalError::NotFound as u8],
+                                false => [vec![0], encode_frame_fields(&fields)].concat(),
+                            });
+                        }
+                        if mode == 5 && n.len() == REFERENCE_SIZE + 20 {
+                            let (address, owner) = n.split_at(REFERENCE_SIZE);
+                            let feed_update = retrieve_owned_soc(
+                                &address.to_vec(),
+                                &owner.to_vec(),
+                                &mut ctrl9,
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                &refreshment_instructions_chan_outgoing,
+                                &retrieval_config,
+                                &scope,
+                            )
+                            .await;
+
+                            let _ = chan.send(feed_update);
+                        }
+                        if mode == 0 {
+                            let chunk_data = retrieve_chunk(
+                                &n,
+                                &mut ctrl9,
+                                &wings.overlay_peers,
+                                &wings.accounting_peers,
+                                &refreshment_instructions_chan_outgoing,
+                                &retrieval_config,
+                                &scope,
+                            )
+                            .await;
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Writing response to retrieve request"
+                            )));
+
+                            let _ = chan.send(chunk_data);
+                        }
+                    };
+                    request_joiner.push(handle);
                 }
 
                 join_all(request_joiner).await;
 
-                let timenow = Date::now();
+                let timenow = self.now_ms();
                 let seg = timenow - timelast;
                 if seg < PROTO_LOOP_INTERRUPTOR {
                     // web_sys::console::log_1(&JsValue::from(format!(
@@ -666,51 +2536,692 @@ impl Sekirei {
                     ))
                     .await;
                 }
-                timelast = Date::now();
+                timelast = self.now_ms();
             }
         };
 
-        join!(
-            conn_handle,
-            event_handle,
-            retrieve_handle,
-            retrieve_data_handle,
-            swarm_event_handle,
-            gossip_inbound_handle,
-            pricing_inbound_handle,
-        );
+        let feed_watch_handle = async {
+            loop {
+                // watches are snapshotted so the lock is never held across a search
+
+                let now = self.retrieval_config.lock_recover().clock.now_ms();
+                let due: Vec<((String, String), Option<u64>, bool)> = self
+                    .feed_watches
+                    .lock_recover()
+                    .iter()
+                    .filter(|(_, watch)| watch.next_poll <= now)
+                    .map(|(key, watch)| {
+                        (
+                            key.clone(),
+                            watch.last_index,
+                            watch
+                                .subscribers
+                                .values()
+                                .any(|(_, delivered)| *delivered != watch.last_index),
+                        )
+                    })
+                    .collect();
+
+                for ((owner, topic), last_index, behind) in due {
+                    let (scope, search) = {
+                        let config = self.retrieval_config.lock_recover();
+                        (
+                            RetrievalScope::new(0, config.retry_budget),
+                            config.feed_search,
+                        )
+                    };
+                    let found = seek_latest_feed_index(
+                        &owner,
+                        &topic,
+                        &data_retrieve_chan_outgoing,
+                        &scope,
+                        search,
+                        last_index.unwrap_or(0),
+                    )
+                    .await;
+
+                    let newer = match (found, last_index) {
+                        (Some(index), Some(last)) => index > last,
+                        (Some(_), None) => true,
+                        _ => false,
+                    };
+
+                    let mut update = vec![];
+                    if found.is_some() && (newer || behind) {
+                        update = get_data(
+                            get_feed_address(&owner, &topic, found.unwrap_or(0)),
+                            &data_retrieve_chan_outgoing,
+                            &scope,
+                        )
+                        .await;
+                    }
+
+                    // idle feeds are polled exponentially less often, an update resets the pace
+
+                    let callbacks: Vec<js_sys::Function> = match self
+                        .feed_watches
+                        .lock_recover()
+                        .get_mut(&(owner.clone(), topic.clone()))
+                    {
+                        Some(watch) => {
+                            if newer {
+                                watch.last_index = found;
+                                watch.idle_rounds = 0;
+                            } else {
+                                watch.idle_rounds =
+                                    (watch.idle_rounds + 1).min(FEED_WATCH_MAX_BACKOFF);
+                            }
+                            watch.next_poll = self.retrieval_config.lock_recover().clock.now_ms()
+                                + watch.interval_ms * 2_u32.pow(watch.idle_rounds) as f64;
+
+                            // the update is fanned out to every subscriber that has not seen it
+
+                            let mut callbacks = vec![];
+                            if found.is_some() {
+                                for (callback, delivered) in watch.subscribers.values_mut() {
+                                    if *delivered != found {
+                                        *delivered = found;
+                                        callbacks.push(callback.clone());
+                                    }
+                                }
+                            }
+                            callbacks
+                        }
+                        None => vec![],
+                    };
+
+                    let payload = match update.len() > 8 {
+                        true => update[8..].to_vec(),
+                        false => vec![],
+                    };
+                    for callback in callbacks {
+                        let _ = callback.call2(
+                            &JsValue::NULL,
+                            &JsValue::from(found.unwrap_or(0)),
+                            &js_sys::Uint8Array::from(&payload[..]),
+                        );
+                    }
+                }
+
+                async_std::task::sleep(Duration::from_millis(PROTO_LOOP_INTERRUPTOR as u64)).await;
+            }
+        };
+
+        // only the first bootstrap ends the run when it fails, one after a reset is reported
+        // to the reset and the service loops carry on
+
+        let bootstrap_handle = async {
+            let mut after_reset: Option<mpsc::Sender<Vec<u8>>> = None;
+            loop {
+                let outcome = conn_handle().await;
+                match after_reset.take() {
+                    None => {
+                        if outcome.is_err() {
+                            return outcome;
+                        }
+                    }
+                    Some(reply) => {
+                        if let Err(e) = &outcome {
+                            web_sys::console::log_1(&JsValue::from(format!(
+                                "Bootstrap after reset failed: {}",
+                                e
+                            )));
+                            *self.bootstrap_error.lock_recover() = Some(e.clone());
+                        }
+                        let _ = reply.send(vec![outcome.is_ok() as u8]);
+                    }
+                }
+
+                after_reset = loop {
+                    if let Some(reply) = self.rebootstrap.lock_recover().take() {
+                        break Some(reply);
+                    }
+                    async_std::task::sleep(Duration::from_millis(EVENT_LOOP_INTERRUPTOR as u64))
+                        .await;
+                };
+                web_sys::console::log_1(&JsValue::from(format!("Bootstrapping after reset")));
+            }
+        };
+
+        let service_handle = async {
+            join!(
+                event_handle,
+                retrieve_handle,
+                retrieve_data_handle,
+                swarm_event_handle,
+                gossip_inbound_handle,
+                pricing_inbound_handle,
+                pushsync_inbound_handle,
+                retrieval_inbound_handle,
+                handshake_inbound_handle,
+                feed_watch_handle,
+            );
+        };
+
+        let shutdown_handle = async {
+            while !std::mem::take(&mut *self.shutdown.lock_recover()) {
+                async_std::task::sleep(Duration::from_millis(EVENT_LOOP_INTERRUPTOR as u64)).await;
+            }
+        };
+
+        let outcome = match select(
+            pin!(bootstrap_handle),
+            pin!(select(pin!(service_handle), pin!(shutdown_handle))),
+        )
+        .await
+        {
+            Either::Left((outcome, _)) => outcome,
+            Either::Right(_) => Ok(()),
+        };
 
         web_sys::console::log_1(&JsValue::from(format!("Dropping All handlers")));
 
-        ()
+        if outcome.is_ok() {
+            let mut swarm = self.swarm.lock_recover();
+            for peer in wings.connected_peers.lock_recover().keys() {
+                let _ = swarm.disconnect_peer_id(*peer);
+            }
+        }
+
+        if let Err(e) = outcome {
+            web_sys::console::log_1(&JsValue::from(format!("Bootstrap failed: {}", e)));
+            *self.bootstrap_error.lock_recover() = Some(e.clone());
+            return Err(JsValue::from(e));
+        }
+
+        Ok(())
+    }
+}
+
+// only built for tests of timing, a released client always runs on the browser clock
+
+#[cfg(feature = "manual-clock")]
+#[wasm_bindgen]
+impl Sekirei {
+    // with a manual clock timing only moves through advance_clock, zero returns to the browser clock
+
+    pub fn use_manual_clock(&self, start_ms: f64) {
+        let mut config = self.retrieval_config.lock_recover();
+        let mut manual_clock = self.manual_clock.lock_recover();
+        if start_ms > 0.0 {
+            let clock = ManualClock::starting_at(start_ms);
+            config.clock = Arc::new(clock.clone());
+            *manual_clock = Some(clock);
+        } else {
+            config.clock = Arc::new(BrowserClock);
+            *manual_clock = None;
+        }
+        config.bandwidth.set_clock(config.clock.clone());
+    }
+
+    pub fn advance_clock(&self, ms: f64) -> bool {
+        match self.manual_clock.lock_recover().as_ref() {
+            Some(clock) => {
+                clock.advance(ms);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Sekirei {
+    // every timing of the client reads the configured clock, the browser clock unless a
+    // manual one was set
+
+    fn now_ms(&self) -> f64 {
+        self.retrieval_config.lock_recover().clock.now_ms()
+    }
+
+    async fn resolve_address(&self, address_or_name: String) -> Vec<u8> {
+        match decode_address(&address_or_name) {
+            Some(hex) => hex,
+            _ => prt(address_or_name, "".to_string()).await,
+        }
+    }
+
+    async fn resolve_chunk_address(&self, address_or_name: String) -> Result<Vec<u8>, JsValue> {
+        let valaddr = self.resolve_address(address_or_name).await;
+        match valid_chunk_address(&valaddr) {
+            true => Ok(valaddr),
+            false => Err(JsValue::from(format!(
+                "{:?}",
+                RetrievalError::InvalidAddress
+            ))),
+        }
+    }
+
+    async fn fetch_website_parts(
+        &self,
+        valaddr: Vec<u8>,
+        path: String,
+    ) -> (Vec<u8>, String, String, u16, String, bool) {
+        let scope = self.register_retrieval(&valaddr);
+        let payload = encode_frame_fields(&vec![valaddr.clone(), path.into_bytes()]);
+        let result = self.port_request(payload, 6, scope.clone()).await;
+
+        let etag = content_etag(&valaddr, &scope.resolved_feeds());
+        let stamped = scope.all_stamped();
+
+        let fields = decode_frame_fields(&result);
+        match fields.as_slice() {
+            [body, mime, encoding, status] => (
+                body.clone(),
+                String::from_utf8_lossy(mime).to_string(),
+                String::from_utf8_lossy(encoding).to_string(),
+                u16::from_le_bytes(status.clone().try_into().unwrap_or(500_u16.to_le_bytes())),
+                etag,
+                stamped,
+            ),
+            _ => (
+                vec![],
+                "text/plain".to_string(),
+                "".to_string(),
+                500,
+                etag,
+                false,
+            ),
+        }
+    }
+
+    // handshaken peers with the address they were reached on, then imported ones not yet dialed
+
+    fn peers_snapshot(
+        &self,
+        overlay_peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    ) -> Vec<serde_json::Value> {
+        let mut snapshot: Vec<serde_json::Value> = vec![];
+
+        {
+            let overlay_peers = overlay_peers.lock_recover();
+            let connection_stats = self.connection_stats.lock_recover();
+            for (overlay, peer_id) in overlay_peers.iter() {
+                let address = match connection_stats
+                    .get(peer_id)
+                    .and_then(|stats| stats.address.clone())
+                {
+                    Some(address) => address,
+                    None => continue,
+                };
+                let underlay = match try_from_multiaddr(&address) {
+                    Some(_) => address,
+                    None => address.with(Protocol::P2p((*peer_id).into())),
+                };
+                snapshot.push(serde_json::json!({
+                    "overlay": overlay.to_string(),
+                    "underlay": underlay.to_string(),
+                    "verified": true,
+                }));
+            }
+        }
+
+        for (overlay, underlay) in self.imported_peers.lock_recover().iter() {
+            snapshot.push(serde_json::json!({
+                "overlay": overlay.to_string(),
+                "underlay": underlay.to_string(),
+                "verified": false,
+            }));
+        }
+
+        snapshot
+    }
+
+    // imported peers closest to a requested address are handed to the dial and handshake path
+
+    fn dial_imported_near(&self, address: &Vec<u8>, chan: &mpsc::Sender<etiquette_2::BzzAddress>) {
+        let mut imported_peers = self.imported_peers.lock_recover();
+        if imported_peers.is_empty() {
+            return;
+        }
+
+        let mut nearest: Vec<OverlayAddress> = imported_peers.keys().copied().collect();
+        nearest.sort_by_cached_key(|overlay| xor_distance(address, overlay));
+        for overlay in nearest.into_iter().take(IMPORTED_DIAL_BATCH) {
+            if let Some(underlay) = imported_peers.remove(&overlay) {
+                let _ = chan.send(etiquette_2::BzzAddress {
+                    underlay: underlay.to_vec(),
+                    signature: vec![],
+                    overlay: overlay.to_vec(),
+                    nonce: vec![],
+                });
+            }
+        }
+    }
+
+    fn handshake_config(&self) -> HandshakeConfig {
+        // only a full node tells the remote how to reach it, through the addresses autonat
+        // confirmed as external
+
+        let advertised = match *self.full_node.lock_recover() {
+            true => self.external_addresses.lock_recover().clone(),
+            false => vec![],
+        };
+
+        HandshakeConfig {
+            network_id: self.network_id,
+            timeout_ms: *self.handshake_timeout_ms.lock_recover(),
+            trace: self.on_protocol_trace.lock_recover().clone(),
+            advertised,
+            protocols: self.handshake_protocols.lock_recover().clone(),
+            clock: self.retrieval_config.lock_recover().clock.clone(),
+        }
+    }
+
+    // establishment is seen through the connection stats kept by the swarm loop, matched by
+    // the peer id in the address or by the remote address itself
+
+    async fn await_connection(&self, address: &Multiaddr) -> Result<(), ConnectError> {
+        let timeout_ms = *self.connect_timeout_ms.lock_recover();
+        let expected = try_from_multiaddr(address);
+        let started = self.now_ms();
+        loop {
+            let established = self
+                .connection_stats
+                .lock_recover()
+                .iter()
+                .any(|(peer, stats)| {
+                    Some(*peer) == expected || stats.address.as_ref() == Some(address)
+                });
+            if established {
+                return Ok(());
+            }
+            if self.now_ms() - started >= timeout_ms {
+                return Err(ConnectError::Timeout {
+                    address: address.clone(),
+                    timeout_ms,
+                });
+            }
+            async_std::task::sleep(Duration::from_millis(PROTO_LOOP_INTERRUPTOR as u64)).await;
+        }
+    }
+
+    // only failures that point at a configuration problem reach the interface
+
+    fn report_handshake_error(&self, e: &HandshakeError) {
+        match e {
+            HandshakeError::NetworkIdMismatch { .. } | HandshakeError::ClosedAfterSyn => (),
+            _ => return,
+        }
+
+        let on_handshake_error = self.on_handshake_error.lock_recover();
+        if let Some(callback) = on_handshake_error.as_ref() {
+            let _ = callback.call2(
+                &JsValue::NULL,
+                &JsValue::from(e.kind()),
+                &JsValue::from(e.to_string()),
+            );
+        }
+    }
+
+    fn register_retrieval(&self, address: &Vec<u8>) -> RetrievalScope {
+        let id = {
+            let mut next_retrieval_id = self.next_retrieval_id.lock_recover();
+            *next_retrieval_id += 1;
+            *next_retrieval_id
+        };
+        let scope = {
+            let config = self.retrieval_config.lock_recover();
+            RetrievalScope::new(id, config.retry_budget)
+                .with_manifest_nodes(config.manifest_nodes.clone(), config.manifest_node_capacity)
+                .with_streams(config.streams.clone())
+                .with_clock(config.clock.clone())
+        };
+
+        self.active_retrievals.lock_recover().insert(
+            id,
+            ActiveRetrieval {
+                address: address.clone(),
+                started_at: self.now_ms(),
+                scope: scope.clone(),
+            },
+        );
+
+        scope
+    }
+
+    fn unregister_retrieval(&self, id: u64) {
+        self.active_retrievals.lock_recover().remove(&id);
+    }
+
+    async fn port_request(&self, payload: Vec<u8>, mode: u8, scope: RetrievalScope) -> Vec<u8> {
+        let (chan_out, chan_in) = mpsc::channel::<Vec<u8>>();
+
+        let id = scope.id();
+        let _ = self.message_port.0.send((payload, mode, chan_out, scope));
+
+        // 3ab408eea4f095bde55c1caeeac8e7fcff49477660f0a28f652f0a6d9c60d05f
+        let k0 = async {
+            loop {
+                match chan_in.try_recv() {
+                    Ok(data) => return data,
+                    Err(mpsc::TryRecvError::Disconnected) => return vec![],
+                    Err(mpsc::TryRecvError::Empty) => {
+                        async_std::task::sleep(Duration::from_millis(
+                            EVENT_LOOP_INTERRUPTOR as u64,
+                        ))
+                        .await;
+                    }
+                }
+            }
+        };
+
+        let result = k0.await;
+
+        self.unregister_retrieval(id);
+
+        return result;
+    }
+
+    pub(crate) fn from_secret_key(
+        secret_key_o: ecdsa::SecretKey,
+        protocol_version: String,
+        agent_version: String,
+        behaviour_config: BehaviourConfig,
+    ) -> Sekirei {
+        let idle_duration = Duration::from_secs(60);
+
+        // let body = Body::from_current_window()?;
+        // body.append_p(&format!("Attempt to establish connection over websocket"))?;
+
+        let secret_key = secret_key_o.clone();
+        let keypair: ecdsa::Keypair = secret_key_o.into();
+
+        let swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone().into())
+            .with_wasm_bindgen()
+            .with_other_transport(|key| {
+                websocket_websys::Transport::default()
+                    .upgrade(core::upgrade::Version::V1)
+                    .authenticate(noise::Config::new(&key).unwrap())
+                    .multiplex(yamux::Config::default())
+                    .boxed()
+            })
+            .expect("Failed to create WebSocket transport")
+            .with_relay_client(noise::Config::new, yamux::Config::default)
+            .expect("Failed to create relay client transport")
+            .with_behaviour(|key, relay_client| {
+                Behaviour::new(
+                    key.public(),
+                    relay_client,
+                    protocol_version,
+                    agent_version,
+                    &behaviour_config,
+                )
+            })
+            .unwrap()
+            .with_swarm_config(|_| {
+                libp2p::swarm::Config::with_wasm_executor()
+                    .with_idle_connection_timeout(idle_duration)
+                    .with_max_negotiating_inbound_streams(NonZero::new(10000_usize).unwrap().into())
+                    .with_per_connection_event_buffer_size(10000_usize)
+                    .with_notify_handler_buffer_size(NonZero::new(10000_usize).unwrap().into())
+            })
+            .build();
+
+        let connected_peers: Mutex<HashMap<PeerId, PeerFile>> = Mutex::new(HashMap::new());
+        let overlay_peers: Mutex<HashMap<OverlayAddress, PeerId>> = Mutex::new(HashMap::new());
+        let accounting_peers: Mutex<HashMap<PeerId, Mutex<PeerAccounting>>> =
+            Mutex::new(HashMap::new());
+        let ongoing_refreshments: Mutex<HashSet<PeerId>> = Mutex::new(HashSet::new());
+
+        let (m_out, m_in) = mpsc::channel::<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>();
+
+        return Sekirei {
+            secret_key: Mutex::new(secret_key),
+            swarm: Mutex::new(swarm),
+            wings: Mutex::new(Wings {
+                connected_peers: connected_peers,
+                overlay_peers: overlay_peers,
+                accounting_peers: accounting_peers,
+                ongoing_refreshments: ongoing_refreshments,
+                chunk_store: Mutex::new(ChunkLru::default()),
+                leaf_cache: Mutex::new(ChunkLru::default()),
+                verified_peers: Mutex::new(HashMap::new()),
+            }),
+            retrieval_config: Mutex::new(RetrievalConfig::default()),
+            #[cfg(feature = "manual-clock")]
+            manual_clock: Mutex::new(None),
+            neighborhood_depth: Mutex::new(DEFAULT_NEIGHBORHOOD_DEPTH),
+            chunk_store_capacity: Mutex::new(DEFAULT_CHUNK_STORE_CAPACITY),
+            on_chunk_stored: Mutex::new(None),
+            on_handshake_error: Mutex::new(None),
+            on_protocol_trace: Mutex::new(None),
+            bootnode_address: Mutex::new(BOOTNODE_ADDRESS.to_string()),
+            relay_addresses: Mutex::new(vec![]),
+            imported_peers: Mutex::new(HashMap::new()),
+            bootstrap_attempts: Mutex::new(DEFAULT_BOOTSTRAP_ATTEMPTS),
+            bootstrap_attempts_made: Mutex::new(0),
+            bootstrap_error: Mutex::new(None),
+            dns_resolver: Mutex::new(DNS_RESOLVER_URL.to_string()),
+            max_established: Mutex::new(DEFAULT_MAX_ESTABLISHED),
+            max_pending: Mutex::new(DEFAULT_MAX_PENDING),
+            network_id: DEFAULT_NETWORK_ID,
+            handshake_timeout_ms: Mutex::new(DEFAULT_HANDSHAKE_TIMEOUT_MS),
+            handshake_protocols: Mutex::new(vec![HANDSHAKE_PROTOCOL]),
+            connect_timeout_ms: Mutex::new(DEFAULT_CONNECT_TIMEOUT_MS),
+            ready_peers: Mutex::new(0),
+            rebootstrap: Mutex::new(None),
+            shutdown: Mutex::new(false),
+            serving: Mutex::new(false),
+            full_node: Mutex::new(false),
+            external_addresses: Mutex::new(vec![]),
+            permissioned_serving: Mutex::new(false),
+            bytes_served: Mutex::new(0),
+            active_retrievals: Mutex::new(HashMap::new()),
+            next_retrieval_id: Mutex::new(0),
+            feed_watches: Mutex::new(HashMap::new()),
+            next_feed_watch_id: Mutex::new(0),
+            connection_stats: Mutex::new(HashMap::new()),
+            behaviour_config,
+            message_port: (m_out, m_in),
+        };
     }
 }
 
 #[derive(NetworkBehaviour)]
 struct Behaviour {
-    autonat: autonat::v2::client::Behaviour,
-    autonat_s: autonat::v2::server::Behaviour,
-    dcutr: dcutr::Behaviour,
-    identify: identify::Behaviour,
-    ping: ping::Behaviour,
+    limits: connection_limits::Behaviour,
+    autonat: Toggle<autonat::v2::client::Behaviour>,
+    autonat_s: Toggle<autonat::v2::server::Behaviour>,
+    dcutr: Toggle<dcutr::Behaviour>,
+    identify: Toggle<identify::Behaviour>,
+    ping: Toggle<ping::Behaviour>,
+    relay: Toggle<relay::client::Behaviour>,
     stream: stream::Behaviour,
 }
 
+// connection limits and the stream behaviour are always present, everything else can be left out
+// through the protocols object of the builder config
+
+#[derive(Debug, Clone, Copy)]
+struct BehaviourConfig {
+    autonat: bool,
+    dcutr: bool,
+    identify: bool,
+    ping: bool,
+    relay: bool,
+    gossip: bool,
+    pricing: bool,
+    pushsync: bool,
+}
+
+impl Default for BehaviourConfig {
+    fn default() -> Self {
+        Self {
+            autonat: true,
+            dcutr: true,
+            identify: true,
+            ping: true,
+            relay: true,
+            gossip: true,
+            pricing: true,
+            pushsync: true,
+        }
+    }
+}
+
+fn relay_dial(swarm: &mut Swarm<Behaviour>, peer: PeerId, relays: &Mutex<Vec<Multiaddr>>) {
+    for relay in relays.lock_recover().iter() {
+        if try_from_multiaddr(relay) == Some(peer) {
+            continue;
+        }
+        if swarm.dial(relayed_address(relay, peer)).is_ok() {
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Dialing {} through relay {}",
+                peer, relay
+            )));
+            return;
+        }
+    }
+}
+
+fn limits_config(max_established: u32, max_pending: u32) -> ConnectionLimits {
+    ConnectionLimits::default()
+        .with_max_established(Some(max_established))
+        .with_max_pending_outgoing(Some(max_pending))
+}
+
 impl Behaviour {
-    fn new(local_public_key: identity::PublicKey) -> Self {
+    fn new(
+        local_public_key: identity::PublicKey,
+        relay_client: relay::client::Behaviour,
+        protocol_version: String,
+        agent_version: String,
+        config: &BehaviourConfig,
+    ) -> Self {
         Self {
-            autonat: autonat::v2::client::Behaviour::new(
-                OsRng,
-                autonat::v2::client::Config::default().with_probe_interval(Duration::from_secs(60)),
+            limits: connection_limits::Behaviour::new(limits_config(
+                DEFAULT_MAX_ESTABLISHED,
+                DEFAULT_MAX_PENDING,
+            )),
+            autonat: Toggle::from(config.autonat.then(|| {
+                autonat::v2::client::Behaviour::new(
+                    OsRng,
+                    autonat::v2::client::Config::default()
+                        .with_probe_interval(Duration::from_secs(60)),
+                )
+            })),
+            autonat_s: Toggle::from(
+                config
+                    .autonat
+                    .then(|| autonat::v2::server::Behaviour::new(OsRng)),
             ),
-            autonat_s: autonat::v2::server::Behaviour::new(OsRng),
-            dcutr: dcutr::Behaviour::new(local_public_key.to_peer_id()),
-            identify: identify::Behaviour::new(
-                identify::Config::new("/weeb-3".into(), local_public_key.clone())
-                    .with_push_listen_addr_updates(true)
-                    .with_interval(Duration::from_secs(60)), // .with_cache_size(10), //
+            dcutr: Toggle::from(
+                config
+                    .dcutr
+                    .then(|| dcutr::Behaviour::new(local_public_key.to_peer_id())),
             ),
-            ping: ping::Behaviour::new(ping::Config::new().with_interval(Duration::from_secs(50))),
+            identify: Toggle::from(config.identify.then(|| {
+                identify::Behaviour::new(
+                    identify::Config::new(protocol_version, local_public_key.clone())
+                        .with_agent_version(agent_version)
+                        .with_push_listen_addr_updates(true)
+                        .with_interval(Duration::from_secs(60)), // .with_cache_size(10), //
+                )
+            })),
+            ping: Toggle::from(config.ping.then(|| {
+                ping::Behaviour::new(ping::Config::new().with_interval(Duration::from_secs(50)))
+            })),
+            relay: Toggle::from(config.relay.then_some(relay_client)),
             stream: stream::Behaviour::new(),
         }
     }
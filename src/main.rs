@@ -8,6 +8,7 @@ use std::time::Duration;
 
 use tower_http::cors::{Any, CorsLayer};
 
+use axum::extract::State;
 use axum::http::header::CONTENT_TYPE;
 use axum::http::StatusCode;
 use axum::response::{Html, IntoResponse};
@@ -76,6 +77,29 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[derive(Clone)]
+struct ServerConfig {
+    network_id: u64,
+    welcome_message: String,
+}
+
+impl ServerConfig {
+    fn from_env() -> Self {
+        let network_id = std::env::var("WEEB_3_NETWORK_ID")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(10_u64);
+
+        let welcome_message =
+            std::env::var("WEEB_3_WELCOME").unwrap_or("... Ara Ara ...".to_string());
+
+        Self {
+            network_id,
+            welcome_message,
+        }
+    }
+}
+
 #[derive(rust_embed::RustEmbed)]
 #[folder = "$CARGO_MANIFEST_DIR/static"]
 struct StaticFiles;
@@ -89,6 +113,8 @@ pub(crate) async fn serve(libp2p_transport: Multiaddr) {
         .await
         .unwrap();
 
+    let server_config = ServerConfig::from_env();
+
     let server = Router::new()
         .route("/", get(get_index))
         .route("/index.html", get(get_index))
@@ -104,7 +130,8 @@ pub(crate) async fn serve(libp2p_transport: Multiaddr) {
             CorsLayer::new()
                 .allow_origin(Any)
                 .allow_methods([Method::GET]),
-        );
+        )
+        .with_state(server_config);
 
     let socket = SocketAddr::new(IpAddr::V4(listen_addr), 8080);
 
@@ -114,18 +141,34 @@ pub(crate) async fn serve(libp2p_transport: Multiaddr) {
         .unwrap();
 }
 
-async fn get_index() -> Result<Html<String>, StatusCode> {
+async fn get_index(State(config): State<ServerConfig>) -> Result<Html<String>, StatusCode> {
     let content = StaticFiles::get("index.html")
         .ok_or(StatusCode::NOT_FOUND)?
         .data;
 
     let html = std::str::from_utf8(&content)
         .expect("index.html to be valid utf8")
-        .to_string();
+        .replace("__NETWORK_ID__", &config.network_id.to_string())
+        .replace("__WELCOME__", &escape_html(&config.welcome_message));
 
     Ok(Html(html))
 }
 
+fn escape_html(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
 async fn get_static_file_weeb_3_js() -> Result<impl IntoResponse, StatusCode> {
     let content = StaticFiles::get("weeb_3.js")
         .ok_or(StatusCode::NOT_FOUND)?
@@ -1,12 +1,17 @@
+use std::collections::HashSet;
 use std::sync::mpsc;
 
 use crate::{
     //
     get_data,
     //
-    seek_latest_feed_update,
+    seek_latest,
+    //
+    FeedSearch,
     //
     JsValue,
+    //
+    RetrievalScope,
 };
 
 use serde_json::Value;
@@ -17,39 +22,193 @@ pub struct Fork {
     pub mime: String,
     // pub filename: String,
     pub path: String,
+    pub encoding: String,
+    // address of the file content, set when the body was left to be fetched on request
+    pub reference: Vec<u8>,
+}
+
+// a node entered twice along one path or a traversal past this depth fails the walk,
+// so a cyclic or adversarially deep manifest cannot hold a request indefinitely
+
+pub const MANIFEST_MAX_DEPTH: usize = 128;
+
+// nodes shared by several forks are walked once per fork, a walk that enters already visited
+// nodes more often than this fails, so a manifest folding one node into exponentially many
+// paths cannot hold a request either
+
+pub const MANIFEST_MAX_REVISITS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManifestError {
+    DepthExceeded,
+    Cycle,
+    Malformed,
+    TooLarge,
+}
+
+// the nodes on the current path and every node entered so far by one walk
+
+#[derive(Default)]
+struct ManifestWalk {
+    ancestors: Vec<Vec<u8>>,
+    visited: HashSet<Vec<u8>>,
+    revisits: usize,
+}
+
+pub const MANIFEST_NODE_CACHE_CAPACITY: usize = 4096;
+
+// keccak256 of the mantaray version strings, truncated to the 31 bytes stored in a node
+
+pub const MANTARAY_VERSION_HASHES: [&str; 2] = [
+    "5768b3b6a7db56d21d1abff40d41cebfc83448fed8d7e9b06ec0d3b073f28f",
+    "025184789d63635766d78c41900196b57d7400875ebe4d9b5d1e76bd9652a9",
+];
+
+// a root node is [span: 8][obfuscation key: 32][version hash: 31][reference size: 1]...,
+// with everything past the key xored against it
+
+pub fn is_manifest(cd0: &[u8]) -> bool {
+    if cd0.len() < 72 {
+        return false;
+    }
+
+    let obfuscation_key = &cd0[8..40];
+    let mf_version: Vec<u8> = cd0[40..71]
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ obfuscation_key[i % obfuscation_key.len()])
+        .collect();
+
+    MANTARAY_VERSION_HASHES.contains(&hex::encode(mf_version).as_str())
+}
+
+// nodes are served from the session cache when the scope carries one, anything fetched that
+// parses as a manifest node is kept for later lookups, pinning scopes always fetch so every
+// node ends up pinned
+
+pub async fn get_manifest_node(
+    address: &Vec<u8>,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Vec<u8> {
+    if let Some((cache, _)) = scope.manifest_nodes() {
+        if scope.pin_root().is_none() {
+            if let Some(node) = cache.get(address) {
+                return node;
+            }
+        }
+    }
+
+    let node = get_data(address.to_vec(), data_retrieve_chan, scope).await;
+
+    if let Some((cache, capacity)) = scope.manifest_nodes() {
+        if is_manifest(&node) {
+            cache.insert(address, &node, *capacity);
+        }
+    }
+
+    node
 }
 
 pub async fn interpret_manifest(
     path_prefix_heritance: String,
     cd0: &Vec<u8>,
-    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>)>,
-) -> (Vec<Fork>, String) {
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Result<(Vec<Fork>, String, String), ManifestError> {
+    walk_manifest(
+        path_prefix_heritance,
+        cd0,
+        data_retrieve_chan,
+        scope,
+        &mut ManifestWalk::default(),
+        true,
+    )
+    .await
+}
+
+// lists the entries with their references only, so a site is looked up without downloading
+// every file in it
+
+pub async fn list_manifest(
+    path_prefix_heritance: String,
+    cd0: &Vec<u8>,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Result<(Vec<Fork>, String, String), ManifestError> {
+    walk_manifest(
+        path_prefix_heritance,
+        cd0,
+        data_retrieve_chan,
+        scope,
+        &mut ManifestWalk::default(),
+        false,
+    )
+    .await
+}
+
+// the body of a listed entry, fetched now when the listing left it out
+
+pub async fn fork_body(
+    fork: &Fork,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Vec<u8> {
+    match fork.data.is_empty() && fork.reference.len() > 0 {
+        true => get_data(fork.reference.clone(), data_retrieve_chan, scope).await,
+        false => fork.data.clone(),
+    }
+}
+
+async fn walk_manifest(
+    path_prefix_heritance: String,
+    cd0: &Vec<u8>,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+    walk: &mut ManifestWalk,
+    bodies: bool,
+) -> Result<(Vec<Fork>, String, String), ManifestError> {
     let mut ind: String = "".to_string();
     let mut ind_set = false;
+    let mut err_doc: String = "".to_string();
     let mut manifest_encrypted = false;
 
     if cd0.len() == 0 {
-        return (
+        return Ok((
             vec![Fork {
                 data: vec![],
                 mime: "undefined".to_string(),
                 // filename: "not found".to_string(),
                 path: "not found".to_string(),
+                encoding: "".to_string(),
+                reference: vec![],
             }],
             ind,
-        );
+            err_doc,
+        ));
+    }
+
+    if walk.ancestors.len() > MANIFEST_MAX_DEPTH {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Manifest traversal aborted beyond depth {} at {}",
+            MANIFEST_MAX_DEPTH, path_prefix_heritance
+        )));
+        return Err(ManifestError::DepthExceeded);
     }
 
     if cd0.len() < 72 {
-        return (
+        return Ok((
             vec![Fork {
                 data: cd0.to_vec(),
                 mime: "application/octet-stream".to_string(),
                 // filename: "unknown00".to_string(),
                 path: "unknown00".to_string(),
+                encoding: "".to_string(),
+                reference: vec![],
             }],
             ind,
-        );
+            err_doc,
+        ));
     }
 
     let obfuscation_key = &cd0[8..40];
@@ -86,23 +245,24 @@ pub async fn interpret_manifest(
     let mf_version = &cd[40..71];
     let enc_mf_version = hex::encode(mf_version);
 
-    if enc_mf_version != "5768b3b6a7db56d21d1abff40d41cebfc83448fed8d7e9b06ec0d3b073f28f"
-        && enc_mf_version != "025184789d63635766d78c41900196b57d7400875ebe4d9b5d1e76bd9652a9"
-    {
-        return (
+    if !MANTARAY_VERSION_HASHES.contains(&enc_mf_version.as_str()) {
+        return Ok((
             vec![Fork {
                 data: cd.to_vec(),
                 mime: "application/octet-stream".to_string(),
                 // filename: "unknown01".to_string(),
                 path: "unknown01".to_string(),
+                encoding: "".to_string(),
+                reference: vec![],
             }],
             ind,
-        );
+            err_doc,
+        ));
     }
 
-    let ref_size = cd[71];
-    let ref_delimiter = (72 + ref_size) as usize;
-    let index_delimiter = (ref_delimiter + 32) as usize;
+    let ref_size = cd[71] as usize;
+    let ref_delimiter = 72 + ref_size;
+    let index_delimiter = ref_delimiter + 32;
 
     // fork parts
 
@@ -112,6 +272,15 @@ pub async fn interpret_manifest(
 
     while cd.len() > fork_start_current {
         let fork_start = fork_start_current;
+        let fork_prefix_delimiter = fork_start + 32;
+        let fork_reference_delimiter = fork_prefix_delimiter + ref_size;
+
+        // a fork cut short or with a prefix past its 30 bytes is not read any further
+
+        if cd.len() < fork_reference_delimiter || cd[fork_start + 1] > 30 {
+            return Err(ManifestError::Malformed);
+        }
+
         let fork_type = cd[fork_start_current];
 
         let fork_prefix_length = cd[fork_start_current + 1];
@@ -120,24 +289,44 @@ pub async fn interpret_manifest(
 
         let string_fork_prefix = String::from_utf8(fork_prefix.to_vec()).unwrap_or("".to_string());
 
-        let fork_prefix_delimiter = fork_start + 32;
-        let fork_reference_delimiter = fork_prefix_delimiter + (ref_size as usize);
         let fork_reference = &cd[fork_prefix_delimiter..fork_reference_delimiter];
 
-        let ref_data = get_data(fork_reference.to_vec(), data_retrieve_chan).await;
+        if walk.ancestors.iter().any(|a| a == fork_reference) {
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Manifest cycle at {} through {}",
+                path_prefix_heritance,
+                hex::encode(fork_reference)
+            )));
+            return Err(ManifestError::Cycle);
+        }
+
+        if !walk.visited.insert(fork_reference.to_vec()) {
+            walk.revisits += 1;
+            if walk.revisits > MANIFEST_MAX_REVISITS {
+                web_sys::console::log_1(&JsValue::from(format!(
+                    "Manifest traversal aborted after {} revisited nodes at {}",
+                    MANIFEST_MAX_REVISITS, path_prefix_heritance
+                )));
+                return Err(ManifestError::TooLarge);
+            }
+        }
+
+        let ref_data = get_manifest_node(&fork_reference.to_vec(), data_retrieve_chan, scope).await;
 
         if fork_type & 16 == 16 {
             let fork_metadata_bytesize: [u8; 2] = cd
-                [fork_reference_delimiter..fork_reference_delimiter + 2]
-                .try_into()
-                .unwrap();
+                .get(fork_reference_delimiter..fork_reference_delimiter + 2)
+                .and_then(|size| size.try_into().ok())
+                .ok_or(ManifestError::Malformed)?;
 
             let calc_metadata_bytesize = u16::from_be_bytes(fork_metadata_bytesize) as usize;
 
             let fork_metadata_delimiter = fork_reference_delimiter + 2 + calc_metadata_bytesize;
             fork_start_current = fork_metadata_delimiter;
 
-            let fork_metadata = &cd[fork_reference_delimiter + 2..fork_metadata_delimiter];
+            let fork_metadata = cd
+                .get(fork_reference_delimiter + 2..fork_metadata_delimiter)
+                .ok_or(ManifestError::Malformed)?;
             let enc_fork_metadata = hex::encode(fork_metadata);
             web_sys::console::log_1(&JsValue::from(format!(
                 "fork_metadata: {}",
@@ -154,12 +343,12 @@ pub async fn interpret_manifest(
             let str0f0 = v1.get("swarm-feed-owner");
             match str0f0 {
                 Some(str0f0) => {
-                    owner = str0f0.as_str().unwrap().to_string();
+                    owner = str0f0.as_str().unwrap_or("").to_string();
                     let str0f1 = v1.get("swarm-feed-topic");
                     match str0f1 {
                         Some(str0f1) => {
-                            topic = str0f1.as_str().unwrap().to_string();
-                            feed = true;
+                            topic = str0f1.as_str().unwrap_or("").to_string();
+                            feed = owner.len() > 0 && topic.len() > 0;
                         }
                         _ => (),
                     }
@@ -167,48 +356,90 @@ pub async fn interpret_manifest(
                 _ => (),
             };
 
+            let feed_key = [owner.as_bytes(), topic.as_bytes()].concat();
+            if feed && walk.ancestors.contains(&feed_key) {
+                web_sys::console::log_1(&JsValue::from(format!(
+                    "Manifest feed cycle at {} through {}/{}",
+                    path_prefix_heritance, owner, topic
+                )));
+                return Err(ManifestError::Cycle);
+            }
+
             if feed {
-                let feed_data_soc =
-                    seek_latest_feed_update(owner, topic, data_retrieve_chan, 8).await;
+                let (_feed_type, feed_data_content) = seek_latest(
+                    owner,
+                    topic,
+                    data_retrieve_chan,
+                    scope,
+                    FeedSearch::from_redundancy(8),
+                    true,
+                )
+                .await;
 
-                let feed_data_content =
-                    get_data(feed_data_soc[16..48].to_vec(), data_retrieve_chan).await;
+                // the feed content was reached through a soc reference, so anything it leads to
+                // is one follow deeper
 
-                let (mut appendix_0, _nondiscard) = Box::pin(interpret_manifest(
+                walk.ancestors.push(feed_key);
+                let (mut appendix_0, _nondiscard, _nondiscard_err) = Box::pin(walk_manifest(
                     "".to_string(),
                     &feed_data_content,
                     data_retrieve_chan,
+                    &scope.following_soc(),
+                    walk,
+                    bodies,
                 ))
-                .await;
+                .await?;
+                walk.ancestors.pop();
 
                 if !ind_set {
                     ind = _nondiscard;
                     ind_set = true;
                 }
 
+                if err_doc.len() == 0 {
+                    err_doc = _nondiscard_err;
+                }
+
                 parts.append(&mut appendix_0);
             }
 
             let str0i = v1.get("website-index-document");
             match str0i {
                 Some(str0i) => {
-                    ind = str0i.as_str().unwrap().to_string();
+                    ind = str0i.as_str().unwrap_or("").to_string();
                     ind_set = true;
                 }
                 _ => (),
             };
 
+            let str0e = v1.get("website-error-document");
+            match str0e {
+                Some(str0e) => {
+                    err_doc = str0e.as_str().unwrap_or("").to_string();
+                }
+                _ => (),
+            };
+
             let str0 = v1.get("Content-Type");
 
             let str1 = match str0 {
-                Some(str0) => str0.as_str().unwrap(),
+                Some(str0) => str0.as_str().ok_or(ManifestError::Malformed)?,
                 _ => {
                     let mut bequeath: String = String::new();
                     bequeath.push_str(&path_prefix_heritance);
                     bequeath.push_str(&string_fork_prefix);
 
-                    let (mut appendix_0, _discard) =
-                        Box::pin(interpret_manifest(bequeath, &ref_data, data_retrieve_chan)).await;
+                    walk.ancestors.push(fork_reference.to_vec());
+                    let (mut appendix_0, _discard, _discard_err) = Box::pin(walk_manifest(
+                        bequeath,
+                        &ref_data,
+                        data_retrieve_chan,
+                        scope,
+                        walk,
+                        bodies,
+                    ))
+                    .await?;
+                    walk.ancestors.pop();
                     parts.append(&mut appendix_0);
                     continue;
                 }
@@ -216,6 +447,11 @@ pub async fn interpret_manifest(
 
             // let str2 = v1.get("Filename").unwrap().as_str().unwrap();
 
+            let encoding_0 = match v1.get("Content-Encoding") {
+                Some(str0e) => str0e.as_str().unwrap_or("").to_string(),
+                _ => "".to_string(),
+            };
+
             let mime_0 = str1.to_string();
             // let filename_0 = str2.to_string();
             if ref_data.len() > 71 {
@@ -257,7 +493,12 @@ pub async fn interpret_manifest(
                         actual_data_address = ref_data0[72..72 + (ref_size_a as usize)].to_vec();
                     }
 
-                    let actual_data = get_data(actual_data_address, data_retrieve_chan).await;
+                    let actual_data = match bodies {
+                        true => {
+                            get_data(actual_data_address.clone(), data_retrieve_chan, scope).await
+                        }
+                        false => vec![],
+                    };
 
                     let mut path_0: String = String::new();
                     path_0.push_str(&path_prefix_heritance);
@@ -268,21 +509,32 @@ pub async fn interpret_manifest(
                         mime: mime_0,
                         // filename: filename_0,
                         path: path_0,
+                        encoding: encoding_0,
+                        reference: actual_data_address,
                     });
                 }
             }
         }
 
         if fork_type & 16 == 0 {
-            fork_start_current = fork_start + 32 + (ref_size as usize);
+            fork_start_current = fork_start + 32 + ref_size;
             let mut bequeath: String = String::new();
             bequeath.push_str(&path_prefix_heritance);
             bequeath.push_str(&string_fork_prefix);
-            let (mut appendix_0, _discard) =
-                Box::pin(interpret_manifest(bequeath, &ref_data, data_retrieve_chan)).await;
+            walk.ancestors.push(fork_reference.to_vec());
+            let (mut appendix_0, _discard, _discard_err) = Box::pin(walk_manifest(
+                bequeath,
+                &ref_data,
+                data_retrieve_chan,
+                scope,
+                walk,
+                bodies,
+            ))
+            .await?;
+            walk.ancestors.pop();
             parts.append(&mut appendix_0);
         }
     }
 
-    return (parts, ind);
+    return Ok((parts, ind, err_doc));
 }
@@ -0,0 +1,79 @@
+#![cfg(target_arch = "wasm32")]
+
+// protocol messages by role, the etiquette_* modules mirror the protobuf packages
+
+// stream headers exchanged before every protocol message
+
+pub use crate::weeb_3::etiquette_0::{Header, Headers};
+
+// handshake
+
+pub use crate::weeb_3::etiquette_1::{Ack, BzzAddress as HandshakeAddress, Syn, SynAck};
+
+// hive peer gossip
+
+pub use crate::weeb_3::etiquette_2::{BzzAddress as GossipAddress, Peers};
+
+// pingpong
+
+pub use crate::weeb_3::etiquette_3::{Ping, Pong};
+
+// pricing and pseudosettle
+
+pub use crate::weeb_3::etiquette_4::AnnouncePaymentThreshold;
+pub use crate::weeb_3::etiquette_5::{Payment, PaymentAck};
+
+// retrieval
+
+pub use crate::weeb_3::etiquette_6::{Delivery as RetrievalDelivery, Request as RetrievalRequest};
+
+// pushsync
+
+pub use crate::weeb_3::etiquette_7::{Delivery as PushDelivery, Receipt as PushReceipt};
+
+impl Header {
+    pub fn new(key: &str, value: Vec<u8>) -> Self {
+        Self {
+            key: key.to_string(),
+            value,
+        }
+    }
+}
+
+impl Syn {
+    pub fn with_underlay(observed_underlay: Vec<u8>) -> Self {
+        Self { observed_underlay }
+    }
+}
+
+impl Ping {
+    pub fn with_greeting(greeting: &str) -> Self {
+        Self {
+            greeting: greeting.to_string(),
+        }
+    }
+}
+
+impl Payment {
+    pub fn with_amount(amount: Vec<u8>) -> Self {
+        Self { amount }
+    }
+}
+
+impl RetrievalRequest {
+    pub fn for_address(chunk_address: Vec<u8>) -> Self {
+        Self {
+            addr: chunk_address,
+        }
+    }
+}
+
+impl PushDelivery {
+    pub fn new(chunk_address: Vec<u8>, chunk_content: Vec<u8>, chunk_stamp: Vec<u8>) -> Self {
+        Self {
+            address: chunk_address,
+            data: chunk_content,
+            stamp: chunk_stamp,
+        }
+    }
+}
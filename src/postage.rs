@@ -0,0 +1,264 @@
+#![cfg(target_arch = "wasm32")]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use alloy::primitives::keccak256;
+use alloy::primitives::{normalize_v, PrimitiveSignature as Signature};
+
+use crate::LockRecover;
+
+// stamp layout: [batch id: 32][bucket: u32 be][index: u32 be][timestamp: u64 be][signature: 65]
+
+pub const STAMP_SIZE: usize = 113;
+pub const BUCKET_DEPTH: u8 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostageStamp {
+    pub batch_id: Vec<u8>,
+    pub bucket: u32,
+    pub index: u32,
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+// chunks are spread over 2^BUCKET_DEPTH buckets by the leading bits of their address
+
+pub fn chunk_bucket(chunk_address: &[u8]) -> u32 {
+    if chunk_address.len() < 4 {
+        return 0;
+    }
+    u32::from_be_bytes([
+        chunk_address[0],
+        chunk_address[1],
+        chunk_address[2],
+        chunk_address[3],
+    ]) >> (32 - BUCKET_DEPTH)
+}
+
+impl PostageStamp {
+    pub fn from_bytes(stamp: &[u8]) -> Option<Self> {
+        if stamp.len() != STAMP_SIZE {
+            return None;
+        }
+
+        Some(Self {
+            batch_id: stamp[0..32].to_vec(),
+            bucket: u32::from_be_bytes(stamp[32..36].try_into().ok()?),
+            index: u32::from_be_bytes(stamp[36..40].try_into().ok()?),
+            timestamp: u64::from_be_bytes(stamp[40..48].try_into().ok()?),
+            signature: stamp[48..113].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut stamp = Vec::with_capacity(STAMP_SIZE);
+        stamp.extend_from_slice(&self.batch_id);
+        stamp.extend_from_slice(&self.bucket.to_be_bytes());
+        stamp.extend_from_slice(&self.index.to_be_bytes());
+        stamp.extend_from_slice(&self.timestamp.to_be_bytes());
+        stamp.extend_from_slice(&self.signature);
+        stamp
+    }
+
+    // the batch owner signs keccak256(chunk address | batch id | bucket and index | timestamp)
+    // as an ethereum message
+
+    pub fn digest(&self, chunk_address: &[u8]) -> Vec<u8> {
+        keccak256(
+            [
+                chunk_address,
+                &self.batch_id,
+                &self.bucket.to_be_bytes(),
+                &self.index.to_be_bytes(),
+                &self.timestamp.to_be_bytes(),
+            ]
+            .concat(),
+        )
+        .to_vec()
+    }
+
+    pub fn signer(&self, chunk_address: &[u8]) -> Option<Vec<u8>> {
+        if self.signature.len() != 65 || chunk_address.len() != 32 {
+            return None;
+        }
+
+        let parity: bool = match normalize_v(self.signature[64] as u64) {
+            Some(par) => par,
+            _ => {
+                return None;
+            }
+        };
+        let sig = Signature::from_bytes_and_parity(&self.signature[0..64], parity);
+
+        match sig.recover_address_from_msg(self.digest(chunk_address)) {
+            Ok(signer) => Some(signer.as_slice().to_vec()),
+            _ => None,
+        }
+    }
+
+    pub fn verify(&self, chunk_address: &[u8], owner: &[u8]) -> bool {
+        self.bucket == chunk_bucket(chunk_address)
+            && self.signer(chunk_address).as_deref() == Some(owner)
+    }
+
+    // a batch of depth d holds 2^(d - BUCKET_DEPTH) slots in every bucket
+
+    pub fn valid_index(&self, depth: u8) -> bool {
+        if depth < BUCKET_DEPTH {
+            return false;
+        }
+        u64::from(self.index) < 1_u64 << (depth - BUCKET_DEPTH).min(32)
+    }
+}
+
+// a stamp of a tracked batch is valid or invalid, one of an untracked batch can only be
+// found well formed, which says nothing about who may spend the batch
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StampVerdict {
+    Valid,
+    Invalid,
+    Unverified,
+}
+
+impl StampVerdict {
+    // when stamps are required only stamps of tracked batches are good enough
+
+    pub fn accepted(self, require_stamp: bool) -> bool {
+        match self {
+            StampVerdict::Valid => true,
+            StampVerdict::Unverified => !require_stamp,
+            StampVerdict::Invalid => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchInfo {
+    pub owner: Vec<u8>,
+    pub depth: u8,
+}
+
+// batches learned from the chain or configured by the application, shared by every clone
+
+#[derive(Debug, Clone, Default)]
+pub struct BatchRegistry {
+    batches: Arc<Mutex<HashMap<Vec<u8>, BatchInfo>>>,
+}
+
+impl BatchRegistry {
+    pub fn track(&self, batch_id: Vec<u8>, owner: Vec<u8>, depth: u8) {
+        self.batches
+            .lock_recover()
+            .insert(batch_id, BatchInfo { owner, depth });
+    }
+
+    pub fn forget(&self, batch_id: &Vec<u8>) -> bool {
+        self.batches.lock_recover().remove(batch_id).is_some()
+    }
+
+    pub fn get(&self, batch_id: &Vec<u8>) -> Option<BatchInfo> {
+        self.batches.lock_recover().get(batch_id).cloned()
+    }
+
+    // stamps of tracked batches must match the owner and fit the depth, stamps of unknown
+    // batches can only be checked for a well formed signature over this chunk
+
+    pub fn verify(&self, stamp: &PostageStamp, chunk_address: &[u8]) -> StampVerdict {
+        let verified = match self.get(&stamp.batch_id) {
+            Some(batch) => {
+                stamp.verify(chunk_address, &batch.owner) && stamp.valid_index(batch.depth)
+            }
+            None => {
+                return match stamp.bucket == chunk_bucket(chunk_address)
+                    && stamp.signer(chunk_address).is_some()
+                {
+                    true => StampVerdict::Unverified,
+                    false => StampVerdict::Invalid,
+                }
+            }
+        };
+
+        match verified {
+            true => StampVerdict::Valid,
+            false => StampVerdict::Invalid,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    // signed independently of alloy with key 634fb5a8..., whose ethereum address is the owner,
+    // over keccak256("weeb-3 chunk") with batch keccak256("weeb-3 batch"), index 3
+
+    const OWNER: &str = "8d3766440f0d7b949a5e32995d09619a7f86e632";
+    const CHUNK: &str = "b1afd52d68b63c63ed1d8c9d723b1f6be16555fc3121d4d5a1888906caa72b90";
+    const BATCH: &str = "79afe985c0617f77221ca65023082fddfe0e79473f4b07a248e16654ef6a03ea";
+    const DIGEST: &str = "3c61469b0c3a6ecd714ce1579820f0c5b0e753140bb8bc2574706a49fa620540";
+    const STAMP: &str = "79afe985c0617f77221ca65023082fddfe0e79473f4b07a248e16654ef6a03ea\
+                         0000b1af0000000317979cfe362a00001002c07b9b134b0748b360d466fe7678fb\
+                         40cb28002b0509746d619dcacc0a8c5ed6de572f8d007b677cfd17ed1902e33f14\
+                         aa6afa4b669bf9f87268cfeb2e8c1c";
+
+    fn reference_stamp() -> PostageStamp {
+        PostageStamp::from_bytes(&hex::decode(STAMP).unwrap()).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn stamp_signer_matches_reference() {
+        let stamp = reference_stamp();
+        let chunk = hex::decode(CHUNK).unwrap();
+
+        assert_eq!(hex::encode(&stamp.batch_id), BATCH);
+        assert_eq!(stamp.bucket, chunk_bucket(&chunk));
+        assert_eq!(stamp.index, 3);
+        assert_eq!(hex::encode(stamp.digest(&chunk)), DIGEST);
+        assert_eq!(hex::encode(stamp.signer(&chunk).unwrap()), OWNER);
+        assert_eq!(hex::encode(stamp.to_bytes()), STAMP);
+    }
+
+    #[wasm_bindgen_test]
+    fn stamp_is_held_to_its_bucket_and_index() {
+        let owner = hex::decode(OWNER).unwrap();
+        let chunk = hex::decode(CHUNK).unwrap();
+        let stamp = reference_stamp();
+        assert!(stamp.verify(&chunk, &owner));
+
+        // the signature stays valid for the stamp as signed, so another chunk recovers a
+        // different signer and a moved bucket falls outside the chunk
+        let mut other = chunk.clone();
+        other[31] ^= 1;
+        assert!(!stamp.verify(&other, &owner));
+        let mut moved = stamp.clone();
+        moved.bucket += 1;
+        assert!(!moved.verify(&chunk, &owner));
+
+        // index 3 needs at least four slots per bucket
+        assert!(!stamp.valid_index(BUCKET_DEPTH - 1));
+        assert!(!stamp.valid_index(BUCKET_DEPTH + 1));
+        assert!(stamp.valid_index(BUCKET_DEPTH + 2));
+    }
+
+    #[wasm_bindgen_test]
+    fn untracked_batches_are_only_unverified() {
+        let chunk = hex::decode(CHUNK).unwrap();
+        let stamp = reference_stamp();
+        let registry = BatchRegistry::default();
+
+        assert_eq!(registry.verify(&stamp, &chunk), StampVerdict::Unverified);
+        assert!(registry.verify(&stamp, &chunk).accepted(false));
+        assert!(!registry.verify(&stamp, &chunk).accepted(true));
+
+        registry.track(stamp.batch_id.clone(), hex::decode(OWNER).unwrap(), 20);
+        assert_eq!(registry.verify(&stamp, &chunk), StampVerdict::Valid);
+        assert!(registry.verify(&stamp, &chunk).accepted(true));
+
+        registry.track(stamp.batch_id.clone(), vec![0; 20], 20);
+        assert_eq!(registry.verify(&stamp, &chunk), StampVerdict::Invalid);
+        assert!(!registry.verify(&stamp, &chunk).accepted(false));
+    }
+}
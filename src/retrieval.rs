@@ -2,48 +2,138 @@ use crate::{
     // // // // // // // //
     apply_credit,
     // // // // // // // //
+    begin_request,
+    // // // // // // // //
     cancel_reserve,
     // // // // // // // //
+    content_address,
+    // // // // // // // //
+    decode_feed_payload,
+    // // // // // // // //
+    delete_chunks,
+    // // // // // // // //
     encode_resources,
     // // // // // // // //
+    end_request,
+    // // // // // // // //
+    epoch_start,
+    // // // // // // // //
+    file_root_address,
+    // // // // // // // //
+    gateway_chunk,
+    // // // // // // // //
+    get_epoch_feed_address,
+    // // // // // // // //
     get_feed_address,
     // // // // // // // //
     get_proximity,
     // // // // // // // //
+    load_chunk,
+    // // // // // // // //
+    manifest::fork_body,
+    // // // // // // // //
+    manifest::get_manifest_node,
+    // // // // // // // //
     manifest::interpret_manifest,
     // // // // // // // //
+    manifest::is_manifest,
+    // // // // // // // //
+    manifest::list_manifest,
+    // // // // // // // //
+    manifest::ManifestError,
+    // // // // // // // //
     mpsc,
     // // // // // // // //
+    network_cache,
+    // // // // // // // //
+    normalize_feed_owner,
+    // // // // // // // //
+    overdraft_refresh,
+    // // // // // // // //
+    peer_score,
+    // // // // // // // //
     price,
     // // // // // // // //
+    record_delivery,
+    // // // // // // // //
+    record_latency,
+    // // // // // // // //
     reserve,
     // // // // // // // //
     retrieve_handler,
     // // // // // // // //
+    sign_request,
+    // // // // // // // //
+    soc_owner,
+    // // // // // // // //
+    sort_by_distance,
+    // // // // // // // //
+    store_chunk,
+    // // // // // // // //
     stream,
     // // // // // // // //
+    strip_span,
+    // // // // // // // //
     valid_cac,
     // // // // // // // //
+    valid_chunk_address,
+    // // // // // // // //
     valid_soc,
     // // // // // // // //
-    Date,
+    BandwidthMeter,
+    // // // // // // // //
+    ChunkKind,
+    // // // // // // // //
+    ChunkLru,
     // // // // // // // //
     Duration,
     // // // // // // // //
+    FeedPayloadType,
+    // // // // // // // //
+    FeedSearch,
+    // // // // // // // //
+    FeedType,
+    // // // // // // // //
     HashMap,
     // // // // // // // //
     HashSet,
     // // // // // // // //
     JsValue,
     // // // // // // // //
+    LockRecover,
+    // // // // // // // //
     Mutex,
     // // // // // // // //
+    OverlayAddress,
+    // // // // // // // //
     PeerAccounting,
     // // // // // // // //
     PeerId,
     // // // // // // // //
+    PostageStamp,
+    // // // // // // // //
+    RetrievalConfig,
+    // // // // // // // //
+    RetrievalError,
+    // // // // // // // //
+    RetrievalScope,
+    // // // // // // // //
+    RetrieveOutcome,
+    // // // // // // // //
+    SelectionStrategy,
+    // // // // // // // //
+    CHUNK_SIZE,
+    // // // // // // // //
+    EPOCH_MAX_LEVEL,
+    // // // // // // // //
+    LEAF_CHUNK_CACHE,
+    // // // // // // // //
+    REFERENCE_SIZE,
+    // // // // // // // //
     RETRIEVE_ROUND_TIME,
     // // // // // // // //
+    SPAN_SIZE,
+    // // // // // // // //
 };
 
 use byteorder::ByteOrder;
@@ -52,284 +142,1566 @@ use alloy::primitives::keccak256;
 
 use libp2p::futures::{stream::FuturesUnordered, StreamExt};
 
+pub const LEAF_CACHE_CAPACITY: usize = 16384;
+pub const FEED_FALLBACK_STEPS: u64 = 3;
+pub const SHARED_FETCH_WAIT_MS: u64 = 50;
+pub const DEFAULT_MAX_JOIN_FANOUT: usize = 32;
+
+// everything the retrieval walk needs from the network, a stream control in the client,
+// an in memory chunk store with seeded peers when the walk is exercised without a swarm
+
+#[allow(async_fn_in_trait)]
+pub trait ChunkTransport: Clone {
+    async fn fetch_chunk(
+        &mut self,
+        peer: PeerId,
+        chunk_address: Vec<u8>,
+        signature: &Vec<u8>,
+        chan: &mpsc::Sender<(Vec<u8>, Vec<u8>)>,
+        bandwidth: &BandwidthMeter,
+    ) -> RetrieveOutcome;
+}
+
+impl ChunkTransport for stream::Control {
+    async fn fetch_chunk(
+        &mut self,
+        peer: PeerId,
+        chunk_address: Vec<u8>,
+        signature: &Vec<u8>,
+        chan: &mpsc::Sender<(Vec<u8>, Vec<u8>)>,
+        bandwidth: &BandwidthMeter,
+    ) -> RetrieveOutcome {
+        retrieve_handler(peer, chunk_address, signature, self, chan, bandwidth).await
+    }
+}
+
 pub async fn retrieve_resource(
     chunk_address: &Vec<u8>,
-    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>)>,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
 ) -> Vec<u8> {
-    let cd = get_data(chunk_address.to_vec(), data_retrieve_chan).await;
+    let cd = get_manifest_node(chunk_address, data_retrieve_chan, scope).await;
+
+    // a root that does not carry a mantaray version is handed back as the raw file it is
+
+    if cd.len() > SPAN_SIZE && !is_manifest(&cd) {
+        return encode_resources(
+            vec![(
+                strip_span(&cd).unwrap_or_default(),
+                "application/octet-stream".to_string(),
+                "".to_string(),
+                "".to_string(),
+            )],
+            "".to_string(),
+            false,
+        );
+    }
+
+    let (data_vector, index, _error_document) =
+        match interpret_manifest("".to_string(), &cd, data_retrieve_chan, scope).await {
+            Ok(walked) => walked,
+            Err(e) => {
+                web_sys::console::log_1(&JsValue::from(format!(
+                    "Manifest {} not interpreted: {:?}",
+                    hex::encode(chunk_address),
+                    e
+                )));
+                return encode_resources(
+                    vec![(
+                        vec![],
+                        "not found".to_string(),
+                        format!("{:?}", e),
+                        "".to_string(),
+                    )],
+                    "".to_string(),
+                    true,
+                );
+            }
+        };
 
-    let (data_vector, index) = interpret_manifest("".to_string(), &cd, data_retrieve_chan).await;
-    let mut data_vector_e: Vec<(Vec<u8>, String, String)> = vec![];
+    web_sys::console::log_1(&JsValue::from(format!(
+        "{} chunk fetches shared within request {}",
+        scope.shared_fetches(),
+        scope.id()
+    )));
+    log_manifest_node_reuse(scope);
+
+    let mut data_vector_e: Vec<(Vec<u8>, String, String, String)> = vec![];
 
     for f in &data_vector {
-        if f.data.len() > 8 {
-            data_vector_e.push((f.data[8..].to_vec(), f.mime.clone(), f.path.clone()));
+        if let Some(content) = strip_span(&f.data) {
+            let (content, encoding) = decode_content(content, &f.encoding);
+            data_vector_e.push((content, f.mime.clone(), f.path.clone(), encoding));
         };
     }
 
     if data_vector_e.len() == 0 {
         return encode_resources(
-            vec![(vec![], "not found".to_string(), "not found".to_string())],
+            vec![(
+                vec![],
+                "not found".to_string(),
+                "not found".to_string(),
+                "".to_string(),
+            )],
             index,
+            true,
         );
     }
 
-    return encode_resources(data_vector_e, index);
+    return encode_resources(data_vector_e, index, true);
+}
+
+fn log_manifest_node_reuse(scope: &RetrievalScope) {
+    if let Some((cache, _)) = scope.manifest_nodes() {
+        let (hits, misses) = cache.stats();
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Manifest nodes this session: {} reused, {} fetched",
+            hits, misses
+        )));
+    }
+}
+
+pub async fn fetch_website(
+    chunk_address: &Vec<u8>,
+    path: &str,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> (Vec<u8>, String, String, u16) {
+    let cd = get_manifest_node(chunk_address, data_retrieve_chan, scope).await;
+
+    let (data_vector, index, error_document) =
+        match list_manifest("".to_string(), &cd, data_retrieve_chan, scope).await {
+            Ok(walked) => walked,
+            // a cyclic, too deep or too repetitive manifest is answered as a detected loop, one
+            // that does not parse as unprocessable
+            Err(e) => {
+                return (
+                    format!("{:?}", e).into_bytes(),
+                    "text/plain".to_string(),
+                    "".to_string(),
+                    match e {
+                        ManifestError::Malformed => 422,
+                        _ => 508,
+                    },
+                )
+            }
+        };
+    log_manifest_node_reuse(scope);
+
+    // root requests fall back to the index document, directories to their own index
+
+    let target = path.trim_start_matches('/');
+    let mut candidates: Vec<String> = vec![];
+    if target.len() == 0 {
+        candidates.push(index.clone());
+    } else {
+        candidates.push(target.to_string());
+        if index.len() > 0 {
+            candidates.push(format!("{}/{}", target.trim_end_matches('/'), index));
+        }
+    }
+
+    for candidate in candidates.iter() {
+        if let Some(f) = data_vector.iter().find(|f| f.path == *candidate) {
+            if let Some(content) = strip_span(&fork_body(f, data_retrieve_chan, scope).await) {
+                let (content, encoding) = decode_content(content, &f.encoding);
+                return (content, f.mime.clone(), encoding, 200);
+            }
+        }
+    }
+
+    // an error document that is the index marks a single page app, unmatched paths are its
+    // client side routes and get the index with 200, any other error document is a real 404
+
+    let spa = error_document.len() > 0
+        && error_document.trim_start_matches('/') == index.trim_start_matches('/');
+
+    if error_document.len() > 0 {
+        if let Some(f) = data_vector.iter().find(|f| f.path == error_document) {
+            if let Some(content) = strip_span(&fork_body(f, data_retrieve_chan, scope).await) {
+                let (content, encoding) = decode_content(content, &f.encoding);
+                return (
+                    content,
+                    f.mime.clone(),
+                    encoding,
+                    if spa { 200 } else { 404 },
+                );
+            }
+        }
+    }
+
+    return (
+        b"not found".to_vec(),
+        "text/plain".to_string(),
+        "".to_string(),
+        404,
+    );
+}
+
+// returns the content with the encoding still applied to it, empty once decoded, so the
+// caller can finish the job when the bytes could not be decoded here
+
+#[cfg(feature = "compression")]
+pub fn decode_content(data: Vec<u8>, encoding: &str) -> (Vec<u8>, String) {
+    use std::io::Read;
+
+    let mut decoded: Vec<u8> = vec![];
+    let result = match encoding.trim().to_ascii_lowercase().as_str() {
+        "" => return (data, "".to_string()),
+        "gzip" | "x-gzip" => flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut decoded),
+        "deflate" => flate2::read::ZlibDecoder::new(&data[..]).read_to_end(&mut decoded),
+        _ => return (data, encoding.trim().to_string()),
+    };
+
+    match result {
+        Ok(_) => (decoded, "".to_string()),
+        Err(_) => {
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Failed to decode content with encoding {}",
+                encoding
+            )));
+            (data, encoding.trim().to_string())
+        }
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn decode_content(data: Vec<u8>, encoding: &str) -> (Vec<u8>, String) {
+    if encoding.len() > 0 {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Content encoding {} left undecoded, compression feature disabled",
+            encoding
+        )));
+    }
+    (data, encoding.trim().to_string())
+}
+
+pub async fn retrieve_data<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+    leaf_cache: &Mutex<ChunkLru>,
+    // chunk_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+) -> Vec<u8> {
+    let orig = retrieve_chunk(
+        chunk_address,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+    )
+    .await;
+
+    join_root(
+        chunk_address,
+        orig,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+        leaf_cache,
+    )
+    .await
 }
 
-pub async fn retrieve_data(
+// the data under a root chunk already retrieved, the root itself when it spans a single chunk
+
+async fn join_root<C: ChunkTransport>(
     chunk_address: &Vec<u8>,
-    control: &mut stream::Control,
-    peers: &Mutex<HashMap<String, PeerId>>,
+    orig: Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
     accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
     refresh_chan: &mpsc::Sender<(PeerId, u64)>,
-    // chunk_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+    leaf_cache: &Mutex<ChunkLru>,
 ) -> Vec<u8> {
-    let orig = retrieve_chunk(chunk_address, control, peers, accounting, refresh_chan).await;
-    if orig.len() < 8 {
+    if orig.len() < SPAN_SIZE {
         return vec![];
     }
 
-    let span = u64::from_le_bytes(orig[0..8].try_into().unwrap_or([0; 8]));
-    if span <= 4096 {
+    let span = u64::from_le_bytes(orig[0..SPAN_SIZE].try_into().unwrap_or([0; SPAN_SIZE]));
+    if span <= CHUNK_SIZE as u64 {
         return orig;
     }
 
     let address_length = chunk_address.len();
 
-    if (orig.len() - 8) % address_length != 0 {
+    if address_length == 0 || (orig.len() - SPAN_SIZE) % address_length != 0 {
         return vec![];
     }
 
     async_std::task::yield_now().await;
 
+    let subs = (orig.len() - SPAN_SIZE) / address_length;
+    let mut content_holder_3: HashMap<usize, Vec<u8>> = HashMap::new();
+
+    let joined = join_children(
+        chunk_address,
+        &orig,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+        leaf_cache,
+        |index, data| {
+            if content_holder_3.insert(index, data).is_some() {
+                debug_assert!(false, "join index {} filled twice", index);
+                web_sys::console::log_1(&JsValue::from(format!(
+                    "Join index {} of {} filled twice",
+                    index,
+                    hex::encode(chunk_address)
+                )));
+                return false;
+            }
+            true
+        },
+    )
+    .await;
+    if !joined {
+        return vec![];
+    }
+
+    let mut data: Vec<u8> = Vec::new();
+    data.append(&mut orig[0..SPAN_SIZE].to_vec());
+    for i in 0..subs {
+        match content_holder_3.get(&i) {
+            Some(data0) => data.append(&mut data0[SPAN_SIZE..].to_vec()),
+            None => return vec![],
+        }
+    }
+
+    if data.len() as u64 != span + SPAN_SIZE as u64 {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Joined {} bytes of {} for {}",
+            data.len() - SPAN_SIZE,
+            span,
+            hex::encode(chunk_address)
+        )));
+        return vec![];
+    }
+
+    return data;
+}
+
+// fetches the children of an intermediate chunk, no more at once than the fan out cap, and
+// hands each to joined with its index once it carries its share of the parent span, so joined
+// and streamed data go through the same checks, false once a child fails or joined refuses it
+
+async fn join_children<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    orig: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+    leaf_cache: &Mutex<ChunkLru>,
+    mut joined: impl FnMut(usize, Vec<u8>) -> bool,
+) -> bool {
+    let address_length = chunk_address.len();
+    if orig.len() < SPAN_SIZE
+        || address_length == 0
+        || (orig.len() - SPAN_SIZE) % address_length != 0
+    {
+        return false;
+    }
+
+    let span = u64::from_le_bytes(orig[0..SPAN_SIZE].try_into().unwrap_or([0; SPAN_SIZE]));
+
     let mut joiner = FuturesUnordered::new(); // ::<dyn Future<Output = Vec<u8>>> // ::<Pin<Box<dyn Future<Output = (Vec<u8>, usize)>>>>
 
-    let subs = (orig.len() - 8) / address_length;
+    let subs = (orig.len() - SPAN_SIZE) / address_length;
+    let unit = subtree_span_unit(span, address_length);
 
     let mut content_holder_2: Vec<Vec<u8>> = vec![];
 
     for i in 0..subs {
-        content_holder_2
-            .push((&orig[8 + i * address_length..8 + (i + 1) * address_length]).to_vec());
+        content_holder_2.push(
+            (&orig[SPAN_SIZE + i * address_length..SPAN_SIZE + (i + 1) * address_length]).to_vec(),
+        );
     }
 
+    let mut ready: Vec<(usize, Vec<u8>)> = vec![];
+    let mut pending = vec![];
+    let leaf_store = network_cache(LEAF_CHUNK_CACHE, config.network_id);
+
     for (i, addr) in content_holder_2.iter().enumerate() {
         let index = i;
         let address = addr.clone();
+
+        // leaves validated in an earlier attempt are taken from the cache, making retries resume,
+        // the ones an earlier session stored are looked up alongside the other fetches
+
+        let leaf = unit.min(span.saturating_sub(i as u64 * unit)) <= CHUNK_SIZE as u64;
+        let cached = leaf && scope.pin_root().is_none();
+        if cached {
+            if let Some(data) = leaf_cache.lock_recover().get(&address) {
+                ready.push((index, data));
+                continue;
+            }
+        }
+
         let mut ctrl = control.clone();
+        let leaf_store = leaf_store.clone();
         let handle = async move {
+            // the storage is shared with every script of the origin, so what it holds is content
+            // addressed again and an entry that does not match is dropped and fetched anew
+
+            if cached {
+                if let Some(data) = load_chunk(&leaf_store, &address).await {
+                    let caddr = (&address[0..address.len().min(REFERENCE_SIZE)]).to_vec();
+                    if valid_cac(&data, &caddr) {
+                        return (data, index, address, leaf);
+                    }
+                    delete_chunks(&leaf_store, &[address.clone()]).await;
+                }
+            }
             return (
-                retrieve_data(
+                Box::pin(retrieve_data(
                     &address,
                     &mut ctrl,
                     peers,
                     accounting,
                     refresh_chan,
+                    config,
+                    scope,
+                    leaf_cache,
                     // chunk_retrieve_chan,
-                )
+                ))
                 .await,
                 index.clone(),
+                address.clone(),
+                leaf,
             );
         };
-        joiner.push(handle);
+        pending.push(handle);
     }
 
-    let mut content_holder_3: HashMap<usize, Vec<u8>> = HashMap::new();
+    // each child covers its share of the parent span in reference order
+
+    let mut accept = |index: usize, data: Vec<u8>| {
+        let expected_span = unit.min(span.saturating_sub(index as u64 * unit));
+        data.len() >= SPAN_SIZE
+            && u64::from_le_bytes(data[0..SPAN_SIZE].try_into().unwrap_or([0; SPAN_SIZE]))
+                == expected_span
+            && joined(index, data)
+    };
 
-    while let Some((result0, result1)) = joiner.next().await {
-        content_holder_3.insert(result1, result0);
+    for (index, data) in ready {
+        if !accept(index, data) {
+            return false;
+        }
     }
 
-    let mut data: Vec<u8> = Vec::new();
-    data.append(&mut orig[0..8].to_vec());
-    for i in 0..subs {
-        match content_holder_3.get(&i) {
-            Some(data0) => {
-                if data0.len() > 0 {
-                    data.append(&mut data0[8..].to_vec());
-                } else {
-                    return vec![];
-                }
-            }
-            None => return vec![],
+    // no more children are awaited at once than the fan out cap, results still land on their
+    // index so the reconstruction order does not depend on it
+
+    let window = match config.max_join_fanout {
+        0 => pending.len(),
+        cap => cap,
+    };
+    let mut pending = pending.into_iter();
+    for handle in pending.by_ref().take(window) {
+        joiner.push(handle);
+    }
+
+    while let Some((result0, result1, result2, result3)) = joiner.next().await {
+        if let Some(handle) = pending.next() {
+            joiner.push(handle);
+        }
+
+        // every result must land on the slot of the reference it was fetched for, exactly once
+
+        if content_holder_2.get(result1) != Some(&result2) {
+            debug_assert!(
+                false,
+                "join result {} does not match its reference",
+                result1
+            );
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Join result {} of {} does not match its reference",
+                result1,
+                hex::encode(chunk_address)
+            )));
+            return false;
+        }
+        if result3 && result0.len() >= SPAN_SIZE && config.leaf_cache_capacity > 0 {
+            let evicted =
+                leaf_cache
+                    .lock_recover()
+                    .insert(&result2, &result0, config.leaf_cache_capacity);
+            let (leaf_store, address, data) = (leaf_store.clone(), result2, result0.clone());
+            wasm_bindgen_futures::spawn_local(async move {
+                delete_chunks(&leaf_store, &evicted).await;
+                store_chunk(&leaf_store, &address, &data).await;
+            });
+        }
+        if !accept(result1, result0) {
+            return false;
         }
     }
 
-    return data;
+    true
 }
 
-pub async fn retrieve_chunk(
+pub async fn retrieve_data_verified<C: ChunkTransport>(
     chunk_address: &Vec<u8>,
-    control: &mut stream::Control,
-    peers: &Mutex<HashMap<String, PeerId>>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
     accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
     refresh_chan: &mpsc::Sender<(PeerId, u64)>,
-) -> Vec<u8> {
-    let mut caddr: Vec<u8> = chunk_address.to_vec();
-    let mut encrey = vec![];
-    let mut encred = false;
-
-    if chunk_address.len() == 64 {
-        caddr = (&chunk_address[0..32]).to_vec();
-        encrey = (&chunk_address[32..64]).to_vec();
-        encred = true;
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+    leaf_cache: &Mutex<ChunkLru>,
+) -> Result<Vec<u8>, RetrievalError> {
+    if !valid_chunk_address(chunk_address) {
+        return Err(RetrievalError::InvalidAddress);
     }
 
-    let mut soc = false;
-    let mut skiplist: HashSet<PeerId> = HashSet::new();
-    let mut overdraftlist: HashSet<PeerId> = HashSet::new();
-
-    let mut closest_overlay = "".to_string();
-    let mut closest_peer_id = libp2p::PeerId::random();
+    // the kind of the root is known from its own retrieval, a soc root wraps the root of its
+    // payload under a signed id, so the payload is held to the address that was signed
+
+    let (kind, orig) = retrieve_chunk_typed(
+        chunk_address,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+    )
+    .await;
+    let expected_root = match kind {
+        ChunkKind::Soc { .. } if orig.len() >= SPAN_SIZE => content_address(orig.clone()),
+        _ => chunk_address.clone(),
+    };
 
-    #[allow(unused_assignments)]
-    let mut selected = false;
-    let mut round_commence = Date::now();
+    let data = join_root(
+        chunk_address,
+        orig,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+        leaf_cache,
+    )
+    .await;
+
+    if data.len() == 0 {
+        return Err(RetrievalError::NotFound);
+    }
 
-    #[allow(unused_assignments)]
-    let mut current_max_po = 0;
+    // encrypted trees can not be rebuilt without their random keys
 
-    let mut error_count = 0;
-    let mut max_error = 8;
+    if !config.verify_root || chunk_address.len() != REFERENCE_SIZE {
+        return Ok(data);
+    }
 
-    let mut cd = vec![];
+    if file_root_address(&data) == expected_root {
+        return Ok(data);
+    }
 
-    while error_count < max_error {
-        let mut seer = true;
+    web_sys::console::log_1(&JsValue::from(format!(
+        "Reconstructed data does not hash to {}",
+        hex::encode(&expected_root)
+    )));
+    Err(RetrievalError::IntegrityMismatch)
+}
 
-        while seer {
-            closest_overlay = "".to_string();
-            closest_peer_id = libp2p::PeerId::random();
-            current_max_po = 0;
-            selected = false;
-            {
-                let peers_map = peers.lock().unwrap();
-                for (ov, id) in peers_map.iter() {
-                    if skiplist.contains(id) {
-                        continue;
-                    }
+pub async fn check_availability<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    sample_rate: f32,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+) -> f32 {
+    let mut attempted: u64 = 0;
+    let mut retrieved: u64 = 0;
+    let mut leaf_counter: u64 = 0;
+
+    availability_walk(
+        chunk_address,
+        None,
+        sample_rate.clamp(0.0, 1.0),
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+        &mut attempted,
+        &mut retrieved,
+        &mut leaf_counter,
+    )
+    .await;
+
+    if attempted == 0 {
+        return 0.0;
+    }
 
-                    let current_po = get_proximity(&caddr, &hex::decode(&ov).unwrap());
+    return retrieved as f32 / attempted as f32;
+}
 
-                    if current_po >= current_max_po {
-                        selected = true;
-                        closest_overlay = ov.clone();
-                        closest_peer_id = id.clone();
-                        current_max_po = current_po;
-                    }
-                }
+async fn availability_walk<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    span_hint: Option<u64>,
+    sample_rate: f32,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+    attempted: &mut u64,
+    retrieved: &mut u64,
+    leaf_counter: &mut u64,
+) {
+    // leaves are only fetched for the sampled subset, intermediate chunks always
+
+    if let Some(span) = span_hint {
+        if span <= CHUNK_SIZE as u64 {
+            let current = *leaf_counter as f32 * sample_rate;
+            *leaf_counter += 1;
+            if (*leaf_counter as f32 * sample_rate).floor() <= current.floor() {
+                return;
             }
-            if selected {
-                skiplist.insert(closest_peer_id);
-            } else {
-                if overdraftlist.is_empty() {
-                    return vec![];
-                } else {
-                    for k in overdraftlist.iter() {
-                        let _ =
-                            refresh_chan.send((k.clone(), 10 * crate::accounting::REFRESH_RATE));
-                        skiplist.remove(k);
-                    }
-                    overdraftlist.clear();
-
-                    let round_now = Date::now();
+        }
+    }
 
-                    let seg = round_now - round_commence;
-                    if seg < RETRIEVE_ROUND_TIME {
-                        async_std::task::sleep(Duration::from_millis(
-                            (RETRIEVE_ROUND_TIME - seg) as u64,
-                        ))
-                        .await;
-                    }
+    *attempted += 1;
+    let orig = retrieve_chunk(
+        chunk_address,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+    )
+    .await;
+    if orig.len() < SPAN_SIZE {
+        return;
+    }
+    *retrieved += 1;
 
-                    round_commence = Date::now();
+    let span = u64::from_le_bytes(orig[0..SPAN_SIZE].try_into().unwrap_or([0; SPAN_SIZE]));
+    if span <= CHUNK_SIZE as u64 {
+        return;
+    }
 
-                    continue;
-                }
-            }
+    let address_length = chunk_address.len();
+    if (orig.len() - SPAN_SIZE) % address_length != 0 {
+        return;
+    }
 
-            let req_price = price(&closest_overlay, &caddr);
+    let unit = subtree_span_unit(span, address_length);
 
-            {
-                let accounting_peers = accounting.lock().unwrap();
-                if max_error > accounting_peers.len() {
-                    max_error = accounting_peers.len();
-                };
-                if accounting_peers.contains_key(&closest_peer_id) {
-                    let accounting_peer = accounting_peers.get(&closest_peer_id).unwrap();
-                    let allowed = reserve(accounting_peer, req_price, refresh_chan);
-                    if !allowed {
-                        overdraftlist.insert(closest_peer_id);
-                    } else {
-                        seer = false;
-                    }
-                }
-            }
-        }
+    let subs = (orig.len() - SPAN_SIZE) / address_length;
+    for i in 0..subs {
+        let address =
+            (&orig[SPAN_SIZE + i * address_length..SPAN_SIZE + (i + 1) * address_length]).to_vec();
+        let sub_span = unit.min(span.saturating_sub(i as u64 * unit));
+
+        Box::pin(availability_walk(
+            &address,
+            Some(sub_span),
+            sample_rate,
+            control,
+            peers,
+            accounting,
+            refresh_chan,
+            config,
+            scope,
+            attempted,
+            retrieved,
+            leaf_counter,
+        ))
+        .await;
+    }
+}
 
-        let req_price = price(&closest_overlay, &caddr);
+pub async fn estimate_cost<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+) -> u64 {
+    let mut cost: u64 = 0;
+    let inputs = selection_inputs(accounting, config);
+
+    estimate_walk(
+        chunk_address,
+        None,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+        &inputs,
+        &mut cost,
+    )
+    .await;
+
+    return cost;
+}
 
-        let (chunk_out, chunk_in) = mpsc::channel::<Vec<u8>>();
+async fn estimate_walk<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    span_hint: Option<u64>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+    inputs: &SelectionInputs,
+    cost: &mut u64,
+) {
+    let caddr = (&chunk_address[0..chunk_address.len().min(REFERENCE_SIZE)]).to_vec();
+
+    // priced at the peer a retrieval would ask first, below the floor only once nothing
+    // within it is left, as a retrieval clamps
+
+    let none_skipped = HashSet::new();
+    let first = select_peer(
+        &caddr,
+        peers,
+        &none_skipped,
+        config.proximity_floor,
+        config,
+        inputs,
+    )
+    .or_else(|| select_peer(&caddr, peers, &none_skipped, 0, config, inputs));
+    match first {
+        Some((overlay, _, _)) => *cost += price(&overlay, &caddr),
+        None => return,
+    }
 
-        retrieve_handler(closest_peer_id, caddr.clone(), control, &chunk_out).await;
+    // leaves are priced without being fetched, only intermediate chunks are retrieved
 
-        let chunk_data = chunk_in.try_recv();
-        if chunk_data.is_err() {
-            let accounting_peers = accounting.lock().unwrap();
-            if accounting_peers.contains_key(&closest_peer_id) {
-                let accounting_peer = accounting_peers.get(&closest_peer_id).unwrap();
-                cancel_reserve(accounting_peer, req_price)
-            }
+    if let Some(span) = span_hint {
+        if span <= CHUNK_SIZE as u64 {
+            return;
         }
+    }
 
-        cd = match chunk_data {
-            Ok(ref x) => x.clone(),
-            Err(_x) => {
-                error_count += 1;
-                let accounting_peers = accounting.lock().unwrap();
-                if accounting_peers.contains_key(&closest_peer_id) {
-                    let accounting_peer = accounting_peers.get(&closest_peer_id).unwrap();
-                    cancel_reserve(accounting_peer, req_price)
-                }
-                vec![]
-            }
-        };
+    let orig = retrieve_chunk(
+        chunk_address,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+    )
+    .await;
+    if orig.len() < SPAN_SIZE {
+        return;
+    }
 
-        // chan send?
+    let span = u64::from_le_bytes(orig[0..SPAN_SIZE].try_into().unwrap_or([0; SPAN_SIZE]));
+    if span <= CHUNK_SIZE as u64 {
+        return;
+    }
 
-        match chunk_data {
-            Ok(_x) => {
-                let contaddrd = valid_cac(&cd, &caddr);
+    let address_length = chunk_address.len();
+    if (orig.len() - SPAN_SIZE) % address_length != 0 {
+        return;
+    }
 
-                if !contaddrd {
-                    soc = valid_soc(&cd, &caddr);
-                    if !soc {
+    let unit = subtree_span_unit(span, address_length);
+
+    let subs = (orig.len() - SPAN_SIZE) / address_length;
+    for i in 0..subs {
+        let address =
+            (&orig[SPAN_SIZE + i * address_length..SPAN_SIZE + (i + 1) * address_length]).to_vec();
+        let sub_span = unit.min(span.saturating_sub(i as u64 * unit));
+
+        Box::pin(estimate_walk(
+            &address,
+            Some(sub_span),
+            control,
+            peers,
+            accounting,
+            refresh_chan,
+            config,
+            scope,
+            inputs,
+            cost,
+        ))
+        .await;
+    }
+}
+
+// every address of the tree under a root with its depth, the root at depth 0, children of an
+// intermediate chunk that could not be retrieved are missing from the list, encrypted roots are
+// not listed since their intermediate chunks can not be read without decrypting them
+
+pub async fn chunk_tree<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+) -> Vec<(u8, Vec<u8>)> {
+    if chunk_address.len() != REFERENCE_SIZE {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Chunk tree of encrypted reference {} is not supported",
+            hex::encode(chunk_address)
+        )));
+        return vec![];
+    }
+
+    // the tree is walked a level at a time, the intermediate chunks of a level are fetched
+    // concurrently under the join fan out cap, children hang off their parent in reference
+    // order so the listing does not depend on which fetch finishes first
+
+    let mut nodes: Vec<(u8, Vec<u8>, Vec<usize>)> = vec![(0, chunk_address.clone(), vec![])];
+    let mut level: Vec<usize> = vec![0];
+
+    while !level.is_empty() && !scope.cancelled() {
+        let mut pending = vec![];
+        for node in level.drain(..) {
+            let address = nodes[node].1.clone();
+            let mut ctrl = control.clone();
+            pending.push(async move {
+                let orig = retrieve_chunk(
+                    &address,
+                    &mut ctrl,
+                    peers,
+                    accounting,
+                    refresh_chan,
+                    config,
+                    scope,
+                )
+                .await;
+                (node, orig)
+            });
+        }
+
+        let window = match config.max_join_fanout {
+            0 => pending.len(),
+            cap => cap,
+        };
+        let mut pending = pending.into_iter();
+        let mut joiner = FuturesUnordered::new();
+        for handle in pending.by_ref().take(window) {
+            joiner.push(handle);
+        }
+
+        let mut fetched: Vec<(usize, Vec<u8>)> = vec![];
+        while let Some(result) = joiner.next().await {
+            if let Some(handle) = pending.next() {
+                joiner.push(handle);
+            }
+            fetched.push(result);
+        }
+        fetched.sort_by_key(|(node, _)| *node);
+
+        for (node, orig) in fetched {
+            if orig.len() < SPAN_SIZE || (orig.len() - SPAN_SIZE) % REFERENCE_SIZE != 0 {
+                continue;
+            }
+            let span = u64::from_le_bytes(orig[0..SPAN_SIZE].try_into().unwrap_or([0; SPAN_SIZE]));
+            if span <= CHUNK_SIZE as u64 {
+                continue;
+            }
+
+            let unit = subtree_span_unit(span, REFERENCE_SIZE);
+            let depth = nodes[node].0.saturating_add(1);
+
+            let subs = (orig.len() - SPAN_SIZE) / REFERENCE_SIZE;
+            for i in 0..subs {
+                let address = (&orig
+                    [SPAN_SIZE + i * REFERENCE_SIZE..SPAN_SIZE + (i + 1) * REFERENCE_SIZE])
+                    .to_vec();
+                let sub_span = unit.min(span.saturating_sub(i as u64 * unit));
+
+                // leaves are listed without being fetched, only intermediate chunks are retrieved
+
+                let child = nodes.len();
+                nodes.push((depth, address, vec![]));
+                nodes[node].2.push(child);
+                if sub_span > CHUNK_SIZE as u64 {
+                    level.push(child);
+                }
+            }
+        }
+    }
+
+    let mut tree: Vec<(u8, Vec<u8>)> = vec![];
+    let mut stack: Vec<usize> = vec![0];
+    while let Some(node) = stack.pop() {
+        tree.push((nodes[node].0, nodes[node].1.clone()));
+        stack.extend(nodes[node].2.iter().rev());
+    }
+
+    return tree;
+}
+
+// what peer selection knows of each peer, read once per chunk so selection does not lock the
+// accounting for every candidate
+
+struct SelectionInputs {
+    latencies: HashMap<PeerId, f64>,
+    scores: HashMap<PeerId, f64>,
+}
+
+fn selection_inputs(
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    config: &RetrievalConfig,
+) -> SelectionInputs {
+    let mut latencies: HashMap<PeerId, f64> = HashMap::new();
+    if config.latency_weight > 0.0 || config.strategy == SelectionStrategy::Fastest {
+        let accounting_peers = accounting.lock_recover();
+        for (id, accounting_peer) in accounting_peers.iter() {
+            latencies.insert(*id, accounting_peer.lock_recover().latency);
+        }
+    }
+
+    let mut scores: HashMap<PeerId, f64> = HashMap::new();
+    if config.score_weight > 0.0 {
+        let now = config.clock.now_ms();
+        let accounting_peers = accounting.lock_recover();
+        for (id, accounting_peer) in accounting_peers.iter() {
+            scores.insert(*id, peer_score(accounting_peer, now));
+        }
+    }
+
+    SelectionInputs { latencies, scores }
+}
+
+// the best scoring peer not yet skipped at or above the proximity floor, candidates are
+// visited closest first so among equal scores the peer nearest by full distance wins
+
+fn select_peer(
+    caddr: &Vec<u8>,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    skiplist: &HashSet<PeerId>,
+    po_floor: u8,
+    config: &RetrievalConfig,
+    inputs: &SelectionInputs,
+) -> Option<(OverlayAddress, PeerId, u8)> {
+    let candidates: Vec<(OverlayAddress, PeerId)> = peers
+        .lock_recover()
+        .iter()
+        .map(|(ov, id)| (*ov, *id))
+        .collect();
+
+    let mut selected: Option<(OverlayAddress, PeerId, u8)> = None;
+    let mut current_max_score = f64::NEG_INFINITY;
+    for (ov, id) in sort_by_distance(caddr, &candidates).iter() {
+        if skiplist.contains(id) {
+            continue;
+        }
+
+        let current_po = get_proximity(caddr, &ov.to_vec());
+        if current_po < po_floor {
+            continue;
+        }
+
+        let current_score = config.selection_score(
+            current_po,
+            *inputs.latencies.get(id).unwrap_or(&0.0),
+            price(ov, caddr),
+            *inputs.scores.get(id).unwrap_or(&0.0),
+        );
+
+        if current_score > current_max_score {
+            selected = Some((*ov, *id, current_po));
+            current_max_score = current_score;
+        }
+    }
+
+    selected
+}
+
+pub fn subtree_span_unit(span: u64, address_length: usize) -> u64 {
+    // the span covered by each full child reference of an intermediate chunk
+
+    let branching = (CHUNK_SIZE / address_length) as u64;
+    let mut unit = CHUNK_SIZE as u64;
+    while unit.saturating_mul(branching) < span {
+        unit *= branching;
+    }
+    unit
+}
+
+pub const STREAM_SEGMENT: u8 = 0;
+pub const STREAM_END: u8 = 1;
+pub const STREAM_FAILED: u8 = 2;
+
+pub async fn retrieve_data_stream<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+    leaf_cache: &Mutex<ChunkLru>,
+    sink: &mpsc::Sender<Vec<u8>>,
+) -> bool {
+    stream_subtree(
+        chunk_address,
+        None,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+        leaf_cache,
+        sink,
+    )
+    .await
+    .is_some()
+}
+
+// the bytes a subtree sent, none once it failed, a subtree must span what its parent expects
+// of it and send exactly its span, as retrieve_data checks a joined tree
+
+async fn stream_subtree<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    expected_span: Option<u64>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+    leaf_cache: &Mutex<ChunkLru>,
+    sink: &mpsc::Sender<Vec<u8>>,
+) -> Option<u64> {
+    let orig = retrieve_chunk(
+        chunk_address,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+    )
+    .await;
+    if orig.len() < SPAN_SIZE {
+        return None;
+    }
+
+    let span = u64::from_le_bytes(orig[0..SPAN_SIZE].try_into().unwrap_or([0; SPAN_SIZE]));
+    if expected_span.is_some_and(|expected| expected != span) {
+        return None;
+    }
+    if span <= CHUNK_SIZE as u64 {
+        sink.send([&[STREAM_SEGMENT], &orig[SPAN_SIZE..]].concat())
+            .ok()?;
+        return Some((orig.len() - SPAN_SIZE) as u64);
+    }
+
+    let address_length = chunk_address.len();
+
+    if address_length == 0 || (orig.len() - SPAN_SIZE) % address_length != 0 {
+        return None;
+    }
+
+    let subs = (orig.len() - SPAN_SIZE) / address_length;
+    let unit = subtree_span_unit(span, address_length);
+    let mut sent: u64 = 0;
+
+    if unit > CHUNK_SIZE as u64 {
+        // deeper levels are streamed one subtree at a time to bound buffering
+
+        for i in 0..subs {
+            let address = (&orig
+                [SPAN_SIZE + i * address_length..SPAN_SIZE + (i + 1) * address_length])
+                .to_vec();
+            sent += Box::pin(stream_subtree(
+                &address,
+                Some(unit.min(span.saturating_sub(i as u64 * unit))),
+                control,
+                peers,
+                accounting,
+                refresh_chan,
+                config,
+                scope,
+                leaf_cache,
+                sink,
+            ))
+            .await?;
+        }
+
+        return (sent == span).then_some(sent);
+    }
+
+    async_std::task::yield_now().await;
+
+    // out of order leaves wait here only until the preceding ones arrived
+
+    let mut pending: HashMap<usize, Vec<u8>> = HashMap::new();
+    let mut next_index = 0;
+
+    let joined = join_children(
+        chunk_address,
+        &orig,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+        leaf_cache,
+        |index, data| {
+            if pending.insert(index, data).is_some() {
+                return false;
+            }
+            while let Some(data0) = pending.remove(&next_index) {
+                sent += (data0.len() - SPAN_SIZE) as u64;
+                if sink
+                    .send([&[STREAM_SEGMENT], &data0[SPAN_SIZE..]].concat())
+                    .is_err()
+                {
+                    return false;
+                }
+                next_index += 1;
+            }
+            true
+        },
+    )
+    .await;
+
+    (joined && next_index == subs && sent == span).then_some(sent)
+}
+
+// a validator that throws counts as a rejection
+
+fn validator_accepts(config: &RetrievalConfig, chunk_address: &Vec<u8>, data: &Vec<u8>) -> bool {
+    match &config.validator {
+        Some(validator) => match validator.call2(
+            &JsValue::NULL,
+            &JsValue::from(hex::encode(chunk_address)),
+            &js_sys::Uint8Array::from(data.as_slice()),
+        ) {
+            Ok(accepted) => accepted.is_truthy(),
+            Err(_) => false,
+        },
+        None => true,
+    }
+}
+
+// requests are signed only when the config carries a key, permissioned servers refuse the rest
+
+async fn request_signature(config: &RetrievalConfig, chunk_address: &Vec<u8>) -> Vec<u8> {
+    match &config.request_key {
+        Some(key) => sign_request(
+            key,
+            chunk_address,
+            config.network_id,
+            config.clock.now_ms() as u64,
+        )
+        .await
+        .unwrap_or_default(),
+        None => vec![],
+    }
+}
+
+pub async fn retrieve_chunk<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+) -> Vec<u8> {
+    let (_kind, data) = retrieve_chunk_typed(
+        chunk_address,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+    )
+    .await;
+
+    return data;
+}
+
+pub async fn retrieve_chunk_typed<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+) -> (ChunkKind, Vec<u8>) {
+    if scope.exhausted() || !valid_chunk_address(chunk_address) {
+        return (ChunkKind::Cac, vec![]);
+    }
+
+    let mut caddr: Vec<u8> = chunk_address.to_vec();
+    let mut encrey = vec![];
+    let mut encred = false;
+
+    if chunk_address.len() == 2 * REFERENCE_SIZE {
+        caddr = (&chunk_address[0..REFERENCE_SIZE]).to_vec();
+        encrey = (&chunk_address[REFERENCE_SIZE..2 * REFERENCE_SIZE]).to_vec();
+        encred = true;
+    }
+
+    let mut soc = false;
+    let mut skiplist: HashSet<PeerId> = HashSet::new();
+    let mut overdraftlist: HashMap<PeerId, u64> = HashMap::new();
+    let mut resetlist: HashSet<PeerId> = HashSet::new();
+    let mut busylist: HashSet<PeerId> = HashSet::new();
+
+    let mut closest_overlay = OverlayAddress::default();
+    let mut closest_peer_id = libp2p::PeerId::random();
+
+    let mut round_commence = config.clock.now_ms();
+
+    let inputs = selection_inputs(accounting, config);
+
+    let mut error_count = 0;
+    let mut max_error = 8;
+
+    // a light node rarely reaches the chunk neighborhood, after enough stale rounds
+    // the floor is dropped and the closest available peer is left to forward the request
+
+    let mut po_floor = config.proximity_floor;
+    let mut best_po: u8 = 0;
+    let mut stale_rounds: u32 = 0;
+    let mut closest_po: u8 = 0;
+
+    // pinned chunks were validated when they were stored
+
+    let mut cd = config.pins.chunk(&caddr).unwrap_or_default();
+    let mut stamped = cd.len() > 0;
+
+    // a fetch by another branch that outlasts one request timeout is not waited for any longer
+
+    let mut claimed = false;
+    let mut waiting = false;
+    let wait_started = config.clock.now_ms();
+    while cd.is_empty() && !claimed {
+        if let Some((shared, shared_stamped)) = scope.fetched_chunk(&caddr) {
+            cd = shared;
+            stamped = shared_stamped;
+        } else if scope.claim_chunk(&caddr, waiting) {
+            claimed = true;
+        } else if scope.exhausted() {
+            scope.stop_waiting(&caddr);
+            return (ChunkKind::Cac, vec![]);
+        } else if config.clock.now_ms() - wait_started > config.request_timeout(0) {
+            scope.stop_waiting(&caddr);
+            break;
+        } else {
+            waiting = true;
+            async_std::task::sleep(Duration::from_millis(SHARED_FETCH_WAIT_MS)).await;
+        }
+    }
+
+    if cd.len() > 0 {
+        soc = !valid_cac(&cd, &caddr);
+    }
+
+    let signature = match cd.is_empty() {
+        true => request_signature(config, &caddr).await,
+        false => vec![],
+    };
+
+    'rounds: while cd.is_empty() && error_count < max_error {
+        let mut seer = true;
+
+        while seer {
+            let selected = select_peer(&caddr, peers, &skiplist, po_floor, config, &inputs);
+            if let Some((overlay, peer_id, po)) = selected {
+                closest_overlay = overlay;
+                closest_peer_id = peer_id;
+                closest_po = po;
+                skiplist.insert(closest_peer_id);
+            } else {
+                // with nobody within the floor a round only counts once its time has passed,
+                // which also gives newly connected peers the chance to be selected
+
+                if po_floor > 0 {
+                    let seg = config.clock.now_ms() - round_commence;
+                    if seg < RETRIEVE_ROUND_TIME {
+                        async_std::task::sleep(Duration::from_millis(
+                            (RETRIEVE_ROUND_TIME - seg) as u64,
+                        ))
+                        .await;
+                    }
+                    round_commence = config.clock.now_ms();
+
+                    stale_rounds += 1;
+                    if stale_rounds >= config.clamp_after_rounds {
                         web_sys::console::log_1(&JsValue::from(format!(
-                            "invalid as Soc&Cac with len {} for address {}!",
-                            cd.len(),
-                            hex::encode(chunk_address)
+                            "No peer within po {} of {}, clamping to best available",
+                            po_floor,
+                            hex::encode(&caddr)
                         )));
-                        error_count += 1;
-                        let accounting_peers = accounting.lock().unwrap();
-                        if accounting_peers.contains_key(&closest_peer_id) {
-                            let accounting_peer = accounting_peers.get(&closest_peer_id).unwrap();
-                            cancel_reserve(accounting_peer, req_price)
+                        po_floor = 0;
+                    }
+                    continue;
+                }
+                if overdraftlist.is_empty() && busylist.is_empty() {
+                    break 'rounds;
+                } else {
+                    {
+                        let accounting_peers = accounting.lock_recover();
+                        for (k, pending_price) in overdraftlist.drain() {
+                            let amount = match accounting_peers.get(&k) {
+                                Some(accounting_peer) => {
+                                    overdraft_refresh(accounting_peer, pending_price)
+                                }
+                                None => 0,
+                            };
+                            if amount > 0 {
+                                let _ = refresh_chan.send((k, amount));
+                            }
+                            skiplist.remove(&k);
                         }
-                        cd = vec![];
+                    }
+                    for k in busylist.drain() {
+                        skiplist.remove(&k);
+                    }
+
+                    let round_now = config.clock.now_ms();
+
+                    let seg = round_now - round_commence;
+                    if seg < RETRIEVE_ROUND_TIME {
+                        async_std::task::sleep(Duration::from_millis(
+                            (RETRIEVE_ROUND_TIME - seg) as u64,
+                        ))
+                        .await;
+                    }
+
+                    round_commence = config.clock.now_ms();
+
+                    continue;
+                }
+            }
+
+            let req_price = price(&closest_overlay, &caddr);
+
+            {
+                let accounting_peers = accounting.lock_recover();
+                if max_error > accounting_peers.len() {
+                    max_error = accounting_peers.len();
+                };
+                if let Some(accounting_peer) = accounting_peers.get(&closest_peer_id) {
+                    // a busy peer is left for this round and the next closest one is tried
+                    if !begin_request(accounting_peer, config.max_in_flight_per_peer) {
+                        busylist.insert(closest_peer_id);
+                    } else if !reserve(
+                        accounting_peer,
+                        req_price,
+                        refresh_chan,
+                        config.clock.now_ms(),
+                    ) {
+                        end_request(accounting_peer);
+                        overdraftlist.insert(closest_peer_id, req_price);
                     } else {
-                        let accounting_peers = accounting.lock().unwrap();
-                        if accounting_peers.contains_key(&closest_peer_id) {
-                            let accounting_peer = accounting_peers.get(&closest_peer_id).unwrap();
-                            apply_credit(accounting_peer, req_price);
-                        }
-                        break;
+                        seer = false;
+                    }
+                }
+            }
+        }
+
+        let req_price = price(&closest_overlay, &caddr);
+
+        let (chunk_out, chunk_in) = mpsc::channel::<(Vec<u8>, Vec<u8>)>();
+
+        let stream_permit = config.streams.acquire().await;
+
+        let request_commence = config.clock.now_ms();
+        // distant peers forward the request, so the wait grows with the expected hop count
+
+        let mut timed_out = false;
+        let outcome = match async_std::future::timeout(
+            Duration::from_millis(config.request_timeout(closest_po) as u64),
+            control.fetch_chunk(
+                closest_peer_id,
+                caddr.clone(),
+                &signature,
+                &chunk_out,
+                &config.bandwidth,
+            ),
+        )
+        .await
+        {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                web_sys::console::log_1(&JsValue::from(format!(
+                    "Retrieve from {} timed out at po {}",
+                    closest_peer_id, closest_po
+                )));
+                timed_out = true;
+                RetrieveOutcome::NotFound
+            }
+        };
+        let request_latency = config.clock.now_ms() - request_commence;
+        drop(stream_permit);
+
+        {
+            let accounting_peers = accounting.lock_recover();
+            if let Some(accounting_peer) = accounting_peers.get(&closest_peer_id) {
+                end_request(accounting_peer);
+            }
+        }
+
+        let chunk_data = chunk_in.try_recv();
+        if chunk_data.is_err() {
+            let accounting_peers = accounting.lock_recover();
+            if let Some(accounting_peer) = accounting_peers.get(&closest_peer_id) {
+                cancel_reserve(accounting_peer, req_price)
+            }
+        }
+
+        cd = match chunk_data {
+            Ok((ref x, ref s)) => {
+                stamped = match PostageStamp::from_bytes(s) {
+                    Some(stamp) => config
+                        .batches
+                        .verify(&stamp, &caddr)
+                        .accepted(config.require_stamp),
+                    None => false,
+                };
+                x.clone()
+            }
+            Err(_x) => {
+                // a reset stream gets the peer one more attempt, a not found answer skips it,
+                // a peer without the protocol is skipped without counting against the chunk
+
+                if outcome == RetrieveOutcome::Unsupported {
+                    continue;
+                }
+                if outcome == RetrieveOutcome::StreamReset && !resetlist.contains(&closest_peer_id)
+                {
+                    web_sys::console::log_1(&JsValue::from(format!(
+                        "Stream reset by {}, retrying peer",
+                        closest_peer_id
+                    )));
+                    resetlist.insert(closest_peer_id);
+                    skiplist.remove(&closest_peer_id);
+                } else {
+                    error_count += 1;
+                }
+                if closest_po > best_po {
+                    best_po = closest_po;
+                    stale_rounds = 0;
+                } else if po_floor > 0 {
+                    stale_rounds += 1;
+                    if stale_rounds >= config.clamp_after_rounds {
+                        po_floor = 0;
+                    }
+                }
+                let answered = outcome == RetrieveOutcome::NotFound && !timed_out;
+                if !(answered && scope.is_probe()) && !scope.consume_retry() {
+                    error_count = max_error;
+                }
+                let accounting_peers = accounting.lock_recover();
+                if let Some(accounting_peer) = accounting_peers.get(&closest_peer_id) {
+                    cancel_reserve(accounting_peer, req_price);
+                    record_delivery(accounting_peer, false, config.clock.now_ms());
+                }
+                vec![]
+            }
+        };
+
+        // chan send?
+
+        match chunk_data {
+            Ok(_x) => {
+                if !stamped && config.require_stamp {
+                    web_sys::console::log_1(&JsValue::from(format!(
+                        "unstamped delivery for address {}!",
+                        hex::encode(chunk_address)
+                    )));
+                    error_count += 1;
+                    if !scope.consume_retry() {
+                        error_count = max_error;
+                    }
+                    let accounting_peers = accounting.lock_recover();
+                    if let Some(accounting_peer) = accounting_peers.get(&closest_peer_id) {
+                        cancel_reserve(accounting_peer, req_price);
+                        record_delivery(accounting_peer, false, config.clock.now_ms());
                     }
+                    cd = vec![];
+                    continue;
+                }
+
+                // hashing runs on the only thread, other retrievals get a turn before and
+                // between the checks so a large batch does not starve them
+
+                async_std::task::yield_now().await;
+                let contaddrd = valid_cac(&cd, &caddr);
+
+                if !contaddrd {
+                    async_std::task::yield_now().await;
+                    soc = valid_soc(&cd, &caddr);
+                }
+
+                let rejected = if !contaddrd && !soc {
+                    web_sys::console::log_1(&JsValue::from(format!(
+                        "invalid as Soc&Cac with len {} for address {}!",
+                        cd.len(),
+                        hex::encode(chunk_address)
+                    )));
+                    true
+                } else if !validator_accepts(config, &caddr, &cd) {
+                    web_sys::console::log_1(&JsValue::from(format!(
+                        "validator rejected chunk for address {}!",
+                        hex::encode(chunk_address)
+                    )));
+                    true
+                } else {
+                    false
+                };
+
+                if rejected {
+                    error_count += 1;
+                    if !scope.consume_retry() {
+                        error_count = max_error;
+                    }
+                    let accounting_peers = accounting.lock_recover();
+                    if let Some(accounting_peer) = accounting_peers.get(&closest_peer_id) {
+                        cancel_reserve(accounting_peer, req_price);
+                        record_delivery(accounting_peer, false, config.clock.now_ms());
+                    }
+                    soc = false;
+                    cd = vec![];
                 } else {
-                    let accounting_peers = accounting.lock().unwrap();
-                    if accounting_peers.contains_key(&closest_peer_id) {
-                        let accounting_peer = accounting_peers.get(&closest_peer_id).unwrap();
+                    let accounting_peers = accounting.lock_recover();
+                    if let Some(accounting_peer) = accounting_peers.get(&closest_peer_id) {
                         apply_credit(accounting_peer, req_price);
+                        record_latency(accounting_peer, request_latency);
+                        record_delivery(accounting_peer, true, config.clock.now_ms());
                     }
                     break;
                 }
@@ -338,43 +1710,90 @@ pub async fn retrieve_chunk(
         };
     }
 
+    // once no peer delivers, the gateway is asked and held to the same content addressing
+    // and validator, it carries no stamps so it is skipped when stamps are required, feed
+    // probes expect most indexes to be missing and never reach it
+
+    if cd.is_empty() && !config.require_stamp && !scope.cancelled() && !scope.is_probe() {
+        if let Some(gateway) = &config.gateway {
+            let fetched = gateway_chunk(gateway, &caddr).await.unwrap_or_default();
+            async_std::task::yield_now().await;
+            let contaddrd = valid_cac(&fetched, &caddr);
+            soc = !contaddrd && valid_soc(&fetched, &caddr);
+            if (contaddrd || soc) && validator_accepts(config, &caddr, &fetched) {
+                cd = fetched;
+            } else {
+                soc = false;
+                if fetched.len() > 0 {
+                    web_sys::console::log_1(&JsValue::from(format!(
+                        "gateway delivered invalid chunk for address {}!",
+                        hex::encode(chunk_address)
+                    )));
+                }
+            }
+        }
+    }
+
+    if claimed {
+        scope.settle_chunk(&caddr, &cd, stamped);
+    }
+
+    scope.add_bytes(cd.len() as u64);
+    if let Some(root) = scope.pin_root() {
+        if cd.len() > 0 {
+            config.pins.store(config.network_id, root, &caddr, &cd);
+            scope.add_pinned();
+        }
+    }
+    if cd.len() > 0 && !stamped {
+        scope.flag_unstamped();
+    }
+
+    let kind = match soc {
+        true => ChunkKind::Soc {
+            owner: soc_owner(&cd).unwrap_or_default(),
+            id: (&cd[0..REFERENCE_SIZE]).to_vec(),
+        },
+        false => ChunkKind::Cac,
+    };
+
     if encred {
         if soc {
             let cd00 = decrypt(&(&cd[97..]).to_vec(), encrey);
-            if cd00.len() >= 8 {
-                return cd00;
+            if cd00.len() >= SPAN_SIZE {
+                return (kind, cd00);
             } else {
-                return vec![];
+                return (ChunkKind::Cac, vec![]);
             }
         }
 
         let cd0 = decrypt(&cd, encrey);
-        return cd0;
+        return (kind, cd0);
     }
 
     if soc && cd.len() >= 97 + 8 {
-        return (&cd[97..]).to_vec();
+        return (kind, (&cd[97..]).to_vec());
     }
 
-    return cd;
+    return (kind, cd);
 }
 
 pub fn decrypt(cd: &Vec<u8>, encrey: Vec<u8>) -> Vec<u8> {
-    if cd.len() < 8 {
+    if cd.len() < SPAN_SIZE {
         return vec![];
     }
 
-    let spancred = (&cd[0..8]).to_vec();
-    let concred = (&cd[8..]).to_vec();
+    let spancred = (&cd[0..SPAN_SIZE]).to_vec();
+    let concred = (&cd[SPAN_SIZE..]).to_vec();
     let creylen = encrey.len();
 
     let mut spanbytes: Vec<u8> = vec![];
     let mut spansegmentkey0: [u8; 4] = [0; 4];
-    byteorder::LittleEndian::write_u32(&mut spansegmentkey0, (4096 / creylen) as u32);
+    byteorder::LittleEndian::write_u32(&mut spansegmentkey0, (CHUNK_SIZE / creylen) as u32);
     let spansegmentkey1 =
         keccak256(keccak256([encrey.clone(), spansegmentkey0.to_vec()].concat()).to_vec()).to_vec();
 
-    for j in 0..8 {
+    for j in 0..SPAN_SIZE {
         spanbytes.push(spancred[j] ^ spansegmentkey1[j])
     }
 
@@ -398,115 +1817,562 @@ pub fn decrypt(cd: &Vec<u8>, encrey: Vec<u8>) -> Vec<u8> {
             content.push(concred[j] ^ contentsegmentkey1[j - i * creylen])
         }
 
-        i += 1;
+        i += 1;
+
+        if !(i * creylen < concred.len()) {
+            done = true;
+        }
+    }
+
+    let mut span_decrypted =
+        u64::from_le_bytes(spanbytes.clone().try_into().unwrap_or([0; SPAN_SIZE]));
+
+    if span_decrypted > CHUNK_SIZE as u64 {
+        let mut done0 = false;
+        let mut carry_span = CHUNK_SIZE as u64;
+        while !done0 {
+            let k = span_decrypted / carry_span;
+            let mut l0 = span_decrypted % carry_span;
+            if l0 > 0 {
+                l0 = 1;
+            }
+
+            if k + l0 <= 64 {
+                done0 = true;
+                span_decrypted = (k + l0) * 64;
+            } else {
+                carry_span = match carry_span.checked_mul(64) {
+                    Some(carry) => carry,
+                    None => return vec![],
+                };
+            }
+        }
+    };
+
+    if span_decrypted as usize > content.len() {
+        return vec![];
+    }
+
+    return [spanbytes, content[..span_decrypted as usize].to_vec()].concat();
+}
+
+pub async fn retrieve_many(
+    addresses: Vec<Vec<u8>>,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Vec<Result<Vec<u8>, RetrievalError>> {
+    let mut results: Vec<Result<Vec<u8>, RetrievalError>> =
+        vec![Err(RetrievalError::NotFound); addresses.len()];
+
+    let addresses_seen = addresses.clone();
+
+    let mut joiner = FuturesUnordered::new();
+
+    for (i, address) in addresses.into_iter().enumerate() {
+        if !valid_chunk_address(&address) {
+            results[i] = Err(RetrievalError::InvalidAddress);
+            continue;
+        }
+        let handle = async move {
+            return (get_data(address, data_retrieve_chan, scope).await, i);
+        };
+        joiner.push(handle);
+    }
+
+    while let Some((data, i)) = joiner.next().await {
+        results[i] = if data.len() > 0 {
+            Ok(data)
+        } else if scope.integrity_mismatched(&addresses_seen[i]) {
+            Err(RetrievalError::IntegrityMismatch)
+        } else if scope.exhausted() {
+            Err(RetrievalError::BudgetExhausted)
+        } else {
+            Err(RetrievalError::NotFound)
+        };
+    }
+
+    return results;
+}
+
+pub async fn get_data(
+    data_address: Vec<u8>,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Vec<u8> {
+    if !valid_chunk_address(&data_address) {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Not retrieving invalid address {}",
+            hex::encode(&data_address)
+        )));
+        return vec![];
+    }
+
+    let (chan_out, chan_in) = mpsc::channel::<Vec<u8>>();
+    if data_retrieve_chan
+        .send((data_address, 1, chan_out, scope.clone()))
+        .is_err()
+    {
+        return vec![];
+    }
+
+    loop {
+        match chan_in.try_recv() {
+            Ok(data) => return data,
+            Err(mpsc::TryRecvError::Disconnected) => return vec![],
+            Err(mpsc::TryRecvError::Empty) => {
+                async_std::task::sleep(Duration::from_millis(RETRIEVE_ROUND_TIME as u64)).await;
+            }
+        }
+    }
+}
+
+// one peer is asked for one chunk without any selection, the delivery is validated and
+// accounted as usual and returned as delivered, span or soc header included
+
+pub async fn retrieve_chunk_from<C: ChunkTransport>(
+    peer: PeerId,
+    chunk_address: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+) -> Result<Vec<u8>, RetrievalError> {
+    if chunk_address.len() != REFERENCE_SIZE || !valid_chunk_address(chunk_address) {
+        return Err(RetrievalError::InvalidAddress);
+    }
+
+    let overlay = match peers
+        .lock_recover()
+        .iter()
+        .find(|(_, id)| **id == peer)
+        .map(|(ov, _)| *ov)
+    {
+        Some(overlay) => overlay,
+        None => return Err(RetrievalError::UnknownPeer),
+    };
+    let req_price = price(&overlay, chunk_address);
+
+    {
+        let accounting_peers = accounting.lock_recover();
+        let accounting_peer = match accounting_peers.get(&peer) {
+            Some(accounting_peer) => accounting_peer,
+            None => return Err(RetrievalError::UnknownPeer),
+        };
+        if !begin_request(accounting_peer, config.max_in_flight_per_peer) {
+            return Err(RetrievalError::PeerUnavailable);
+        }
+        if !reserve(
+            accounting_peer,
+            req_price,
+            refresh_chan,
+            config.clock.now_ms(),
+        ) {
+            end_request(accounting_peer);
+            let amount = overdraft_refresh(accounting_peer, req_price);
+            if amount > 0 {
+                let _ = refresh_chan.send((peer, amount));
+            }
+            return Err(RetrievalError::PeerUnavailable);
+        }
+    }
+
+    let signature = request_signature(config, chunk_address).await;
+    let (chunk_out, chunk_in) = mpsc::channel::<(Vec<u8>, Vec<u8>)>();
+
+    let stream_permit = config.streams.acquire().await;
+
+    let request_commence = config.clock.now_ms();
+    let outcome = async_std::future::timeout(
+        Duration::from_millis(
+            config.request_timeout(get_proximity(&overlay, chunk_address)) as u64,
+        ),
+        control.fetch_chunk(
+            peer,
+            chunk_address.clone(),
+            &signature,
+            &chunk_out,
+            &config.bandwidth,
+        ),
+    )
+    .await;
+    let request_latency = config.clock.now_ms() - request_commence;
+    drop(stream_permit);
+
+    async_std::task::yield_now().await;
+
+    let delivered = match chunk_in.try_recv() {
+        Ok((data, stamp)) => {
+            let stamped = match PostageStamp::from_bytes(&stamp) {
+                Some(stamp) => config
+                    .batches
+                    .verify(&stamp, chunk_address)
+                    .accepted(config.require_stamp),
+                None => false,
+            };
+            let valid = (stamped || !config.require_stamp)
+                && (valid_cac(&data, chunk_address) || valid_soc(&data, chunk_address))
+                && validator_accepts(config, chunk_address, &data);
+            match valid {
+                true => Ok(data),
+                false => Err(RetrievalError::IntegrityMismatch),
+            }
+        }
+        Err(_) if matches!(outcome, Ok(RetrieveOutcome::Unsupported)) => {
+            Err(RetrievalError::UnsupportedProtocol)
+        }
+        Err(_) => Err(RetrievalError::NotFound),
+    };
+
+    let accounting_peers = accounting.lock_recover();
+    if let Some(accounting_peer) = accounting_peers.get(&peer) {
+        end_request(accounting_peer);
+        match delivered {
+            Ok(_) => {
+                apply_credit(accounting_peer, req_price);
+                record_latency(accounting_peer, request_latency);
+                record_delivery(accounting_peer, true, config.clock.now_ms());
+            }
+            Err(_) => {
+                cancel_reserve(accounting_peer, req_price);
+                record_delivery(accounting_peer, false, config.clock.now_ms());
+            }
+        }
+    }
+
+    delivered
+}
+
+// a feed update is only taken from the soc its owner signed, anything else at the address
+// counts as missing
+
+pub async fn retrieve_owned_soc<C: ChunkTransport>(
+    chunk_address: &Vec<u8>,
+    owner: &Vec<u8>,
+    control: &mut C,
+    peers: &Mutex<HashMap<OverlayAddress, PeerId>>,
+    accounting: &Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+    refresh_chan: &mpsc::Sender<(PeerId, u64)>,
+    config: &RetrievalConfig,
+    scope: &RetrievalScope,
+) -> Vec<u8> {
+    let (kind, data) = retrieve_chunk_typed(
+        chunk_address,
+        control,
+        peers,
+        accounting,
+        refresh_chan,
+        config,
+        scope,
+    )
+    .await;
+
+    match kind {
+        ChunkKind::Soc { owner: signer, .. } if signer == *owner => data,
+        ChunkKind::Soc { owner: signer, .. } => {
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Feed update {} signed by {} instead of {}",
+                hex::encode(chunk_address),
+                hex::encode(signer),
+                hex::encode(owner)
+            )));
+            vec![]
+        }
+        ChunkKind::Cac => {
+            if data.len() > 0 {
+                web_sys::console::log_1(&JsValue::from(format!(
+                    "Feed update {} is not a soc",
+                    hex::encode(chunk_address)
+                )));
+            }
+            vec![]
+        }
+    }
+}
+
+pub async fn get_feed_update(
+    feed_address: Vec<u8>,
+    owner: &String,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Vec<u8> {
+    let owner_bytes = match hex::decode(owner) {
+        Ok(owner_bytes) => owner_bytes,
+        Err(_) => return vec![],
+    };
+    if !valid_chunk_address(&feed_address) {
+        return vec![];
+    }
+
+    let (chan_out, chan_in) = mpsc::channel::<Vec<u8>>();
+    if data_retrieve_chan
+        .send((
+            [feed_address, owner_bytes].concat(),
+            5,
+            chan_out,
+            scope.clone(),
+        ))
+        .is_err()
+    {
+        return vec![];
+    }
+
+    loop {
+        match chan_in.try_recv() {
+            Ok(feed_update) => return feed_update,
+            Err(mpsc::TryRecvError::Disconnected) => return vec![],
+            Err(mpsc::TryRecvError::Empty) => {
+                async_std::task::sleep(Duration::from_millis(RETRIEVE_ROUND_TIME as u64)).await;
+            }
+        }
+    }
+}
+
+pub async fn get_chunk(
+    data_address: Vec<u8>,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Vec<u8> {
+    if !valid_chunk_address(&data_address) {
+        return vec![];
+    }
+
+    let (chan_out, chan_in) = mpsc::channel::<Vec<u8>>();
+    if data_retrieve_chan
+        .send((data_address, 0, chan_out, scope.clone()))
+        .is_err()
+    {
+        return vec![];
+    }
+
+    loop {
+        match chan_in.try_recv() {
+            Ok(data) => return data,
+            Err(mpsc::TryRecvError::Disconnected) => return vec![],
+            Err(mpsc::TryRecvError::Empty) => {
+                async_std::task::sleep(Duration::from_millis(RETRIEVE_ROUND_TIME as u64)).await;
+            }
+        }
+    }
+}
+
+pub async fn seek_latest_feed_update(
+    owner: String,
+    topic: String,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+    search: FeedSearch,
+    follow: bool,
+) -> Vec<u8> {
+    // owner is the ethereum address of the feed signer, not an overlay or public key
+
+    let owner = match normalize_feed_owner(&owner) {
+        Some(normalized) => normalized,
+        None => {
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Feed owner must be a 20 byte ethereum address in hex, got {}",
+                owner
+            )));
+            return vec![];
+        }
+    };
+
+    let index =
+        match seek_latest_feed_index(&owner, &topic, data_retrieve_chan, scope, search, 0).await {
+            Some(index) => index,
+            None => return vec![],
+        };
+
+    let feed_address = get_feed_address(&owner, &topic, index);
+    scope.record_feed(&feed_address);
+
+    let feed_update = get_feed_update(feed_address, &owner, data_retrieve_chan, scope).await;
+
+    if !follow {
+        return feed_update;
+    }
+
+    let resolved = resolve_soc_reference(feed_update, data_retrieve_chan, scope).await;
+    if resolved.len() > 0 {
+        return resolved;
+    }
+
+    // the latest update may point at content that has not propagated yet, earlier updates
+    // are tried before giving up on the feed
 
-        if !(i * creylen < concred.len()) {
-            done = true;
+    for previous in (index.saturating_sub(FEED_FALLBACK_STEPS)..index).rev() {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "Feed update {} unresolvable, falling back to {}",
+            previous + 1,
+            previous
+        )));
+
+        let feed_address = get_feed_address(&owner, &topic, previous);
+        scope.record_feed(&feed_address);
+
+        let feed_update = get_feed_update(feed_address, &owner, data_retrieve_chan, scope).await;
+        let resolved = resolve_soc_reference(feed_update, data_retrieve_chan, scope).await;
+        if resolved.len() > 0 {
+            return resolved;
         }
     }
 
-    let mut span_decrypted = u64::from_le_bytes(spanbytes.clone().try_into().unwrap());
+    return vec![];
+}
 
-    if span_decrypted > 4096 {
-        let mut done0 = false;
-        let mut carry_span = 4096_u64;
-        while !done0 {
-            let k = span_decrypted / carry_span;
-            let mut l0 = span_decrypted % carry_span;
-            if l0 > 0 {
-                l0 = 1;
-            }
+// the latest update unfollowed, with the type it declares or raw when it declares none
 
-            if k + l0 <= 64 {
-                done0 = true;
-                span_decrypted = (k + l0) * 64;
-            } else {
-                carry_span *= 64;
-            }
+pub async fn seek_latest_typed_feed_update(
+    owner: String,
+    topic: String,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+    search: FeedSearch,
+) -> Option<(FeedPayloadType, Vec<u8>)> {
+    let feed_update =
+        seek_latest_feed_update(owner, topic, data_retrieve_chan, scope, search, false).await;
+
+    feed_update
+        .get(SPAN_SIZE..)
+        .map(|payload| decode_feed_payload(payload))
+}
+
+// for callers that do not know how the feed was published, the first update of either
+// scheme is probed and the matching search follows
+
+pub async fn seek_latest(
+    owner: String,
+    topic: String,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+    search: FeedSearch,
+    follow: bool,
+) -> (Option<FeedType>, Vec<u8>) {
+    let normalized = match normalize_feed_owner(&owner) {
+        Some(normalized) => normalized,
+        None => {
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Feed owner must be a 20 byte ethereum address in hex, got {}",
+                owner
+            )));
+            return (None, vec![]);
         }
     };
 
-    return [spanbytes, content[..span_decrypted as usize].to_vec()].concat();
+    let (sequential_probe, epoch_probe) = libp2p::futures::join!(
+        get_chunk(
+            get_feed_address(&normalized, &topic, 0),
+            data_retrieve_chan,
+            scope
+        ),
+        get_chunk(
+            get_epoch_feed_address(&normalized, &topic, 0, EPOCH_MAX_LEVEL),
+            data_retrieve_chan,
+            scope
+        ),
+    );
+
+    if sequential_probe.len() > 0 {
+        let feed_update =
+            seek_latest_feed_update(owner, topic, data_retrieve_chan, scope, search, follow).await;
+        return (Some(FeedType::Sequential), feed_update);
+    }
+
+    if epoch_probe.len() > 0 {
+        let feed_update = seek_latest_epoch_feed_update(
+            owner,
+            topic,
+            data_retrieve_chan,
+            scope,
+            (scope.now_ms() / 1000.0) as u64,
+            follow,
+        )
+        .await;
+        return (Some(FeedType::Epoch), feed_update);
+    }
+
+    web_sys::console::log_1(&JsValue::from(format!(
+        "No sequential or epoch updates found for feed {}/{}",
+        normalized, topic
+    )));
+
+    return (None, vec![]);
 }
 
-pub async fn get_data(
-    data_address: Vec<u8>,
-    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>)>,
+// descend from the root epoch towards the epoch holding at, the deepest update found on the way is the latest
+
+pub async fn seek_latest_epoch_feed_update(
+    owner: String,
+    topic: String,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+    at: u64,
+    follow: bool,
 ) -> Vec<u8> {
-    let (chan_out, chan_in) = mpsc::channel::<Vec<u8>>();
-    data_retrieve_chan
-        .send((data_address, 1, chan_out))
-        .unwrap();
-
-    let k0 = async {
-        let mut timelast: f64;
-        #[allow(irrefutable_let_patterns)]
-        while let that = chan_in.try_recv() {
-            timelast = Date::now();
-            if !that.is_err() {
-                return that.unwrap();
-            }
-
-            let timenow = Date::now();
-            let seg = timenow - timelast;
-            if seg < RETRIEVE_ROUND_TIME {
-                async_std::task::sleep(Duration::from_millis((RETRIEVE_ROUND_TIME - seg) as u64))
-                    .await;
-            };
+    let owner = match normalize_feed_owner(&owner) {
+        Some(normalized) => normalized,
+        None => {
+            web_sys::console::log_1(&JsValue::from(format!(
+                "Feed owner must be a 20 byte ethereum address in hex, got {}",
+                owner
+            )));
+            return vec![];
         }
-
-        return vec![];
     };
 
-    let result = k0.await;
-
-    return result;
-}
+    let mut latest: Option<(Vec<u8>, Vec<u8>)> = None;
+    let mut level = EPOCH_MAX_LEVEL;
+    let probe_scope = scope.probing();
+
+    loop {
+        let feed_address = get_epoch_feed_address(&owner, &topic, epoch_start(at, level), level);
+        let feed_update = get_feed_update(
+            feed_address.clone(),
+            &owner,
+            data_retrieve_chan,
+            &probe_scope,
+        )
+        .await;
+
+        if feed_update.len() == 0 {
+            break;
+        }
+        latest = Some((feed_address, feed_update));
 
-pub async fn get_chunk(
-    data_address: Vec<u8>,
-    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>)>,
-) -> Vec<u8> {
-    let (chan_out, chan_in) = mpsc::channel::<Vec<u8>>();
-    data_retrieve_chan
-        .send((data_address, 0, chan_out))
-        .unwrap();
-
-    let k0 = async {
-        let mut timelast: f64;
-        #[allow(irrefutable_let_patterns)]
-        while let that = chan_in.try_recv() {
-            timelast = Date::now();
-            if !that.is_err() {
-                return that.unwrap();
-            }
-
-            let timenow = Date::now();
-            let seg = timenow - timelast;
-            if seg < RETRIEVE_ROUND_TIME {
-                async_std::task::sleep(Duration::from_millis((RETRIEVE_ROUND_TIME - seg) as u64))
-                    .await;
-            };
+        if level == 0 {
+            break;
         }
+        level -= 1;
+    }
 
-        return vec![];
+    let (feed_address, feed_update) = match latest {
+        Some(latest) => latest,
+        None => return vec![],
     };
 
-    let result = k0.await;
+    scope.record_feed(&feed_address);
+
+    if follow {
+        return resolve_soc_reference(feed_update, data_retrieve_chan, scope).await;
+    }
 
-    return result;
+    return feed_update;
 }
 
-pub async fn seek_latest_feed_update(
-    owner: String,
-    topic: String,
-    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>)>,
-    redundancy: u8,
-) -> Vec<u8> {
-    let mut largest_found = 0;
+// frontier search for the latest feed index, probing upwards from start
+
+pub async fn seek_latest_feed_index(
+    owner: &String,
+    topic: &String,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+    search: FeedSearch,
+    start: u64,
+) -> Option<u64> {
+    let mut largest_found = start;
     let mut smallest_not_found = u64::MAX;
-    let mut lower_bound = 0;
-    let mut upper_bound = 2_u64.pow(redundancy.into());
+    let mut lower_bound = start;
+    let mut upper_bound = start.saturating_add(search.initial_width);
     let mut _exact_ = false;
 
     while !_exact_ {
@@ -516,29 +2382,56 @@ pub async fn seek_latest_feed_update(
         let mut joiner = FuturesUnordered::new(); // ::<dyn Future<Output = Vec<u8>>> // ::<Pin<Box<dyn Future<Output = (Vec<u8>, usize)>>>>
 
         let mut i = 0;
+        let mut probes: Vec<u64> = vec![];
 
-        // dispatch probes
+        // indexes near the top of the range stop the probes instead of wrapping around
 
-        while lower_bound + i <= upper_bound {
-            let j = lower_bound + i;
-            let feed_update_address = get_feed_address(&owner, &topic, j);
-            let handle = async move {
-                web_sys::console::log_1(&JsValue::from(format!("dispatching {}", j)));
-                //
-                return (get_chunk(feed_update_address, data_retrieve_chan).await, j);
-            };
-            joiner.push(handle);
+        while let Some(probe) = lower_bound
+            .checked_add(i)
+            .filter(|probe| *probe <= upper_bound)
+        {
+            probes.push(probe);
 
-            if i == 0 || angle <= (redundancy as u64) {
+            if i == 0 || angle <= search.probe_density {
                 i += 1;
             } else {
-                i *= 2;
+                i = i.saturating_mul(2);
+            }
+        }
+
+        // dispatch probes, no more at once than the shared stream budget allows, results are
+        // folded into min and max so the order they arrive in does not change the frontier
+
+        let window = match scope.streams().map(|streams| streams.cap()) {
+            Some(cap) if cap > 0 => cap as usize,
+            _ => probes.len(),
+        };
+        let mut pending = probes.into_iter();
+
+        let probe_scope = scope.probing();
+        let dispatch = |j: u64| {
+            let feed_update_address = get_feed_address(owner, topic, j);
+            let probe_scope = &probe_scope;
+            async move {
+                web_sys::console::log_1(&JsValue::from(format!("dispatching {}", j)));
+                //
+                return (
+                    get_chunk(feed_update_address, data_retrieve_chan, probe_scope).await,
+                    j,
+                );
             }
+        };
+
+        for j in pending.by_ref().take(window) {
+            joiner.push(dispatch(j));
         }
 
         // receive results, update scores
 
         while let Some((result0, result1)) = joiner.next().await {
+            if let Some(j) = pending.next() {
+                joiner.push(dispatch(j));
+            }
             web_sys::console::log_1(&JsValue::from(format!(
                 "receiving {} with len: {}",
                 result1,
@@ -552,42 +2445,94 @@ pub async fn seek_latest_feed_update(
             }
         }
 
-        // if _exact_ frontier found return corresponding data
+        // if _exact_ frontier found return its index
 
-        if largest_found + 1 == smallest_not_found {
-            return get_data(
-                get_feed_address(&owner, &topic, largest_found),
-                data_retrieve_chan,
-            )
-            .await;
+        if largest_found.checked_add(1) == Some(smallest_not_found) {
+            return Some(largest_found);
         }
 
         // search above previous record height
 
-        lower_bound = largest_found + 1;
+        lower_bound = largest_found.saturating_add(1);
 
         // if smallest not found update was higher than current zone lower bound, narrow search between these values
 
         if smallest_not_found > lower_bound {
             upper_bound = smallest_not_found;
         } else {
-            // exit if largest found stayed zero and smallest not found is also zero
+            // exit if largest found stayed at start and start itself was not found
 
-            if smallest_not_found == 0 && largest_found == 0 {
-                return vec![];
+            if smallest_not_found <= start && largest_found == start {
+                return None;
             }
 
             // if we had a missing update below the record found height, discard hole and start from scratch regarding potential height
 
             smallest_not_found = u64::MAX;
 
-            // set upper bound to search redundancy based limit
+            // nothing lies above the last index, so one found there is the latest
 
-            upper_bound = lower_bound + 2_u64.pow(redundancy.into());
+            if largest_found == u64::MAX {
+                return Some(largest_found);
+            }
+
+            // set upper bound to the initial search width
+
+            upper_bound = lower_bound.saturating_add(search.initial_width);
         }
     }
 
-    return vec![];
+    return None;
+}
+
+// soc payload (span included) carrying a reference, optionally prefixed by a timestamp, is followed to its content
+
+pub async fn resolve_soc_reference(
+    soc_payload: Vec<u8>,
+    data_retrieve_chan: &mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    scope: &RetrievalScope,
+) -> Vec<u8> {
+    if soc_payload.len() < 8 {
+        return soc_payload;
+    }
+
+    let content = &soc_payload[8..];
+
+    let reference = match content.len() {
+        32 | 64 => content.to_vec(),
+        40 | 72 => content[8..].to_vec(),
+        _ => return soc_payload,
+    };
+
+    // a reference back into a feed already resolved for this request would loop
+
+    if scope
+        .resolved_feeds()
+        .contains(&reference[0..REFERENCE_SIZE].to_vec())
+    {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "soc reference {} loops back to a resolved feed",
+            hex::encode(&reference)
+        )));
+        return vec![];
+    }
+    if !scope.can_follow_soc() {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "soc reference {} exceeds the follow depth",
+            hex::encode(&reference)
+        )));
+        return vec![];
+    }
+
+    let resolved = get_data(reference, data_retrieve_chan, &scope.following_soc()).await;
+
+    if resolved.len() == 0 {
+        web_sys::console::log_1(&JsValue::from(format!(
+            "soc reference could not be resolved"
+        )));
+    }
+
+    return resolved;
 }
 
 //
@@ -619,3 +2564,996 @@ pub async fn seek_latest_feed_update(
 // 9f2a74cdaad2654660bb95b3e29354696b25d492072110ef091d48434e1d76eed80e865888dd5686ada4acc4528dec8925298a7c818cd758dc95c31c0687acb6
 //
 // a018d027eeb247872ef8b77966baa34b644adeccfdf62f41382714e912632ddbfbabb83b217431f66f872f2bfb2ecb001935152c1c380b1200574c6a3ea03541
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        content_address, feed_topic, insert_peer, set_payment_threshold, ManifestNodeCache,
+        PeerFile, StreamBudget, SOC_FOLLOW_MAX_DEPTH,
+    };
+    use std::sync::Arc;
+    use wasm_bindgen_test::*;
+
+    // chunks served from memory, a corrupt peer answers every request with bytes that match
+    // no address, every peer asked is recorded in order
+
+    #[derive(Clone, Default)]
+    struct MemoryTransport {
+        chunks: Arc<HashMap<Vec<u8>, Vec<u8>>>,
+        corrupt: Arc<HashSet<PeerId>>,
+        asked: Arc<Mutex<Vec<PeerId>>>,
+    }
+
+    impl ChunkTransport for MemoryTransport {
+        async fn fetch_chunk(
+            &mut self,
+            peer: PeerId,
+            chunk_address: Vec<u8>,
+            _signature: &Vec<u8>,
+            chan: &mpsc::Sender<(Vec<u8>, Vec<u8>)>,
+            _bandwidth: &BandwidthMeter,
+        ) -> RetrieveOutcome {
+            self.asked.lock_recover().push(peer);
+            if self.corrupt.contains(&peer) {
+                let _ = chan.send((vec![1; SPAN_SIZE + 1], vec![]));
+                return RetrieveOutcome::Delivered;
+            }
+            match self.chunks.get(&chunk_address) {
+                Some(chunk) => {
+                    let _ = chan.send((chunk.clone(), vec![]));
+                    RetrieveOutcome::Delivered
+                }
+                None => RetrieveOutcome::NotFound,
+            }
+        }
+    }
+
+    // the unencrypted chunk tree of the data built like file_root_address, the root and
+    // every chunk by its address
+
+    fn chunk_tree_of(data: &[u8]) -> (Vec<u8>, HashMap<Vec<u8>, Vec<u8>>) {
+        let mut chunks = HashMap::new();
+        let mut level: Vec<(Vec<u8>, u64)> = vec![];
+        for leaf in data.chunks(CHUNK_SIZE) {
+            let chunk = [(leaf.len() as u64).to_le_bytes().to_vec(), leaf.to_vec()].concat();
+            let address = content_address(chunk.clone());
+            chunks.insert(address.clone(), chunk);
+            level.push((address, leaf.len() as u64));
+        }
+
+        while level.len() > 1 {
+            let mut next = vec![];
+            for group in level.chunks(CHUNK_SIZE / REFERENCE_SIZE) {
+                if group.len() == 1 {
+                    next.push(group[0].clone());
+                    continue;
+                }
+                let span: u64 = group.iter().map(|(_, sub_span)| sub_span).sum();
+                let refs: Vec<u8> = group
+                    .iter()
+                    .flat_map(|(address, _)| address.clone())
+                    .collect();
+                let chunk = [span.to_le_bytes().to_vec(), refs].concat();
+                let address = content_address(chunk.clone());
+                chunks.insert(address.clone(), chunk);
+                next.push((address, span));
+            }
+            level = next;
+        }
+
+        (level[0].0.clone(), chunks)
+    }
+
+    fn seeded_peers(
+        overlays: &[Vec<u8>],
+    ) -> (
+        Mutex<HashMap<OverlayAddress, PeerId>>,
+        Mutex<HashMap<PeerId, Mutex<PeerAccounting>>>,
+        Vec<PeerId>,
+    ) {
+        let peers = Mutex::new(HashMap::new());
+        let connected = Mutex::new(HashMap::new());
+        let accounting = Mutex::new(HashMap::new());
+
+        let mut ids = vec![];
+        for overlay in overlays {
+            let peer_id = PeerId::random();
+            insert_peer(
+                &peers,
+                &connected,
+                &accounting,
+                OverlayAddress::from_slice(overlay).unwrap(),
+                PeerFile {
+                    peer_id,
+                    overlay: overlay.clone(),
+                },
+            );
+            set_payment_threshold(&accounting.lock_recover()[&peer_id], 1 << 40);
+            ids.push(peer_id);
+        }
+
+        (peers, accounting, ids)
+    }
+
+    #[wasm_bindgen_test]
+    async fn skips_a_peer_delivering_invalid_data() {
+        let chunk = [5_u64.to_le_bytes().to_vec(), b"weeb3".to_vec()].concat();
+        let address = content_address(chunk.clone());
+
+        // the corrupt peer sits on the chunk address so it is asked first
+        let mut far = address.clone();
+        far[0] ^= 0x80;
+        let (peers, accounting, ids) = seeded_peers(&[address.clone(), far]);
+
+        let mut transport = MemoryTransport {
+            chunks: Arc::new(HashMap::from([(address.clone(), chunk.clone())])),
+            corrupt: Arc::new(HashSet::from([ids[0]])),
+            ..Default::default()
+        };
+        let (refresh_out, _refresh_in) = mpsc::channel();
+
+        let retrieved = retrieve_chunk(
+            &address,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &RetrievalConfig::default(),
+            &RetrievalScope::new(1, 8),
+        )
+        .await;
+
+        assert_eq!(retrieved, chunk);
+        assert_eq!(*transport.asked.lock_recover(), ids);
+    }
+
+    #[wasm_bindgen_test]
+    async fn joins_a_file_from_memory() {
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        let (root, chunks) = chunk_tree_of(&data);
+        let (peers, accounting, _) = seeded_peers(&[root.clone()]);
+
+        let mut transport = MemoryTransport {
+            chunks: Arc::new(chunks),
+            ..Default::default()
+        };
+        let (refresh_out, _refresh_in) = mpsc::channel();
+
+        let retrieved = retrieve_data(
+            &root,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &RetrievalConfig::default(),
+            &RetrievalScope::new(1, 8),
+            &Mutex::new(ChunkLru::default()),
+        )
+        .await;
+
+        assert_eq!(
+            retrieved,
+            [(data.len() as u64).to_le_bytes().to_vec(), data].concat()
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn joins_a_three_level_tree_in_reference_order() {
+        // every leaf differs, so a child landing on the wrong slot changes the joined bytes
+        let branching = CHUNK_SIZE / REFERENCE_SIZE;
+        let data: Vec<u8> = (0..2 * branching * CHUNK_SIZE)
+            .map(|i| ((i / CHUNK_SIZE) as u8) ^ ((i % 251) as u8))
+            .collect();
+        let (root, chunks) = chunk_tree_of(&data);
+        assert_eq!(chunks.len(), 2 * branching + 2 + 1);
+        assert_eq!(chunks[&root].len(), SPAN_SIZE + 2 * REFERENCE_SIZE);
+
+        let (peers, accounting, _) = seeded_peers(&[root.clone()]);
+        let mut transport = MemoryTransport {
+            chunks: Arc::new(chunks),
+            ..Default::default()
+        };
+        let (refresh_out, _refresh_in) = mpsc::channel();
+        let config = RetrievalConfig {
+            max_join_fanout: 7,
+            ..Default::default()
+        };
+
+        let retrieved = retrieve_data(
+            &root,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &RetrievalScope::new(1, 8),
+            &Mutex::new(ChunkLru::default()),
+        )
+        .await;
+
+        assert_eq!(retrieved.len(), SPAN_SIZE + data.len());
+        assert!(retrieved[SPAN_SIZE..] == data[..]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_a_soc_referencing_itself() {
+        let owner = "8d3766440f0d7b949a5e32995d09619a7f86e632".to_string();
+        let feed_address = get_feed_address(&owner, &"weeb-3".to_string(), 0);
+        let payload = [
+            (REFERENCE_SIZE as u64).to_le_bytes().to_vec(),
+            feed_address.clone(),
+        ]
+        .concat();
+
+        // nothing answers the channel, so a follow that is not refused never resolves
+        let (data_out, data_in) = mpsc::channel();
+        let scope = RetrievalScope::new(1, 8);
+        scope.record_feed(&feed_address);
+
+        let resolved = async_std::future::timeout(
+            Duration::from_millis(1000),
+            resolve_soc_reference(payload.clone(), &data_out, &scope),
+        )
+        .await;
+        assert_eq!(resolved, Ok(vec![]));
+        assert!(data_in.try_recv().is_err());
+
+        // the same holds once a chain of follows is as deep as allowed
+        let mut deep = RetrievalScope::new(2, 8);
+        for _ in 0..SOC_FOLLOW_MAX_DEPTH {
+            deep = deep.following_soc();
+        }
+        let resolved = async_std::future::timeout(
+            Duration::from_millis(1000),
+            resolve_soc_reference(payload, &data_out, &deep),
+        )
+        .await;
+        assert_eq!(resolved, Ok(vec![]));
+        assert!(data_in.try_recv().is_err());
+    }
+
+    // answers every request with the same manifest node and counts the requests
+
+    fn manifest_node_server() -> (
+        mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+        Arc<Mutex<u32>>,
+    ) {
+        let mut node = vec![0_u8; SPAN_SIZE + 32];
+        node.extend_from_slice(&hex::decode(crate::manifest::MANTARAY_VERSION_HASHES[1]).unwrap());
+        node.push(32);
+
+        let (requests_out, requests_in) =
+            mpsc::channel::<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>();
+        let fetches = Arc::new(Mutex::new(0));
+        let counted = fetches.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            loop {
+                match requests_in.try_recv() {
+                    Ok((_, _, reply, _)) => {
+                        *counted.lock_recover() += 1;
+                        let _ = reply.send(node.clone());
+                    }
+                    Err(mpsc::TryRecvError::Empty) => {
+                        async_std::task::sleep(Duration::from_millis(10)).await
+                    }
+                    Err(mpsc::TryRecvError::Disconnected) => break,
+                }
+            }
+        });
+
+        (requests_out, fetches)
+    }
+
+    #[wasm_bindgen_test]
+    async fn least_recently_used_manifest_nodes_are_fetched_again() {
+        let (requests, fetches) = manifest_node_server();
+        let scope = RetrievalScope::new(1, 8).with_manifest_nodes(ManifestNodeCache::default(), 2);
+        let (a, b, c) = (vec![1_u8; 32], vec![2_u8; 32], vec![3_u8; 32]);
+
+        for (address, fetched) in [(&a, 1), (&b, 2), (&a, 2), (&c, 3), (&b, 4), (&c, 4)] {
+            assert!(!get_manifest_node(address, &requests, &scope)
+                .await
+                .is_empty());
+            assert_eq!(*fetches.lock_recover(), fetched);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_a_soc_signed_by_another_owner() {
+        // id keccak("weeb-3 soc"), the requested owner holds key 634fb5a8..., the other
+        // signer 4c0883a6... whose address is 2c7536e3605d9c16a7a3d7b1898e529396a65c23
+        let owner = hex::decode("8d3766440f0d7b949a5e32995d09619a7f86e632").unwrap();
+        let id = hex::decode("c6704baa21f28d64004d5b1f7f23f18b723624536e765164a9fc3c34b003a500")
+            .unwrap();
+        let at_owner =
+            hex::decode("d347e416a9c9f0582c9aaf47ec4f6fe92e82a3961dbfd7fe03a1ae501b92df39")
+                .unwrap();
+        let at_signer =
+            hex::decode("0e3d02088a9f3050a73e6dc192d663686478cabc8c3e5bb234ee1e0021cc4a71")
+                .unwrap();
+        let signed_by_owner = "2fb58cd9b03ae4caabe3493c173139b9886d4096d5164379000531778678065d\
+                               7ea333622c4b2737a93e68cee009e6b18bd5b37ef60c78341ed4b0b1647958031b";
+        let signed_by_other = "3d546a0dbcccc2a9dc2c50fee81db62201e35b62ffdf873dcc71fcc058dbd934\
+                               3fb5785c0fcc74c01b9ba8ea7717e1fbaef89ba5ef8d1ca6ebda3b4605cfe76a1b";
+
+        let payload = b"weeb-3 feed update, 32 bytes ...".to_vec();
+        let wrapped = [32_u64.to_le_bytes().to_vec(), payload].concat();
+        assert_eq!(
+            hex::encode(content_address(wrapped.clone())),
+            "c225b21e806c3abd9c0f21424bb63475785547345a237019ec6c0d3ea868c778"
+        );
+        let soc = |signature: &str| {
+            [id.clone(), hex::decode(signature).unwrap(), wrapped.clone()].concat()
+        };
+
+        let (refresh_out, _refresh_in) = mpsc::channel();
+        let config = RetrievalConfig::default();
+        let retrieve = |chunks: Vec<(Vec<u8>, Vec<u8>)>, address: Vec<u8>| {
+            let (peers, accounting, _) = seeded_peers(&[address.clone()]);
+            let mut transport = MemoryTransport {
+                chunks: Arc::new(chunks.into_iter().collect()),
+                ..Default::default()
+            };
+            let (owner, refresh_out, config) = (owner.clone(), refresh_out.clone(), &config);
+            async move {
+                retrieve_owned_soc(
+                    &address,
+                    &owner,
+                    &mut transport,
+                    &peers,
+                    &accounting,
+                    &refresh_out,
+                    config,
+                    &RetrievalScope::new(1, 8),
+                )
+                .await
+            }
+        };
+
+        // signed by the owner at the owner's address
+        let update = retrieve(
+            vec![(at_owner.clone(), soc(signed_by_owner))],
+            at_owner.clone(),
+        )
+        .await;
+        assert!(!update.is_empty());
+
+        // another key signed it but it sits at the owner's address
+        let update = retrieve(vec![(at_owner.clone(), soc(signed_by_other))], at_owner).await;
+        assert!(update.is_empty());
+
+        // a valid soc of the other signer handed out for the owner
+        let update = retrieve(vec![(at_signer.clone(), soc(signed_by_other))], at_signer).await;
+        assert!(update.is_empty());
+    }
+
+    // answers every data request from memory, empty for a chunk it does not hold
+
+    fn answering_server(
+        answer: impl Fn(&Vec<u8>) -> Vec<u8> + 'static,
+    ) -> mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)> {
+        let (requests_out, requests_in) =
+            mpsc::channel::<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>();
+        wasm_bindgen_futures::spawn_local(async move {
+            loop {
+                match requests_in.try_recv() {
+                    Ok((address, _, reply, _)) => {
+                        let _ = reply.send(answer(&address));
+                    }
+                    Err(mpsc::TryRecvError::Empty) => {
+                        async_std::task::sleep(Duration::from_millis(10)).await
+                    }
+                    Err(mpsc::TryRecvError::Disconnected) => break,
+                }
+            }
+        });
+
+        requests_out
+    }
+
+    // an unobfuscated mantaray node, [span][zero key][version hash][reference size][entry]
+    // [fork index] and then every fork as [type][prefix length][prefix padded to 30][reference]
+    // with [metadata size: u16 big endian][metadata json] when the type carries metadata
+
+    fn mantaray_node(entry: &[u8], forks: &[(&str, &[u8], &str)]) -> Vec<u8> {
+        let mut node = vec![0_u8; SPAN_SIZE + 32];
+        node.extend_from_slice(&hex::decode(crate::manifest::MANTARAY_VERSION_HASHES[1]).unwrap());
+        node.push(REFERENCE_SIZE as u8);
+        node.extend_from_slice(entry);
+        node.extend_from_slice(&[0; 32]);
+        for (prefix, reference, metadata) in forks {
+            let mut prefix_field = prefix.as_bytes().to_vec();
+            prefix_field.resize(30, 0);
+            node.extend_from_slice(&[2 | 16, prefix.len() as u8]);
+            node.extend_from_slice(&prefix_field);
+            node.extend_from_slice(reference);
+            node.extend_from_slice(&(metadata.len() as u16).to_be_bytes());
+            node.extend_from_slice(metadata.as_bytes());
+        }
+        node
+    }
+
+    // a website manifest with an index and a not found page, the error document named as the
+    // site metadata, every chunk it needs answered from memory
+
+    fn website_fixture(
+        error_document: &str,
+    ) -> (
+        Vec<u8>,
+        mpsc::Sender<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>,
+    ) {
+        let mut chunks: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut store = |chunk: Vec<u8>| {
+            let address = content_address(chunk.clone());
+            chunks.insert(address.clone(), chunk);
+            address
+        };
+
+        let file = |content: &[u8]| {
+            [
+                (content.len() as u64).to_le_bytes().to_vec(),
+                content.to_vec(),
+            ]
+            .concat()
+        };
+        let index_body = store(file(b"<html>app</html>"));
+        let missing_body = store(file(b"<html>missing</html>"));
+        let index_value = store(mantaray_node(&index_body, &[]));
+        let missing_value = store(mantaray_node(&missing_body, &[]));
+        let site_value = store(mantaray_node(&[0; 32], &[]));
+
+        let site_metadata = format!(
+            "{{\"website-index-document\":\"index.html\",\"website-error-document\":\"{}\"}}",
+            error_document
+        );
+        let html = "{\"Content-Type\":\"text/html\"}";
+        let root = store(mantaray_node(
+            &[0; 32],
+            &[
+                ("/", site_value.as_slice(), site_metadata.as_str()),
+                ("404.html", missing_value.as_slice(), html),
+                ("index.html", index_value.as_slice(), html),
+            ],
+        ));
+
+        (
+            root,
+            answering_server(move |address| chunks.get(address).cloned().unwrap_or_default()),
+        )
+    }
+
+    #[wasm_bindgen_test]
+    async fn diamond_manifests_fail_instead_of_walking_every_path() {
+        // every node forks twice into the same child, so the paths double at each level while
+        // the manifest holds no cycle and stays far under the depth cap
+        let mut chunks: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut node = mantaray_node(&[0; 32], &[]);
+        for _ in 0..24 {
+            let child = content_address(node.clone());
+            chunks.insert(child.clone(), node);
+            node = mantaray_node(&[0; 32], &[("a", &child, "{}"), ("b", &child, "{}")]);
+        }
+        let root = content_address(node.clone());
+        chunks.insert(root.clone(), node);
+        let requests =
+            answering_server(move |address| chunks.get(address).cloned().unwrap_or_default());
+
+        // cached nodes keep the walk off the channel, only its own guard can end it
+        let scope = RetrievalScope::new(1, 8).with_manifest_nodes(ManifestNodeCache::default(), 64);
+        let (body, _, _, status) = fetch_website(&root, "/", &requests, &scope).await;
+        assert_eq!(status, 508);
+        assert_eq!(body, format!("{:?}", ManifestError::TooLarge).into_bytes());
+    }
+
+    #[wasm_bindgen_test]
+    async fn single_page_apps_serve_the_index_for_unmatched_paths() {
+        let scope = RetrievalScope::new(1, 8);
+
+        let (root, requests) = website_fixture("index.html");
+        let (content, mime, _, status) =
+            fetch_website(&root, "/settings/profile", &requests, &scope).await;
+        assert_eq!(
+            (content.as_slice(), mime.as_str(), status),
+            (b"<html>app</html>".as_slice(), "text/html", 200)
+        );
+
+        // a separate error document is a real not found page
+        let (root, requests) = website_fixture("404.html");
+        let (content, _, _, status) =
+            fetch_website(&root, "/settings/profile", &requests, &scope).await;
+        assert_eq!(
+            (content.as_slice(), status),
+            (b"<html>missing</html>".as_slice(), 404)
+        );
+        let (content, _, _, status) = fetch_website(&root, "/", &requests, &scope).await;
+        assert_eq!(
+            (content.as_slice(), status),
+            (b"<html>app</html>".as_slice(), 200)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn feed_search_at_the_last_indexes_does_not_overflow() {
+        let (owner, topic) = (
+            "8d3766440f0d7b949a5e32995d09619a7f86e632".to_string(),
+            "t".to_string(),
+        );
+        let scope = RetrievalScope::new(1, 8);
+        let search = FeedSearch::from_redundancy(8);
+
+        let empty = answering_server(|_| vec![]);
+        let found = seek_latest_feed_index(&owner, &topic, &empty, &scope, search, u64::MAX - 1);
+        assert_eq!(found.await, None);
+
+        let full = answering_server(|_| vec![1; SPAN_SIZE + 1]);
+        let found = seek_latest_feed_index(&owner, &topic, &full, &scope, search, u64::MAX - 2);
+        assert_eq!(found.await, Some(u64::MAX));
+    }
+
+    #[wasm_bindgen_test]
+    async fn lists_a_three_level_tree_depth_first_and_rejects_encrypted_roots() {
+        let branching = CHUNK_SIZE / REFERENCE_SIZE;
+        let data: Vec<u8> = (0..2 * branching * CHUNK_SIZE)
+            .map(|i| ((i / CHUNK_SIZE) as u8) ^ ((i % 251) as u8))
+            .collect();
+        let (root, chunks) = chunk_tree_of(&data);
+
+        // the root, then each intermediate chunk followed by its leaves in reference order
+        let mut expected = vec![(0, root.clone())];
+        for intermediate in chunks[&root][SPAN_SIZE..].chunks(REFERENCE_SIZE) {
+            expected.push((1, intermediate.to_vec()));
+            for leaf in chunks[intermediate][SPAN_SIZE..].chunks(REFERENCE_SIZE) {
+                expected.push((2, leaf.to_vec()));
+            }
+        }
+
+        let (peers, accounting, _) = seeded_peers(&[root.clone()]);
+        let mut transport = MemoryTransport {
+            chunks: Arc::new(chunks),
+            ..Default::default()
+        };
+        let (refresh_out, _refresh_in) = mpsc::channel();
+        let config = RetrievalConfig {
+            max_join_fanout: 1,
+            ..Default::default()
+        };
+
+        let tree = chunk_tree(
+            &root,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &RetrievalScope::new(1, 8),
+        )
+        .await;
+        assert_eq!(tree, expected);
+        // leaves are listed without being fetched
+        assert_eq!(transport.asked.lock_recover().len(), 3);
+
+        let encrypted = [root.clone(), vec![7; REFERENCE_SIZE]].concat();
+        let tree = chunk_tree(
+            &encrypted,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &RetrievalScope::new(2, 8),
+        )
+        .await;
+        assert!(tree.is_empty());
+        assert_eq!(transport.asked.lock_recover().len(), 3);
+    }
+
+    #[wasm_bindgen_test]
+    async fn feed_probes_do_not_fall_back_to_the_gateway() {
+        let address = content_address([1_u64.to_le_bytes().to_vec(), vec![7]].concat());
+        let (peers, accounting, _) = seeded_peers(&[address.clone()]);
+        let mut transport = MemoryTransport::default();
+        let (refresh_out, _refresh_in) = mpsc::channel();
+        let config = RetrievalConfig {
+            gateway: Some("http://gateway.invalid".to_string()),
+            ..Default::default()
+        };
+
+        // fetch is swapped for one that only counts the gateway requests
+        let global = js_sys::global();
+        let fetch_key = JsValue::from_str("fetch");
+        let count_key = JsValue::from_str("weeb3GatewayFetches");
+        let original = js_sys::Reflect::get(&global, &fetch_key).unwrap();
+        let counting = js_sys::Function::new_no_args(
+            "globalThis.weeb3GatewayFetches = (globalThis.weeb3GatewayFetches || 0) + 1; \
+             return Promise.reject(new Error('offline'));",
+        );
+        js_sys::Reflect::set(&global, &count_key, &JsValue::from(0)).unwrap();
+        js_sys::Reflect::set(&global, &fetch_key, &counting).unwrap();
+
+        let scope = RetrievalScope::new(1, 8);
+        let probed = retrieve_chunk(
+            &address,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &scope.probing(),
+        )
+        .await;
+        let probe_fetches = js_sys::Reflect::get(&global, &count_key).unwrap();
+
+        let retrieved = retrieve_chunk(
+            &address,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &RetrievalScope::new(2, 8),
+        )
+        .await;
+        let fetches = js_sys::Reflect::get(&global, &count_key).unwrap();
+
+        js_sys::Reflect::set(&global, &fetch_key, &original).unwrap();
+
+        assert!(probed.is_empty() && retrieved.is_empty());
+        assert_eq!(probe_fetches.as_f64(), Some(0.0));
+        assert_eq!(fetches.as_f64(), Some(1.0));
+    }
+
+    #[wasm_bindgen_test]
+    async fn stored_leaves_are_content_addressed_again() {
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE).map(|i| (i % 241) as u8).collect();
+        let (root, chunks) = chunk_tree_of(&data);
+        let (peers, accounting, _) = seeded_peers(&[root.clone()]);
+
+        // the first leaf is stored tampered, as another script of the origin could leave it
+        let config = RetrievalConfig {
+            network_id: 0x7e57,
+            ..Default::default()
+        };
+        let leaf_store = network_cache(LEAF_CHUNK_CACHE, config.network_id);
+        let first = chunks[&root][SPAN_SIZE..SPAN_SIZE + REFERENCE_SIZE].to_vec();
+        let mut tampered = chunks[&first].clone();
+        tampered[SPAN_SIZE] ^= 1;
+        store_chunk(&leaf_store, &first, &tampered).await;
+
+        let mut transport = MemoryTransport {
+            chunks: Arc::new(chunks),
+            ..Default::default()
+        };
+        let (refresh_out, _refresh_in) = mpsc::channel();
+
+        let retrieved = retrieve_data(
+            &root,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &RetrievalScope::new(1, 8),
+            &Mutex::new(ChunkLru::default()),
+        )
+        .await;
+
+        assert!(retrieved[SPAN_SIZE..] == data[..]);
+        assert_ne!(load_chunk(&leaf_store, &first).await, Some(tampered));
+    }
+
+    #[wasm_bindgen_test]
+    async fn an_overdrawn_peer_is_asked_to_refresh() {
+        let chunk = [5_u64.to_le_bytes().to_vec(), b"weeb3".to_vec()].concat();
+        let address = content_address(chunk.clone());
+        let (peers, accounting, ids) = seeded_peers(&[address.clone()]);
+
+        // the only peer is half a price short of its threshold, so the chunk cannot be reserved
+        let req_price = price(&OverlayAddress::from_slice(&address).unwrap(), &address);
+        let expected = {
+            let accounting_peers = accounting.lock_recover();
+            let accounting_peer = &accounting_peers[&ids[0]];
+            set_payment_threshold(accounting_peer, 10 * req_price);
+            apply_credit(accounting_peer, 9 * req_price + req_price / 2);
+            overdraft_refresh(accounting_peer, req_price)
+        };
+        assert!(expected > 0);
+
+        let mut transport = MemoryTransport {
+            chunks: Arc::new(HashMap::from([(address.clone(), chunk)])),
+            ..Default::default()
+        };
+        let (refresh_out, refresh_in) = mpsc::channel();
+
+        // nothing settles the refresh, so the retrieval keeps waiting on the peer
+        let _ = async_std::future::timeout(
+            Duration::from_millis(1000),
+            retrieve_chunk(
+                &address,
+                &mut transport,
+                &peers,
+                &accounting,
+                &refresh_out,
+                &RetrievalConfig::default(),
+                &RetrievalScope::new(1, 8),
+            ),
+        )
+        .await;
+
+        assert_eq!(refresh_in.try_recv(), Ok((ids[0], expected)));
+        assert!(transport.asked.lock_recover().is_empty());
+    }
+
+    // memory chunks served with a delay, the most fetches ever running at once is recorded
+
+    #[derive(Clone, Default)]
+    struct CountingTransport {
+        inner: MemoryTransport,
+        in_flight: Arc<Mutex<(u32, u32)>>,
+    }
+
+    impl ChunkTransport for CountingTransport {
+        async fn fetch_chunk(
+            &mut self,
+            peer: PeerId,
+            chunk_address: Vec<u8>,
+            signature: &Vec<u8>,
+            chan: &mpsc::Sender<(Vec<u8>, Vec<u8>)>,
+            bandwidth: &BandwidthMeter,
+        ) -> RetrieveOutcome {
+            {
+                let mut in_flight = self.in_flight.lock_recover();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            async_std::task::sleep(Duration::from_millis(20)).await;
+            let outcome = self
+                .inner
+                .fetch_chunk(peer, chunk_address, signature, chan, bandwidth)
+                .await;
+            self.in_flight.lock_recover().0 -= 1;
+            outcome
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn a_redundancy_16_feed_search_stays_within_the_stream_cap() {
+        use alloy::signers::{local::PrivateKeySigner, Signer};
+
+        // a sequential feed with updates at indexes 0 to 37, every one a soc signed by the owner
+        let signer = PrivateKeySigner::from_slice(&[0x11; 32]).unwrap();
+        let (owner, topic) = (hex::encode(signer.address()), "weeb-3".to_string());
+        let mut chunks = HashMap::new();
+        for index in 0..=37_u64 {
+            let id =
+                keccak256([feed_topic(&topic).to_vec(), index.to_le_bytes().to_vec()].concat());
+            let wrapped = [32_u64.to_le_bytes().to_vec(), vec![index as u8; 32]].concat();
+            let to_sign = keccak256([id.to_vec(), content_address(wrapped.clone())].concat());
+            let signature = signer.sign_message(to_sign.as_slice()).await.unwrap();
+            chunks.insert(
+                get_feed_address(&owner, &topic, index),
+                [id.to_vec(), signature.as_bytes().to_vec(), wrapped].concat(),
+            );
+        }
+        let chunks = Arc::new(chunks);
+
+        let search = |cap: u64| {
+            let (owner, topic, chunks) = (owner.clone(), topic.clone(), chunks.clone());
+            async move {
+                let config = RetrievalConfig {
+                    max_in_flight_per_peer: 0,
+                    streams: StreamBudget::with_cap(cap),
+                    ..Default::default()
+                };
+                let transport = CountingTransport {
+                    inner: MemoryTransport {
+                        chunks,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                let in_flight = transport.in_flight.clone();
+
+                // every probe is retrieved on its own task, as the client serves the channel
+                let network = std::rc::Rc::new((seeded_peers(&[vec![0x80; 32]]), config.clone()));
+                let (requests_out, requests_in) =
+                    mpsc::channel::<(Vec<u8>, u8, mpsc::Sender<Vec<u8>>, RetrievalScope)>();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let (refresh_out, _refresh_in) = mpsc::channel();
+                    loop {
+                        match requests_in.try_recv() {
+                            Ok((address, _, reply, scope)) => {
+                                let (network, refresh_out) = (network.clone(), refresh_out.clone());
+                                let mut transport = transport.clone();
+                                wasm_bindgen_futures::spawn_local(async move {
+                                    let ((peers, accounting, _), config) = &*network;
+                                    let chunk = retrieve_chunk(
+                                        &address,
+                                        &mut transport,
+                                        peers,
+                                        accounting,
+                                        &refresh_out,
+                                        config,
+                                        &scope,
+                                    )
+                                    .await;
+                                    let _ = reply.send(chunk);
+                                });
+                            }
+                            Err(mpsc::TryRecvError::Empty) => {
+                                async_std::task::sleep(Duration::from_millis(5)).await
+                            }
+                            Err(mpsc::TryRecvError::Disconnected) => break,
+                        }
+                    }
+                });
+
+                let scope = RetrievalScope::new(1, 64).with_streams(config.streams.clone());
+                let found = seek_latest_feed_index(
+                    &owner,
+                    &topic,
+                    &requests_out,
+                    &scope,
+                    FeedSearch::from_redundancy(16),
+                    0,
+                )
+                .await;
+                let peak = in_flight.lock_recover().1;
+                (found, peak)
+            }
+        };
+
+        let (found, peak) = search(4).await;
+        assert_eq!(found, Some(37));
+        assert!(peak > 1 && peak <= 4, "{} fetches at once", peak);
+
+        // the cap only spreads the probes out, the frontier found is the same without it
+        let (uncapped, _) = search(0).await;
+        assert_eq!(uncapped, found);
+    }
+
+    #[wasm_bindgen_test]
+    async fn streams_the_bytes_it_joins() {
+        let branching = CHUNK_SIZE / REFERENCE_SIZE;
+        let data: Vec<u8> = (0..branching * CHUNK_SIZE + 3 * CHUNK_SIZE + 17)
+            .map(|i| ((i / CHUNK_SIZE) as u8) ^ ((i % 251) as u8))
+            .collect();
+        let (root, mut chunks) = chunk_tree_of(&data);
+        let (peers, accounting, _) = seeded_peers(&[root.clone()]);
+        let (refresh_out, _refresh_in) = mpsc::channel();
+        let config = RetrievalConfig {
+            max_join_fanout: 5,
+            ..Default::default()
+        };
+
+        let stream = |chunks: HashMap<Vec<u8>, Vec<u8>>| {
+            let (root, peers, accounting) = (&root, &peers, &accounting);
+            let (refresh_out, config) = (refresh_out.clone(), &config);
+            async move {
+                let mut transport = MemoryTransport {
+                    chunks: Arc::new(chunks),
+                    ..Default::default()
+                };
+                let (sink, segments) = mpsc::channel();
+                let streamed = retrieve_data_stream(
+                    root,
+                    &mut transport,
+                    peers,
+                    accounting,
+                    &refresh_out,
+                    config,
+                    &RetrievalScope::new(1, 8),
+                    &Mutex::new(ChunkLru::default()),
+                    &sink,
+                )
+                .await;
+                let bytes: Vec<u8> = segments
+                    .try_iter()
+                    .flat_map(|segment: Vec<u8>| segment[1..].to_vec())
+                    .collect();
+                (streamed, bytes)
+            }
+        };
+
+        assert_eq!(stream(chunks.clone()).await, (true, data.clone()));
+
+        // a leaf shorter than its share of the span fails the stream as it fails the join
+        let last = chunks[&root][chunks[&root].len() - REFERENCE_SIZE..].to_vec();
+        let short = [
+            4_u64.to_le_bytes().to_vec(),
+            chunks[&last][SPAN_SIZE..SPAN_SIZE + 4].to_vec(),
+        ]
+        .concat();
+        let short_address = content_address(short.clone());
+        let mut parent = chunks[&root].clone();
+        let at = parent.len() - REFERENCE_SIZE;
+        parent[at..].copy_from_slice(&short_address);
+        let tampered_root = content_address(parent.clone());
+        chunks.insert(short_address, short);
+        chunks.insert(tampered_root.clone(), parent);
+
+        let mut transport = MemoryTransport {
+            chunks: Arc::new(chunks),
+            ..Default::default()
+        };
+        let (sink, _segments) = mpsc::channel();
+        let streamed = retrieve_data_stream(
+            &tampered_root,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &RetrievalScope::new(2, 8),
+            &Mutex::new(ChunkLru::default()),
+            &sink,
+        )
+        .await;
+        assert!(!streamed);
+        let joined = retrieve_data(
+            &tampered_root,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &RetrievalScope::new(3, 8),
+            &Mutex::new(ChunkLru::default()),
+        )
+        .await;
+        assert!(joined.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn verifies_a_soc_root_against_its_signed_payload_in_one_root_fetch() {
+        use alloy::signers::{local::PrivateKeySigner, Signer};
+
+        // a soc wrapping the root of a three chunk file
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 5).map(|i| (i % 239) as u8).collect();
+        let (payload_root, mut chunks) = chunk_tree_of(&data);
+        let signer = PrivateKeySigner::from_slice(&[0x22; 32]).unwrap();
+        let id = keccak256(b"weeb-3 verified soc").to_vec();
+        let signature = signer
+            .sign_message(keccak256([id.clone(), payload_root.clone()].concat()).as_slice())
+            .await
+            .unwrap();
+        let soc_address = keccak256([id.clone(), signer.address().to_vec()].concat()).to_vec();
+        let soc = [
+            id,
+            signature.as_bytes().to_vec(),
+            chunks[&payload_root].clone(),
+        ]
+        .concat();
+        chunks.insert(soc_address.clone(), soc);
+
+        let (peers, accounting, _) = seeded_peers(&[soc_address.clone()]);
+        let mut transport = MemoryTransport {
+            chunks: Arc::new(chunks),
+            ..Default::default()
+        };
+        let (refresh_out, _refresh_in) = mpsc::channel();
+        let config = RetrievalConfig {
+            verify_root: true,
+            ..Default::default()
+        };
+
+        let verified = retrieve_data_verified(
+            &soc_address,
+            &mut transport,
+            &peers,
+            &accounting,
+            &refresh_out,
+            &config,
+            &RetrievalScope::new(1, 8),
+            &Mutex::new(ChunkLru::default()),
+        )
+        .await;
+
+        assert_eq!(
+            verified,
+            Ok([(data.len() as u64).to_le_bytes().to_vec(), data].concat())
+        );
+        // the soc and its three leaves, the root is not fetched a second time
+        assert_eq!(transport.asked.lock_recover().len(), 4);
+    }
+}
@@ -4,6 +4,8 @@ use alloy::signers::Signer;
 
 use js_sys::Date;
 
+use crate::postage::{chunk_bucket, PostageStamp};
+
 pub async fn stamp_chunk(
     //
     // stamp_signer: Signer,
@@ -18,33 +20,23 @@ pub async fn stamp_chunk(
         _ => return vec![],
     };
 
-    let bucket = u32::from_be_bytes(chunk_address[..4].try_into().unwrap()) >> (32 - 16);
-    let index = 0_u32;
-    let index_bytes = [bucket.to_be_bytes(), index.to_be_bytes()].concat();
-
-    let timestamp: u64 = (Date::now() as u64) * 1000000;
-    let timestamp_bytes = timestamp.to_be_bytes();
-
-    let to_sign_digest = keccak256(
-        [
-            chunk_address.clone(),
-            batch_id.clone(),
-            index_bytes.clone(),
-            timestamp_bytes.to_vec(),
-        ]
-        .concat(),
-    );
+    let mut stamp = PostageStamp {
+        batch_id,
+        bucket: chunk_bucket(&chunk_address),
+        index: 0,
+        timestamp: (Date::now() as u64) * 1000000,
+        signature: vec![],
+    };
 
-    let signature = stamp_signer
-        .sign_message(to_sign_digest.as_slice())
+    stamp.signature = match stamp_signer
+        .sign_message(stamp.digest(&chunk_address).as_slice())
         .await
-        .unwrap()
-        .as_bytes()
-        .to_vec();
-
-    let stamp = [batch_id, index_bytes, timestamp_bytes.to_vec(), signature].concat();
+    {
+        Ok(signature) => signature.as_bytes().to_vec(),
+        _ => return vec![],
+    };
 
-    stamp
+    stamp.to_bytes()
     //    if n := copy(buf, s.batchID); n != 32 {
     //        return nil, ErrInvalidBatchID
     //    }
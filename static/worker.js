@@ -8,7 +8,7 @@ self.onconnect = async function (event) {
   if (sekirei == undefined){
     console.log('Wings');  
     sekirei = Sekirei.new("");
-    sekirei.run("");
+    sekirei.run("").catch((e) => console.log("Bootstrap failed", e));
   }
 
   console.log("Clouds")
@@ -17,7 +17,10 @@ self.onconnect = async function (event) {
 
   port.onmessage = async function (e) {
     console.log(e.data)
-    var workerResultPromise = sekirei.acquire(e.data);
+    var workerResultPromise = sekirei.acquire(e.data).catch((err) => {
+      console.log("Acquire failed", err);
+      return new Uint8Array();
+    });
     port.postMessage(await workerResultPromise);
   };
 };